@@ -40,6 +40,7 @@ MARKET_SEED = b"market"
 VAULT_SEED = b"vault"
 USER_POSITION_SEED = b"position"
 FEE_VAULT_SEED = b"fee_vault"
+AUDIT_LOG_SEED = b"audit_log"
 
 INITIAL_LIQUIDITY_SOL = 0.1
 MARKET_DURATION_MINUTES = 30
@@ -751,6 +752,7 @@ Return ONLY JSON:
             
             market_pda = Pubkey.from_string(market_pubkey_str)
             config_pda, _ = Pubkey.find_program_address([CONFIG_SEED], self.program_id)
+            audit_log_pda, _ = Pubkey.find_program_address([AUDIT_LOG_SEED], self.program_id)
 
             data = DISCRIMINATORS["resolve_market"]
             data += struct.pack('<B', outcome_yes)
@@ -759,6 +761,8 @@ Return ONLY JSON:
                 AccountMeta(config_pda, is_signer=False, is_writable=False),
                 AccountMeta(market_pda, is_signer=False, is_writable=True),
                 AccountMeta(self.authority_pubkey, is_signer=True, is_writable=True),
+                AccountMeta(SYSTEM_PROGRAM_ID, is_signer=False, is_writable=False),
+                AccountMeta(audit_log_pda, is_signer=False, is_writable=True),
             ]
 
             instruction = Instruction(self.program_id, data, accounts)
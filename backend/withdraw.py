@@ -16,11 +16,14 @@ load_dotenv()
 
 SOLANA_RPC_URL = os.getenv("SOLANA_RPC_URL", "https://api.devnet.solana.com/")
 PRIVATE_KEY_BYTES = os.getenv("PRIVATE_KEY_BYTES")
+# Must match config.treasury; defaults to the authority, as a fresh config does
+TREASURY_PUBKEY = os.getenv("TREASURY_PUBKEY")
 PROGRAM_ID_STR = "CogMUfHjP4A9Lx6M94D6CCjEytxZuaB1uy1AaHQoq3KV"
 SYSTEM_PROGRAM_ID = Pubkey.from_string("11111111111111111111111111111111")
 
 CONFIG_SEED = b"config"
 FEE_VAULT_SEED = b"fee_vault"
+AUDIT_LOG_SEED = b"audit_log"
 
 DISCRIMINATORS = {
     "withdraw_fees": bytes([198, 212, 171, 109, 144, 215, 174, 89]),
@@ -102,11 +105,15 @@ async def withdraw_all_fees():
     authority_pubkey = keypair.pubkey()
     program_id = Pubkey.from_string(PROGRAM_ID_STR)
 
+    treasury_pubkey = Pubkey.from_string(TREASURY_PUBKEY) if TREASURY_PUBKEY else authority_pubkey
+
     print(f"Using Authority: {authority_pubkey}")
+    print(f"Paying Treasury: {treasury_pubkey}")
 
     try:
         config_pda, _ = Pubkey.find_program_address([CONFIG_SEED], program_id)
         feeVaultPda, _ = Pubkey.find_program_address([FEE_VAULT_SEED], program_id)
+        audit_log_pda, _ = Pubkey.find_program_address([AUDIT_LOG_SEED], program_id)
         
         print(f"Fee Vault PDA: {feeVaultPda}")
 
@@ -136,7 +143,9 @@ async def withdraw_all_fees():
             AccountMeta(config_pda, is_signer=False, is_writable=False),
             AccountMeta(feeVaultPda, is_signer=False, is_writable=True),
             AccountMeta(authority_pubkey, is_signer=True, is_writable=True),
+            AccountMeta(treasury_pubkey, is_signer=False, is_writable=True),
             AccountMeta(SYSTEM_PROGRAM_ID, is_signer=False, is_writable=False),
+            AccountMeta(audit_log_pda, is_signer=False, is_writable=True),
         ]
 
         instruction = Instruction(program_id, data, accounts)
@@ -0,0 +1,367 @@
+// Pricing math for both market types. Everything here works on plain numbers so it
+// can be tested and fuzzed without accounts; the handlers map market state onto it.
+use anchor_lang::prelude::*;
+
+use crate::{ErrorCode, BPS_DENOMINATOR, PRECISION};
+
+// Constant-product swap shared by buys (lamports in, shares out) and sells (shares in,
+// lamports out). Returns (amount_out, new_reserve_in, new_reserve_out).
+//
+// Rounding rule: the reserve a trade pushes into is taken exactly, and the reserve on
+// the other side is rounded UP to a whole lamport. The amount paid out is the
+// difference, so truncation always lands in the pool and yes * no can only drift
+// above k, never below it.
+pub fn swap(
+    reserve_in: u64,
+    reserve_out: u64,
+    k_constant: u128,
+    amount_in: u64,
+    precision: u128,
+) -> Result<(u64, u64, u64)> {
+    let new_in = reserve_in
+        .checked_add(amount_in)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let new_in_with_precision = (new_in as u128)
+        .checked_mul(precision)
+        .ok_or(ErrorCode::MathOverflow)?;
+    require!(new_in_with_precision > 0, ErrorCode::InsufficientLiquidity);
+
+    let new_out = k_constant
+        .div_ceil(new_in_with_precision)
+        .div_ceil(precision);
+    let new_out = u64::try_from(new_out).map_err(|_| error!(ErrorCode::MathOverflow))?;
+
+    if new_out > reserve_out {
+        msg!("Pool holds {} on the out side but the swap needs {}", reserve_out, new_out);
+        return err!(ErrorCode::InsufficientLiquidity);
+    }
+    let amount_out = reserve_out - new_out;
+
+    Ok((amount_out, new_in, new_out))
+}
+
+// LMSR fixed-point math. Values are scaled by PRECISION; share quantities are
+// divided by the liquidity parameter b before going through exp/ln.
+pub const LN_2: u128 = 693_147_181;
+
+// e^(-x) for x >= 0
+pub fn exp_neg_fixed(x: u128) -> u128 {
+    let k = x / LN_2;
+    if k >= 64 {
+        return 0;
+    }
+    // e^(-x) = 2^(-k) * e^(-r) with r in [0, ln 2), where the Taylor series converges fast
+    let r = (x - k * LN_2) as i128;
+    let precision = PRECISION as i128;
+    let mut term = precision;
+    let mut sum = precision;
+    for i in 1..=20 {
+        term = -term * r / (precision * i);
+        if term == 0 {
+            break;
+        }
+        sum += term;
+    }
+    (sum.max(0) as u128) >> k
+}
+
+// ln(v) for v > 0
+pub fn ln_fixed(v: u128) -> Result<i128> {
+    require!(v > 0, ErrorCode::MathOverflow);
+
+    // Normalise to m in [1, 2) so that ln(v) = k * ln 2 + ln(m)
+    let mut k: i128 = 0;
+    let mut m = v;
+    while m >= 2 * PRECISION {
+        m >>= 1;
+        k += 1;
+    }
+    while m < PRECISION {
+        m <<= 1;
+        k -= 1;
+    }
+
+    // ln(m) = 2 * atanh(z) with z = (m - 1) / (m + 1) in [0, 1/3)
+    let precision = PRECISION as i128;
+    let z = ((m - PRECISION) * PRECISION / (m + PRECISION)) as i128;
+    let z_squared = z * z / precision;
+    let mut term = z;
+    let mut sum = 0;
+    let mut n = 1;
+    while term != 0 {
+        sum += term / n;
+        term = term * z_squared / precision;
+        n += 2;
+    }
+    Ok(k * LN_2 as i128 + 2 * sum)
+}
+
+// C(q) = b * ln(e^(q_yes/b) + e^(q_no/b)), in lamports
+pub fn lmsr_cost(b: u64, q_yes: u128, q_no: u128) -> Result<u128> {
+    require!(b > 0, ErrorCode::InvalidLiquidityParameter);
+    let b = b as u128;
+    let scaled_yes = q_yes.checked_mul(PRECISION).ok_or(ErrorCode::MathOverflow)? / b;
+    let scaled_no = q_no.checked_mul(PRECISION).ok_or(ErrorCode::MathOverflow)? / b;
+    let (hi, lo) = (scaled_yes.max(scaled_no), scaled_yes.min(scaled_no));
+
+    // log-sum-exp: ln(e^hi + e^lo) = hi + ln(1 + e^(lo - hi))
+    let spread = ln_fixed(PRECISION + exp_neg_fixed(hi - lo))? as u128;
+    hi.checked_add(spread)
+        .and_then(|c| c.checked_mul(b))
+        .map(|c| c / PRECISION)
+        .ok_or(error!(ErrorCode::MathOverflow))
+}
+
+// Instantaneous YES price e^(q_yes/b) / (e^(q_yes/b) + e^(q_no/b)), in bps
+pub fn lmsr_yes_price_bps(b: u64, q_yes: u128, q_no: u128) -> u16 {
+    if b == 0 {
+        return (BPS_DENOMINATOR / 2) as u16;
+    }
+    let b = b as u128;
+    let scaled_yes = q_yes.saturating_mul(PRECISION) / b;
+    let scaled_no = q_no.saturating_mul(PRECISION) / b;
+    let price = if scaled_yes >= scaled_no {
+        PRECISION * PRECISION / (PRECISION + exp_neg_fixed(scaled_yes - scaled_no))
+    } else {
+        let e = exp_neg_fixed(scaled_no - scaled_yes);
+        e * PRECISION / (PRECISION + e)
+    };
+    (price * BPS_DENOMINATOR as u128 / PRECISION) as u16
+}
+
+// Shares of one side that `cost` lamports buys, solving C(q + shares) - C(q) = cost:
+//   shares / b = x + ln(1 + e^(n - a) * (1 - e^(-x)))
+// with a, n the bought/other side quantities over b and x = cost / b
+pub fn lmsr_shares_for_cost(b: u64, bought: u128, other: u128, cost: u64) -> Result<u64> {
+    require!(b > 0, ErrorCode::InvalidLiquidityParameter);
+    let b_wide = b as u128;
+    let a = bought.checked_mul(PRECISION).ok_or(ErrorCode::MathOverflow)? / b_wide;
+    let n = other.checked_mul(PRECISION).ok_or(ErrorCode::MathOverflow)? / b_wide;
+    let x = (cost as u128).checked_mul(PRECISION).ok_or(ErrorCode::MathOverflow)? / b_wide;
+    let y = PRECISION - exp_neg_fixed(x);
+
+    // Split on the sign of n - a so every exponent stays non-positive
+    let scaled_shares = if a >= n {
+        let d = exp_neg_fixed(a - n);
+        x as i128 + ln_fixed(PRECISION + d * y / PRECISION)?
+    } else {
+        x as i128 + (n - a) as i128 + ln_fixed(exp_neg_fixed(n - a) + y)?
+    };
+
+    let shares = (scaled_shares.max(0) as u128)
+        .checked_mul(b_wide)
+        .ok_or(ErrorCode::MathOverflow)?
+        / PRECISION;
+    u64::try_from(shares).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAX_AMM_PRECISION, MIN_AMM_PRECISION};
+
+    fn k_for(yes: u64, no: u64, precision: u128) -> u128 {
+        yes as u128 * precision * no as u128 * precision
+    }
+
+    #[test]
+    fn lmsr_fixed_point_helpers_match_reference_values() {
+        // e^-1 = 0.367879441, ln 3 = 1.098612289
+        assert!((exp_neg_fixed(PRECISION) as i128 - 367_879_441).abs() <= 2);
+        assert!((ln_fixed(3 * PRECISION).unwrap() - 1_098_612_289).abs() <= 10);
+        assert_eq!(ln_fixed(PRECISION).unwrap(), 0);
+        assert_eq!(exp_neg_fixed(0), PRECISION);
+    }
+
+    #[test]
+    fn lmsr_cost_is_monotonic() {
+        let b = 100_000_000;
+        let mut previous = lmsr_cost(b, 0, 0).unwrap();
+        for step in 1..=50u128 {
+            let cost = lmsr_cost(b, step * 10_000_000, 20_000_000).unwrap();
+            assert!(cost > previous);
+            previous = cost;
+        }
+    }
+
+    #[test]
+    fn lmsr_buy_spends_the_quoted_cost() {
+        let b = 100_000_000;
+        let (q_yes, q_no) = (30_000_000u128, 80_000_000u128);
+
+        for &(is_yes, cost) in &[(true, 5_000_000u64), (false, 5_000_000), (true, 250_000_000)] {
+            let (bought, other) = if is_yes { (q_yes, q_no) } else { (q_no, q_yes) };
+            let shares = lmsr_shares_for_cost(b, bought, other, cost).unwrap() as u128;
+            let before = lmsr_cost(b, q_yes, q_no).unwrap();
+            let after = if is_yes {
+                lmsr_cost(b, q_yes + shares, q_no).unwrap()
+            } else {
+                lmsr_cost(b, q_yes, q_no + shares).unwrap()
+            };
+            assert!(((after - before) as i128 - cost as i128).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn swap_rounds_in_the_pools_favor() {
+        // Tiny LCG so the run is reproducible without pulling in a rand crate
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % bound
+        };
+
+        let liquidity: u64 = 100_000_000;
+        let k = k_for(liquidity, liquidity, PRECISION);
+        let (mut yes, mut no) = (liquidity, liquidity);
+        let (mut total_yes, mut total_no) = (0u64, 0u64);
+        let mut vault = 2 * liquidity;
+
+        for _ in 0..5_000 {
+            let is_yes = next(2) == 0;
+            let held = if is_yes { total_yes } else { total_no };
+            if next(3) == 0 && held > 0 {
+                let shares = 1 + next(held);
+                let (reserve_in, reserve_out) = if is_yes { (no, yes) } else { (yes, no) };
+                let Ok((lamports, new_in, new_out)) = swap(reserve_in, reserve_out, k, shares, PRECISION) else {
+                    continue;
+                };
+                vault -= lamports;
+                if is_yes {
+                    (no, yes, total_yes) = (new_in, new_out, total_yes - shares);
+                } else {
+                    (yes, no, total_no) = (new_in, new_out, total_no - shares);
+                }
+            } else {
+                let lamports = 1 + next(50_000_000);
+                let (reserve_in, reserve_out) = if is_yes { (yes, no) } else { (no, yes) };
+                let (shares, new_in, new_out) = swap(reserve_in, reserve_out, k, lamports, PRECISION).unwrap();
+                vault += lamports;
+                if is_yes {
+                    (yes, no, total_yes) = (new_in, new_out, total_yes + shares);
+                } else {
+                    (no, yes, total_no) = (new_in, new_out, total_no + shares);
+                }
+            }
+
+            // The invariant only ever drifts in the solvent direction
+            assert!(yes as u128 * no as u128 * PRECISION * PRECISION >= k);
+            // Whichever side wins, the vault holds at least a lamport per winning share
+            assert!(vault >= total_yes.max(total_no));
+        }
+    }
+
+    #[test]
+    fn swap_at_coarse_and_fine_precision() {
+        for precision in [MIN_AMM_PRECISION as u128, MAX_AMM_PRECISION as u128] {
+            let k = k_for(10_000_000, 10_000_000, precision);
+            // A 1000 lamport buy still gets shares instead of truncating to zero
+            let (shares, new_in, new_out) = swap(10_000_000, 10_000_000, k, 1_000, precision).unwrap();
+            assert_eq!(shares, 999);
+            assert_eq!((new_in, new_out), (10_001_000, 9_999_001));
+            assert!(new_in as u128 * new_out as u128 * precision * precision >= k);
+        }
+    }
+
+    #[test]
+    fn swap_errors_instead_of_wrapping_past_u64() {
+        let max = u64::MAX as u128;
+        // Just inside the boundary: the opposite reserve lands exactly on u64::MAX
+        assert_eq!(swap(1, u64::MAX, 2 * max, 1, 1).unwrap(), (0, 2, u64::MAX));
+
+        // One past it would have wrapped to zero with an `as` cast
+        assert_eq!(
+            swap(1, u64::MAX, 2 * max + 2, 1, 1).unwrap_err(),
+            ErrorCode::MathOverflow.into()
+        );
+        // As would a reserve_in pushed past the boundary by the trade itself
+        assert_eq!(
+            swap(u64::MAX, u64::MAX, max * max, 1, 1).unwrap_err(),
+            ErrorCode::MathOverflow.into()
+        );
+    }
+
+    #[test]
+    fn symmetric_trades_mirror_each_other() {
+        let liquidity = 50_000_000;
+        let k = k_for(liquidity, liquidity, PRECISION);
+
+        for amount in [1, 1_000, 10_000_000, 500_000_000] {
+            let (shares, new_in, new_out) = swap(liquidity, liquidity, k, amount, PRECISION).unwrap();
+
+            // Selling the shares straight back undoes the buy: it never returns more than
+            // was paid and loses at most the couple of lamports rounding keeps in the pool
+            let (back, after_in, after_out) = swap(new_out, new_in, k, shares, PRECISION).unwrap();
+            assert!(back <= amount);
+            assert!(amount - back <= 2);
+            assert!(after_in.abs_diff(liquidity) <= 2 && after_out.abs_diff(liquidity) <= 2);
+            assert!(after_in as u128 * after_out as u128 * PRECISION * PRECISION >= k);
+        }
+    }
+
+    #[test]
+    fn swap_survives_extreme_imbalances() {
+        let big = 1_000_000_000_000_000u64;
+
+        // One lamport against a huge reserve: a tiny buy takes half the other side
+        let k = k_for(1, big, PRECISION);
+        let (shares, new_in, new_out) = swap(1, big, k, 1, PRECISION).unwrap();
+        assert_eq!((shares, new_in, new_out), (big / 2, 2, big / 2));
+
+        // The mirror case: a huge buy against a one-lamport reserve gets nothing, and
+        // the pool never hands out its last lamport
+        let k = k_for(big, 1, PRECISION);
+        let (shares, new_in, new_out) = swap(big, 1, k, big, PRECISION).unwrap();
+        assert_eq!((shares, new_out), (0, 1));
+        assert_eq!(new_in, 2 * big);
+    }
+
+    #[test]
+    fn swap_preserves_the_invariant_and_conserves_the_out_reserve() {
+        let reserves = [1u64, 7, 1_000, 10_000_000, 123_456_789, 1_000_000_000];
+        let amounts = [0u64, 1, 3, 999, 1_000_000, 1_000_000_000_000];
+
+        for precision in [MIN_AMM_PRECISION as u128, PRECISION] {
+            for &reserve_in in &reserves {
+                for &reserve_out in &reserves {
+                    let k = k_for(reserve_in, reserve_out, precision);
+                    for &amount in &amounts {
+                        let (out, new_in, new_out) =
+                            swap(reserve_in, reserve_out, k, amount, precision).unwrap();
+                        assert_eq!(new_in, reserve_in + amount);
+                        assert_eq!(out + new_out, reserve_out);
+                        assert!(new_in as u128 * precision * new_out as u128 * precision >= k);
+                    }
+                }
+            }
+        }
+    }
+
+    // Each swap prices off k rather than off the previous product, so rounding cannot
+    // compound: however many trades came before, the reserve a trade pays out of sits
+    // less than one lamport above the exact curve
+    #[test]
+    fn rounding_drift_stays_within_one_lamport_over_many_trades() {
+        let k = k_for(100_000_000, 100_000_000, PRECISION);
+        let (mut yes, mut no) = (100_000_000u64, 100_000_000u64);
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+
+        for _ in 0..5_000 {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            let yes_in = seed >> 63 == 1;
+            let (reserve_in, reserve_out) = if yes_in { (yes, no) } else { (no, yes) };
+            let amount = 1 + (seed >> 20) % (reserve_in / 20);
+
+            let (_, new_in, new_out) = swap(reserve_in, reserve_out, k, amount, PRECISION).unwrap();
+            let scaled_in = new_in as u128 * PRECISION * PRECISION;
+            assert!(scaled_in * new_out as u128 >= k);
+            assert!(scaled_in * (new_out as u128 - 1) < k, "drifted a lamport or more");
+
+            if yes_in {
+                (yes, no) = (new_in, new_out);
+            } else {
+                (no, yes) = (new_in, new_out);
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use anchor_lang::prelude::*;
 use anchor_lang::system_program;
+use solana_sha256_hasher::hash;
+
+mod amm;
 
 declare_id!("CogMUfHjP4A9Lx6M94D6CCjEytxZuaB1uy1AaHQoq3KV");
 
@@ -7,7 +10,35 @@ const MARKET_SEED: &[u8] = b"market";
 const VAULT_SEED: &[u8] = b"vault";
 const USER_POSITION_SEED: &[u8] = b"position";
 const FEE_VAULT_SEED: &[u8] = b"fee_vault";
+const METADATA_SEED: &[u8] = b"metadata";
+const TRIGGER_ORDER_SEED: &[u8] = b"trigger";
+const CATEGORY_STATS_SEED: &[u8] = b"category_stats";
+const CATEGORY_INDEX_SEED: &[u8] = b"category_index";
+const CREATOR_STATS_SEED: &[u8] = b"creator_stats";
+const WHITELIST_SEED: &[u8] = b"whitelist";
+const INSURANCE_VAULT_SEED: &[u8] = b"insurance_vault";
+#[cfg(feature = "test-clock")]
+const MOCK_CLOCK_SEED: &[u8] = b"mock_clock";
+const TRADING_ESCROW_SEED: &[u8] = b"escrow";
+const AUDIT_LOG_SEED: &[u8] = b"audit_log";
 const PRECISION: u128 = 1_000_000_000; // 9 decimal precision for AMM calculations
+// Range a market may pick for its own constant-product scaling
+const MIN_AMM_PRECISION: u64 = 1_000;
+const MAX_AMM_PRECISION: u64 = 1_000_000_000_000;
+const BPS_DENOMINATOR: u64 = 10_000;
+const MAX_RESOLVERS: usize = 10; // Whitelisted resolvers Config has room for
+const CATEGORY_PAGE_SIZE: u64 = 10; // Market ids held by one CategoryIndex page
+const SUMMARY_QUESTION_LEN: usize = 64; // Question bytes kept in MarketSummary
+const EVIDENCE_URI_LEN: usize = 200;
+const MAX_SHARE_DECIMALS: u8 = 3; // Positions are u64, so finer shares shrink the largest one
+const MIN_RESERVE: u64 = 1_000_000; // Lamports a buy must leave in the opposite reserve
+const REBALANCE_FEE_BPS: u64 = 50; // Cut of a rebalancing top-up that goes to the fee vault
+const DEFAULT_RESOLVE_GRACE_PERIOD: i64 = 7 * 24 * 60 * 60; // Seconds past resolution_time before refunds open
+const MAX_BATCH_MARKETS: usize = 5; // Markets one create_markets_batch call may create
+const BATCH_MARKET_ACCOUNTS: usize = 6; // Remaining accounts each batch entry takes
+const MIN_CLAIM_WINDOW: i64 = 90 * 24 * 60 * 60; // Shortest non-zero time winners get to claim
+const AUDIT_LOG_CAPACITY: usize = 64; // Entries the audit log keeps before overwriting the oldest
+const MARKET_VERSION: u8 = 23; // Bump when Market gains fields that need migrate_market
 
 #[program]
 pub mod prediction_market {
@@ -18,6 +49,21 @@ pub mod prediction_market {
         config.authority = ctx.accounts.authority.key();
         config.market_count = 0;
         config.fee_percentage = 200;
+        config.max_fee_bps = 1_000;
+        config.trading_buffer = 0;
+        config.max_markets_per_category = 0;
+        config.require_trades_to_resolve = false;
+        config.min_market_duration = 0;
+        config.max_market_duration = 0;
+        config.resolve_grace_period = DEFAULT_RESOLVE_GRACE_PERIOD;
+        config.resolvers = Vec::new();
+        config.revert_window = 0;
+        config.active_markets = 0;
+        config.create_cooldown = 0;
+        config.insurance_fee_bps = 0;
+        config.treasury = ctx.accounts.authority.key();
+        config.claim_window = 0;
+        config.house_fee_exempt = false;
         config.bump = ctx.bumps.config;
         config.fee_vault_bump = ctx.bumps.fee_vault;
 
@@ -39,6 +85,7 @@ pub mod prediction_market {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_market(
         ctx: Context<CreateMarket>,
         market_id: u64,
@@ -47,587 +94,5537 @@ pub mod prediction_market {
         category: String,
         resolution_time: i64,
         initial_liquidity_lamports: u64,
+        house_shares: u64,
+        market_type: MarketType,
+        amm_precision: u64,
     ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
         require!(
             ctx.accounts.authority.key() == ctx.accounts.config.authority,
             ErrorCode::Unauthorized
         );
 
-        require!(question.len() <= 200, ErrorCode::QuestionTooLong);
-        require!(description.len() <= 1000, ErrorCode::DescriptionTooLong);
-        require!(category.len() <= 50, ErrorCode::CategoryTooLong);
-        require!(
-            resolution_time > Clock::get()?.unix_timestamp,
-            ErrorCode::InvalidResolutionTime
-        );
-        require!(
-            initial_liquidity_lamports >= 10_000_000,
-            ErrorCode::InsufficientInitialLiquidity
-        );
+        let params = MarketParams {
+            market_id,
+            question,
+            description,
+            category,
+            resolution_time,
+            initial_liquidity_lamports,
+            market_type,
+            amm_precision,
+        };
+        let amm_precision = validate_market_params(&ctx.accounts.config, &params, house_shares, now)?;
+
+        let category_hash = hash(params.category.as_bytes()).to_bytes();
+        add_to_category(
+            &ctx.accounts.config,
+            &mut ctx.accounts.category_stats,
+            ctx.bumps.category_stats,
+            &mut ctx.accounts.category_index,
+            ctx.bumps.category_index,
+            category_hash,
+            market_id,
+        )?;
+
+        ctx.accounts.creator_stats.record_created(
+            ctx.accounts.authority.key(),
+            ctx.bumps.creator_stats,
+            ctx.accounts.config.create_cooldown,
+            1,
+            now,
+        )?;
 
         let market = &mut ctx.accounts.market;
-        market.market_id = market_id;
-        market.authority = ctx.accounts.config.authority;
-        market.question = question;
-        market.description = description;
-        market.category = category;
-        market.resolution_time = resolution_time;
-        market.created_at = Clock::get()?.unix_timestamp;
-        market.initial_liquidity = initial_liquidity_lamports;
-        market.yes_liquidity = initial_liquidity_lamports;
-        market.no_liquidity = initial_liquidity_lamports;
-        
-        // High-precision k constant
-        market.k_constant = (initial_liquidity_lamports as u128)
-            .checked_mul(PRECISION)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(initial_liquidity_lamports as u128)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_mul(PRECISION)
-            .ok_or(ErrorCode::MathOverflow)?;
-            
-        market.total_volume = 0;
-        market.resolved = false;
-        market.outcome = None;
-        market.total_yes_shares = 0;
-        market.total_no_shares = 0;
+        market.init_new(&params, amm_precision, house_shares, ctx.accounts.authority.key(), now)?;
         market.bump = ctx.bumps.market;
         market.vault_bump = ctx.bumps.vault;
 
-        // Transfer initial liquidity to vault PDA
-        let cpi_context = CpiContext::new(
-            ctx.accounts.system_program.to_account_info(),
-            system_program::Transfer {
-                from: ctx.accounts.authority.to_account_info(),
-                to: ctx.accounts.vault.to_account_info(),
-            },
-        );
-        system_program::transfer(cpi_context, initial_liquidity_lamports * 2)?;
+        let insurance_vault = &mut ctx.accounts.insurance_vault;
+        insurance_vault.market_id = market_id;
+        insurance_vault.bump = ctx.bumps.insurance_vault;
+
+        // The house holds both sides equally, so the opening odds stay at 50/50
+        if house_shares > 0 {
+            let house_position = ctx.accounts.house_position
+                .as_mut()
+                .ok_or(ErrorCode::HousePositionMissing)?;
+            house_position.user = ctx.accounts.authority.key();
+            house_position.market_id = market_id;
+            house_position.yes_shares = house_shares;
+            house_position.no_shares = house_shares;
+            house_position.claimed = false;
+            house_position.bump = ctx.bumps.house_position.ok_or(ErrorCode::HousePositionMissing)?;
+        }
+
+        // Display fields live in their own account so trading never loads them
+        let metadata = &mut ctx.accounts.metadata;
+        metadata.market_id = market_id;
+        metadata.question = params.question;
+        metadata.description = params.description;
+        metadata.category = params.category;
+        metadata.bump = ctx.bumps.metadata;
 
         let config = &mut ctx.accounts.config;
         config.market_count += 1;
+        config.active_markets += 1;
+
+        fund_market_vault(
+            ctx.accounts.authority.as_ref(),
+            ctx.accounts.vault.as_ref(),
+            ctx.accounts.system_program.as_ref(),
+            ctx.accounts.market.creator_deposit,
+        )?;
 
-        msg!("Market #{} created: {}", market_id, market.question);
+        emit_market_created(ctx.accounts.market.key(), &ctx.accounts.market, &ctx.accounts.metadata);
+
+        msg!("Market #{} created: {}", market_id, ctx.accounts.metadata.question);
         Ok(())
     }
 
-    pub fn buy_shares(
-        ctx: Context<BuyShares>,
-        is_yes: bool,
-        amount_lamports: u64,
-        min_shares_out: u64,
+    // Creates several markets in one transaction, all or nothing. Each entry takes
+    // BATCH_MARKET_ACCOUNTS remaining accounts, in order: market, metadata, vault,
+    // insurance vault, category stats and the category's current index page. Entries
+    // sharing a category pass the same stats account; house shares are not offered here
+    pub fn create_markets_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateMarketsBatch<'info>>,
+        markets: Vec<MarketParams>,
     ) -> Result<()> {
-        let market = &mut ctx.accounts.market;
-
-        require!(!market.resolved, ErrorCode::MarketResolved);
+        let now = unix_now(ctx.remaining_accounts)?;
         require!(
-            Clock::get()?.unix_timestamp < market.resolution_time,
-            ErrorCode::MarketExpired
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
         );
-        require!(amount_lamports > 0, ErrorCode::InvalidAmount);
-
-        let fee = amount_lamports
-            .checked_mul(ctx.accounts.config.fee_percentage as u64)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(10000)
-            .ok_or(ErrorCode::MathOverflow)?;
-
-        let amount_after_fee = amount_lamports
-            .checked_sub(fee)
-            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            !markets.is_empty() && markets.len() <= MAX_BATCH_MARKETS,
+            ErrorCode::InvalidBatchSize
+        );
+        // A trailing mock clock is allowed on test-clock builds
+        let needed = markets.len() * BATCH_MARKET_ACCOUNTS;
+        if ctx.remaining_accounts.len() < needed {
+            msg!("Batch of {} markets needs {} accounts, got {}", markets.len(), needed, ctx.remaining_accounts.len());
+            return err!(ErrorCode::BatchAccountMismatch);
+        }
 
-        // High-precision AMM calculation
-        let (shares_out, new_yes_liquidity, new_no_liquidity) = if is_yes {
-            let new_yes_with_precision = (market.yes_liquidity as u128)
-                .checked_mul(PRECISION)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_add((amount_after_fee as u128).checked_mul(PRECISION).ok_or(ErrorCode::MathOverflow)?)
-                .ok_or(ErrorCode::MathOverflow)?;
+        // The cooldown gates the batch as a whole, not each market in it
+        ctx.accounts.creator_stats.record_created(
+            ctx.accounts.authority.key(),
+            ctx.bumps.creator_stats,
+            ctx.accounts.config.create_cooldown,
+            markets.len() as u64,
+            now,
+        )?;
 
-            let new_no_with_precision = market.k_constant
-                .checked_div(new_yes_with_precision)
-                .ok_or(ErrorCode::MathOverflow)?;
+        let authority = ctx.accounts.authority.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        for (params, accounts) in markets.iter().zip(ctx.remaining_accounts.chunks(BATCH_MARKET_ACCOUNTS)) {
+            let [market_info, metadata_info, vault_info, insurance_info, stats_info, index_info] = accounts else {
+                return err!(ErrorCode::BatchAccountMismatch);
+            };
+            let amm_precision = validate_market_params(&ctx.accounts.config, params, 0, now)?;
+            let id_bytes = params.market_id.to_le_bytes();
+            let category_hash = hash(params.category.as_bytes()).to_bytes();
 
-            let new_yes = (new_yes_with_precision / PRECISION) as u64;
-            let new_no = (new_no_with_precision / PRECISION) as u64;
+            let market_bump = batch_pda_bump(market_info, &[MARKET_SEED, &id_bytes])?;
+            let metadata_bump = batch_pda_bump(metadata_info, &[METADATA_SEED, &id_bytes])?;
+            let vault_bump = batch_pda_bump(vault_info, &[VAULT_SEED, &id_bytes])?;
+            let insurance_bump = batch_pda_bump(insurance_info, &[INSURANCE_VAULT_SEED, &id_bytes])?;
+            let stats_bump = batch_pda_bump(stats_info, &[CATEGORY_STATS_SEED, &category_hash])?;
 
-            let old_no_with_precision = (market.no_liquidity as u128)
-                .checked_mul(PRECISION)
-                .ok_or(ErrorCode::MathOverflow)?;
+            let mut category_stats: CategoryStats = load_or_create_pda(
+                &authority,
+                stats_info,
+                &system_program,
+                8 + CategoryStats::LEN,
+                &[CATEGORY_STATS_SEED, &category_hash, &[stats_bump]],
+            )?;
+            let page_bytes = (category_stats.market_count / CATEGORY_PAGE_SIZE).to_le_bytes();
+            let index_bump = batch_pda_bump(index_info, &[CATEGORY_INDEX_SEED, &category_hash, &page_bytes])?;
+            let mut category_index: CategoryIndex = load_or_create_pda(
+                &authority,
+                index_info,
+                &system_program,
+                8 + CategoryIndex::LEN,
+                &[CATEGORY_INDEX_SEED, &category_hash, &page_bytes, &[index_bump]],
+            )?;
+            add_to_category(
+                &ctx.accounts.config,
+                &mut category_stats,
+                stats_bump,
+                &mut category_index,
+                index_bump,
+                category_hash,
+                params.market_id,
+            )?;
+            store_account(&category_stats, stats_info)?;
+            store_account(&category_index, index_info)?;
 
-            let shares_with_precision = old_no_with_precision
-                .checked_sub(new_no_with_precision)
-                .ok_or(ErrorCode::InsufficientLiquidity)?;
+            create_pda_account(
+                &authority,
+                market_info,
+                &system_program,
+                8 + Market::LEN,
+                &[MARKET_SEED, &id_bytes, &[market_bump]],
+            )?;
+            let mut market = Market::try_deserialize_unchecked(&mut &market_info.try_borrow_data()?[..])?;
+            market.init_new(params, amm_precision, 0, authority.key(), now)?;
+            market.bump = market_bump;
+            market.vault_bump = vault_bump;
+            store_account(&market, market_info)?;
 
-            let shares = (shares_with_precision / PRECISION) as u64;
+            create_pda_account(
+                &authority,
+                metadata_info,
+                &system_program,
+                8 + MarketMetadata::LEN,
+                &[METADATA_SEED, &id_bytes, &[metadata_bump]],
+            )?;
+            let metadata = MarketMetadata {
+                market_id: params.market_id,
+                question: params.question.clone(),
+                description: params.description.clone(),
+                category: params.category.clone(),
+                bump: metadata_bump,
+            };
+            store_account(&metadata, metadata_info)?;
 
-            (shares, new_yes, new_no)
-        } else {
-            let new_no_with_precision = (market.no_liquidity as u128)
-                .checked_mul(PRECISION)
-                .ok_or(ErrorCode::MathOverflow)?
-                .checked_add((amount_after_fee as u128).checked_mul(PRECISION).ok_or(ErrorCode::MathOverflow)?)
-                .ok_or(ErrorCode::MathOverflow)?;
+            create_pda_account(
+                &authority,
+                insurance_info,
+                &system_program,
+                8 + InsuranceVault::LEN,
+                &[INSURANCE_VAULT_SEED, &id_bytes, &[insurance_bump]],
+            )?;
+            let insurance_vault = InsuranceVault { market_id: params.market_id, bump: insurance_bump };
+            store_account(&insurance_vault, insurance_info)?;
 
-            let new_yes_with_precision = market.k_constant
-                .checked_div(new_no_with_precision)
-                .ok_or(ErrorCode::MathOverflow)?;
+            let config = &mut ctx.accounts.config;
+            config.market_count += 1;
+            config.active_markets += 1;
 
-            let new_yes = (new_yes_with_precision / PRECISION) as u64;
-            let new_no = (new_no_with_precision / PRECISION) as u64;
+            fund_market_vault(&authority, vault_info, &system_program, market.creator_deposit)?;
 
-            let old_yes_with_precision = (market.yes_liquidity as u128)
-                .checked_mul(PRECISION)
-                .ok_or(ErrorCode::MathOverflow)?;
+            emit_market_created(market_info.key(), &market, &metadata);
+            msg!("Market #{} created: {}", params.market_id, metadata.question);
+        }
 
-            let shares_with_precision = old_yes_with_precision
-                .checked_sub(new_yes_with_precision)
-                .ok_or(ErrorCode::InsufficientLiquidity)?;
+        msg!("Created {} markets in one batch", markets.len());
+        Ok(())
+    }
 
-            let shares = (shares_with_precision / PRECISION) as u64;
+    // Creates the empty position a user must hold before buying into a market
+    pub fn open_position(ctx: Context<OpenPosition>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
 
-            (shares, new_yes, new_no)
-        };
+        let position = &mut ctx.accounts.user_position;
+        position.user = ctx.accounts.user.key();
+        position.market_id = market.market_id;
+        position.yes_shares = 0;
+        position.no_shares = 0;
+        position.claimed = false;
+        position.bump = ctx.bumps.user_position;
+        position.last_trade_time = 0;
+        position.lamports_contributed = 0;
+        position.cosigner = None;
+        position.cosign_threshold = 0;
 
-        require!(shares_out >= min_shares_out, ErrorCode::SlippageExceeded);
+        msg!("User {} opened a position in market #{}", position.user, market.market_id);
+        Ok(())
+    }
 
-        // Send fees to protocol fee vault
-        let fee_cpi = CpiContext::new(
-            ctx.accounts.system_program.to_account_info(),
-            system_program::Transfer {
-                from: ctx.accounts.user.to_account_info(),
-                to: ctx.accounts.fee_vault.to_account_info(),
-            },
-        );
-        system_program::transfer(fee_cpi, fee)?;
+    // Markets created before insurance existed get their vault here; anyone may pay for it
+    pub fn open_insurance_vault(ctx: Context<OpenInsuranceVault>) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
 
-        // Transfer net amount to market vault
-        let net_cpi = CpiContext::new(
-            ctx.accounts.system_program.to_account_info(),
-            system_program::Transfer {
-                from: ctx.accounts.user.to_account_info(),
-                to: ctx.accounts.vault.to_account_info(),
-            },
-        );
-        system_program::transfer(net_cpi, amount_after_fee)?;
+        let insurance_vault = &mut ctx.accounts.insurance_vault;
+        insurance_vault.market_id = market.market_id;
+        insurance_vault.bump = ctx.bumps.insurance_vault;
 
-        market.yes_liquidity = new_yes_liquidity;
-        market.no_liquidity = new_no_liquidity;
-        market.total_volume += amount_lamports;
+        msg!("Insurance vault opened for market #{}", market.market_id);
+        Ok(())
+    }
 
+    // Sets or clears the key that must co-sign this position's large buys. Once a
+    // cosigner is set, changing it needs their signature too
+    pub fn set_position_cosigner(
+        ctx: Context<SetPositionCosigner>,
+        cosigner: Option<Pubkey>,
+        cosign_threshold: u64,
+    ) -> Result<()> {
         let position = &mut ctx.accounts.user_position;
-        if position.user == Pubkey::default() {
-            position.user = ctx.accounts.user.key();
-            position.market_id = market.market_id;
-            position.yes_shares = if is_yes { shares_out } else { 0 };
-            position.no_shares = if !is_yes { shares_out } else { 0 };
-            position.claimed = false;
-            position.bump = ctx.bumps.user_position;
-        } else {
-            if is_yes {
-                position.yes_shares = position.yes_shares
-                    .checked_add(shares_out)
-                    .ok_or(ErrorCode::MathOverflow)?;
-            } else {
-                position.no_shares = position.no_shares
-                    .checked_add(shares_out)
-                    .ok_or(ErrorCode::MathOverflow)?;
-            }
+        if let Some(current) = position.cosigner {
+            let signed = ctx.accounts.current_cosigner.as_ref().map(|c| c.key());
+            require!(signed == Some(current), ErrorCode::CosignerRequired);
         }
 
-        if is_yes {
-            market.total_yes_shares = market.total_yes_shares
-                .checked_add(shares_out as u128)
-                .ok_or(ErrorCode::MathOverflow)?;
-        } else {
-            market.total_no_shares = market.total_no_shares
-                .checked_add(shares_out as u128)
-                .ok_or(ErrorCode::MathOverflow)?;
+        position.cosigner = cosigner;
+        position.cosign_threshold = cosign_threshold;
+
+        msg!(
+            "Position of {} in market #{}: cosigner {:?} above {} lamports",
+            position.user,
+            position.market_id,
+            cosigner,
+            cosign_threshold
+        );
+        Ok(())
+    }
+
+    // With `allow_partial` a buy too large for the pool fills as far as MIN_RESERVE allows
+    // instead of failing; only the filled amount is taken from the wallet. Returns the
+    // lamports actually spent, fee included
+    pub fn buy_shares(
+        ctx: Context<BuyShares>,
+        is_yes: bool,
+        amount_lamports: u64,
+        min_shares_out: u64,
+        fee_bucket: Option<u16>,
+        allow_partial: Option<bool>,
+    ) -> Result<u64> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let mut filled = amount_lamports;
+        if allow_partial.unwrap_or(false) {
+            let market = &ctx.accounts.market;
+            let fee_bps = ctx.accounts.config.fee_bps_for(market);
+            if let Some(max_amount) = market.max_buy_lamports(is_yes, fee_bps) {
+                require!(max_amount > 0, ErrorCode::TradeExceedsLiquidity);
+                if max_amount < amount_lamports {
+                    msg!("Partial fill: {} of {} lamports", max_amount, amount_lamports);
+                    filled = max_amount;
+                }
+            }
         }
 
-        emit!(BuySharesEvent {
-            market_pubkey: market.key(),
-            market_id: market.market_id,
-            user: ctx.accounts.user.key(),
+        process_buy(
+            ctx.accounts.buy_accounts(now),
             is_yes,
-            shares: shares_out,
-            yes_liquidity: market.yes_liquidity,
-            no_liquidity: market.no_liquidity,
-            timestamp: Clock::get()?.unix_timestamp,
-        });
+            filled,
+            min_shares_out,
+            fee_bucket.unwrap_or(0),
+        )?;
+        Ok(filled)
+    }
 
-        msg!(
-            "User {} bought {} {} shares for {} lamports (fee: {})",
-            ctx.accounts.user.key(),
-            shares_out,
-            if is_yes { "YES" } else { "NO" },
-            amount_lamports,
-            fee
-        );
+    // Buys YES then NO in one atomic call, so a spread can be locked without price risk
+    // between the legs. The NO leg prices off the reserves the YES leg left behind
+    pub fn buy_both(
+        ctx: Context<BuyShares>,
+        yes_amount: u64,
+        no_amount: u64,
+        min_yes_shares: u64,
+        min_no_shares: u64,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        // The legs are checked one by one too, but the cosign threshold is on the total
+        let cosigner = ctx.accounts.cosigner.as_ref().map(|c| c.key());
+        ctx.accounts.user_position.require_cosigner(cosigner, yes_amount.saturating_add(no_amount))?;
 
+        let last_trade_time = ctx.accounts.user_position.last_trade_time;
+        process_buy(
+            ctx.accounts.buy_accounts(now),
+            true,
+            yes_amount,
+            min_yes_shares,
+            0,
+        )?;
+
+        // Both legs count as a single trade against the cooldown
+        ctx.accounts.user_position.last_trade_time = last_trade_time;
+        process_buy(
+            ctx.accounts.buy_accounts(now),
+            false,
+            no_amount,
+            min_no_shares,
+            0,
+        )?;
         Ok(())
     }
 
-    pub fn resolve_market(
-        ctx: Context<ResolveMarket>,
-        outcome_yes: bool,
+    // Alias of buy_shares without partial fills, which now also requires an opened
+    // position. Kept for clients that already call it
+    pub fn buy_shares_existing(
+        ctx: Context<BuyShares>,
+        is_yes: bool,
+        amount_lamports: u64,
+        min_shares_out: u64,
+        fee_bucket: Option<u16>,
+    ) -> Result<()> {
+        buy_shares(ctx, is_yes, amount_lamports, min_shares_out, fee_bucket, None)?;
+        Ok(())
+    }
+
+    // Same as buy_shares, but the guard is a cap on the average price paid per share
+    pub fn buy_shares_with_price_cap(
+        ctx: Context<BuyShares>,
+        is_yes: bool,
+        amount_lamports: u64,
+        max_avg_price_bps: u16,
     ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
         require!(
-            ctx.accounts.authority.key() == ctx.accounts.config.authority,
-            ErrorCode::Unauthorized
+            max_avg_price_bps > 0 && max_avg_price_bps as u64 <= BPS_DENOMINATOR,
+            ErrorCode::InvalidPriceCap
         );
 
-        let market = &mut ctx.accounts.market;
+        let receipt = process_buy(
+            ctx.accounts.buy_accounts(now),
+            is_yes,
+            amount_lamports,
+            0,
+            0,
+        )?;
 
-        require!(!market.resolved, ErrorCode::MarketResolved);
+        let avg_price_bps = receipt.avg_price_bps()?;
         require!(
-            Clock::get()?.unix_timestamp >= market.resolution_time,
-            ErrorCode::MarketNotExpired
+            avg_price_bps <= max_avg_price_bps as u64,
+            ErrorCode::PriceCapExceeded
         );
 
-        market.resolved = true;
-        market.outcome = Some(outcome_yes);
+        Ok(())
+    }
 
-        msg!(
-            "Market #{} resolved: {} - Outcome: {}",
-            market.market_id,
-            market.question,
-            if outcome_yes { "YES" } else { "NO" }
+    // Same as buy_shares, but reverts if the implied price already moved away from what
+    // the user saw, e.g. because the trade was front-run
+    pub fn buy_shares_protected(
+        ctx: Context<BuyShares>,
+        is_yes: bool,
+        amount_lamports: u64,
+        expected_yes_bps: u16,
+        tolerance_bps: u16,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        require!(
+            expected_yes_bps as u64 <= BPS_DENOMINATOR,
+            ErrorCode::InvalidExpectedPrice
         );
 
+        let current_yes_bps = ctx.accounts.market.implied_yes_bps();
+        if current_yes_bps.abs_diff(expected_yes_bps) > tolerance_bps {
+            msg!(
+                "Price is {} bps, expected {} within {} bps",
+                current_yes_bps,
+                expected_yes_bps,
+                tolerance_bps
+            );
+            return err!(ErrorCode::PriceMoved);
+        }
+
+        process_buy(
+            ctx.accounts.buy_accounts(now),
+            is_yes,
+            amount_lamports,
+            0,
+            0,
+        )?;
         Ok(())
     }
 
-    pub fn claim_winnings(ctx: Context<ClaimWinnings>) -> Result<()> {
-        let market = &mut ctx.accounts.market;
-        let position = &mut ctx.accounts.user_position;
+    pub fn open_trading_escrow(
+        ctx: Context<OpenTradingEscrow>,
+        operator: Pubkey,
+        deposit_lamports: u64,
+    ) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.owner = ctx.accounts.owner.key();
+        escrow.operator = operator;
+        escrow.bump = ctx.bumps.escrow;
 
-        require!(
-            position.user == ctx.accounts.user.key(),
-            ErrorCode::Unauthorized
+        if deposit_lamports > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.owner.to_account_info(),
+                    to: ctx.accounts.escrow.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_context, deposit_lamports)?;
+        }
+
+        msg!(
+            "User {} opened a trading escrow for operator {} with {} lamports",
+            ctx.accounts.owner.key(),
+            operator,
+            deposit_lamports
         );
+        Ok(())
+    }
 
-        require!(market.resolved, ErrorCode::MarketNotResolved);
-        require!(!position.claimed, ErrorCode::AlreadyClaimed);
+    // The operator trades with the escrow's lamports; shares land in the owner's position
+    pub fn buy_shares_delegated(
+        ctx: Context<BuySharesDelegated>,
+        is_yes: bool,
+        amount_lamports: u64,
+        min_shares_out: u64,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        require!(
+            escrow_available(&ctx.accounts.escrow.to_account_info())? >= amount_lamports,
+            ErrorCode::InsufficientEscrow
+        );
 
-        let outcome_yes = market.outcome.ok_or(ErrorCode::MarketNotResolved)?;
+        process_buy(
+            ctx.accounts.buy_accounts(now),
+            is_yes,
+            amount_lamports,
+            min_shares_out,
+            0,
+        )?;
+        Ok(())
+    }
 
-        let winning_shares = if outcome_yes {
-            position.yes_shares
-        } else {
-            position.no_shares
-        };
+    // Buys for someone else: the signer pays the trade and fee, the shares land in the
+    // beneficiary's position, which is opened here on the signer's rent if need be
+    pub fn buy_shares_for(
+        ctx: Context<BuySharesFor>,
+        beneficiary: Pubkey,
+        is_yes: bool,
+        amount_lamports: u64,
+        min_shares_out: u64,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let position = &mut ctx.accounts.user_position;
+        if position.user == Pubkey::default() {
+            position.user = beneficiary;
+            position.market_id = ctx.accounts.market.market_id;
+            position.bump = ctx.bumps.user_position;
+        }
 
-        require!(winning_shares > 0, ErrorCode::NoWinningShares);
+        process_buy(
+            ctx.accounts.buy_accounts(now),
+            is_yes,
+            amount_lamports,
+            min_shares_out,
+            0,
+        )?;
 
-        let total_winning_shares_u128 = if outcome_yes {
-            market.total_yes_shares
-        } else {
-            market.total_no_shares
-        };
+        msg!("{} bought for {}", ctx.accounts.payer.key(), beneficiary);
+        Ok(())
+    }
 
-        require!(total_winning_shares_u128 > 0, ErrorCode::NoWinningShares);
+    pub fn withdraw_escrow(ctx: Context<WithdrawEscrow>, amount: u64) -> Result<()> {
+        let escrow = ctx.accounts.escrow.to_account_info();
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(escrow_available(&escrow)? >= amount, ErrorCode::InsufficientEscrow);
 
-        let vault_balance = ctx.accounts.vault.lamports();
+        **escrow.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.owner.to_account_info().try_borrow_mut_lamports()? += amount;
 
-        let payout = (winning_shares as u128)
-            .checked_mul(vault_balance as u128)
-            .ok_or(ErrorCode::MathOverflow)?
-            .checked_div(total_winning_shares_u128)
-            .ok_or(ErrorCode::MathOverflow)?;
+        msg!("User {} withdrew {} lamports from their trading escrow", ctx.accounts.owner.key(), amount);
+        Ok(())
+    }
 
-        let payout = payout as u64;
+    pub fn resolve_market<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ResolveMarket<'info>>,
+        outcome_yes: bool,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let audit_bump = ctx.bumps.audit_log;
+        process_resolve(ctx.accounts, outcome_yes, [0; 32], String::new(), ResolutionSource::Manual, audit_bump, now)?;
+        notify_resolution_callback(&mut ctx.accounts.market, ctx.remaining_accounts, outcome_yes)
+    }
 
-        require!(payout > 0, ErrorCode::NoWinningShares);
+    // Same as resolve_market, committing the hash and location of the source used
+    pub fn resolve_with_evidence<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ResolveMarket<'info>>,
+        outcome_yes: bool,
+        evidence_hash: [u8; 32],
+        evidence_uri: String,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let audit_bump = ctx.bumps.audit_log;
+        process_resolve(ctx.accounts, outcome_yes, evidence_hash, evidence_uri, ResolutionSource::Manual, audit_bump, now)?;
+        notify_resolution_callback(&mut ctx.accounts.market, ctx.remaining_accounts, outcome_yes)
+    }
 
-        let market_id_bytes = market.market_id.to_le_bytes();
+    // Settles a scalar market on a value. Out-of-range values clamp to the nearest bound
+    pub fn resolve_scalar(ctx: Context<ResolveMarket>, settlement_value: i64) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        check_resolvable(ctx.accounts, now)?;
 
-        let seeds = &[
-            VAULT_SEED,
-            market_id_bytes.as_ref(),
-            &[market.vault_bump],
-        ];
-        let signer = &[&seeds[..]];
+        let market = &mut ctx.accounts.market;
+        let (lower_bound, upper_bound) = market.scalar_bounds().ok_or(ErrorCode::NotScalarMarket)?;
+        let settled = settlement_value.clamp(lower_bound, upper_bound);
 
-        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-            ctx.accounts.vault.key,
-            ctx.accounts.user.key,
-            payout,
-        );
+        if market.refunds_at(settled) {
+            market.cancelled = true;
+            market.resolution_source = ResolutionSource::Manual;
+            let config = &mut ctx.accounts.config;
+            config.active_markets = config.active_markets.saturating_sub(1);
 
-        anchor_lang::solana_program::program::invoke_signed(
-            &transfer_ix,
-            &[
-                ctx.accounts.vault.to_account_info(),
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-            signer,
-        )?;
+            ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+                action: AuditAction::ResolvedScalar,
+                actor: ctx.accounts.authority.key(),
+                market_id: market.market_id,
+                amount: 0,
+                timestamp: now,
+            });
 
-        if outcome_yes {
-            market.total_yes_shares = market.total_yes_shares
-                .checked_sub(winning_shares as u128)
-                .ok_or(ErrorCode::MathOverflow)?;
-        } else {
-            market.total_no_shares = market.total_no_shares
-                .checked_sub(winning_shares as u128)
-                .ok_or(ErrorCode::MathOverflow)?;
+            msg!(
+                "Market #{} settled exactly on its threshold {}; cancelled for refunds",
+                market.market_id,
+                settled
+            );
+            return Ok(());
         }
 
-        position.yes_shares = 0;
-        position.no_shares = 0;
-        position.claimed = true;
+        market.resolved = true;
+        market.resolved_at = now;
+        market.settlement_value = Some(settled);
+        market.resolution_source = ResolutionSource::Manual;
+        market.claim_deadline = claim_deadline(now, ctx.accounts.config.claim_window)?;
+        let (yes_weight, _) = market.payout_weights()?;
 
-        msg!("User {} claimed {} lamports", ctx.accounts.user.key(), payout);
+        ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+            action: AuditAction::ResolvedScalar,
+            actor: ctx.accounts.authority.key(),
+            market_id: market.market_id,
+            amount: 0,
+            timestamp: now,
+        });
+
+        emit!(ScalarResolvedEvent {
+            version: ScalarResolvedEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            settlement_value: settled,
+            yes_payout_bps: yes_weight as u16,
+            timestamp: now,
+        });
+
+        msg!(
+            "Market #{} resolved at {} (YES pays {} bps)",
+            market.market_id,
+            settled,
+            yes_weight
+        );
 
         Ok(())
     }
 
-    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+    // Undoes a resolution within config.revert_window, as long as nothing has been paid
+    // out at the resolved odds yet. A zero window disables reverts.
+    pub fn revert_resolution(ctx: Context<ResolveMarket>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
         require!(
             ctx.accounts.authority.key() == ctx.accounts.config.authority,
             ErrorCode::Unauthorized
         );
 
-        let fee_vault_balance = ctx.accounts.fee_vault.lamports();
-        require!(amount <= fee_vault_balance, ErrorCode::InsufficientFunds);
-
-        let seeds = &[
-            FEE_VAULT_SEED,
-            &[ctx.accounts.config.fee_vault_bump],
-        ];
-        let signer = &[&seeds[..]];
-
-        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-            ctx.accounts.fee_vault.key,
-            ctx.accounts.authority.key,
-            amount,
+        let revert_window = ctx.accounts.config.revert_window;
+        let market = &mut ctx.accounts.market;
+        require!(market.resolved, ErrorCode::MarketNotResolved);
+        let revert_deadline = market
+            .resolved_at
+            .checked_add(revert_window)
+            .ok_or(ErrorCode::TimeOverflow)?;
+        require!(now < revert_deadline, ErrorCode::RevertWindowClosed);
+        // Any claim fixes payout_per_share, and the seed can only be withdrawn once
+        require!(
+            market.total_claimed == 0 && market.payout_per_share == 0 && market.creator_deposit > 0,
+            ErrorCode::SettlementStarted
         );
 
-        anchor_lang::solana_program::program::invoke_signed(
-            &transfer_ix,
-            &[
-                ctx.accounts.fee_vault.to_account_info(),
-                ctx.accounts.authority.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-            signer,
-        )?;
+        market.resolved = false;
+        market.resolved_at = 0;
+        market.outcome = None;
+        market.settlement_value = None;
+        market.evidence_hash = [0; 32];
+        market.evidence_uri = String::new();
+        market.resolution_source = ResolutionSource::Manual;
+        market.claim_deadline = 0;
 
-        msg!("Authority withdrew {} lamports in fees", amount);
+        ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+            action: AuditAction::ResolutionReverted,
+            actor: ctx.accounts.authority.key(),
+            market_id: market.market_id,
+            amount: 0,
+            timestamp: now,
+        });
 
+        emit!(ResolutionRevertedEvent {
+            version: ResolutionRevertedEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            timestamp: now,
+        });
+
+        msg!("Market #{} resolution reverted", market.market_id);
         Ok(())
     }
 
-    
-}
+    pub fn claim_winnings(ctx: Context<ClaimWinnings>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let market = &mut ctx.accounts.market;
+        let position = &mut ctx.accounts.user_position;
 
-// CORRECT FIX: Use UncheckedAccount and manually initialize in the function
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + Config::LEN,
-        seeds = [b"config"],
-        bump
-    )]
-    pub config: Account<'info, Config>,
+        require!(
+            position.user == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
 
-    /// CHECK: Fee vault PDA - manually initialized in initialize() function
-    #[account(
-        mut,
-        seeds = [FEE_VAULT_SEED],
-        bump
-    )]
-    pub fee_vault: UncheckedAccount<'info>,
+        // Cancelled markets settle through claim_refund only
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+        require!(market.resolved, ErrorCode::MarketNotResolved);
+        require!(!position.claimed, ErrorCode::AlreadyClaimed);
+        market.require_claimable(now)?;
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        let winning_shares = position.winning_shares(market)?;
+        require!(winning_shares > 0, ErrorCode::NoWinningShares);
 
-    pub system_program: Program<'info, System>,
-}
+        let payout = settle_position(
+            market,
+            position,
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            ctx.accounts.insurance_vault.as_ref().map(|v| v.as_ref()),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
 
-#[derive(Accounts)]
-#[instruction(market_id: u64)]
-pub struct CreateMarket<'info> {
-    #[account(
-        mut,
-        seeds = [b"config"],
-        bump = config.bump
-    )]
-    pub config: Account<'info, Config>,
+        emit!(ClaimWinningsEvent {
+            version: ClaimWinningsEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            user: ctx.accounts.user.key(),
+            payout,
+            total_claimed: market.total_claimed,
+            timestamp: now,
+        });
 
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + Market::LEN,
-        seeds = [MARKET_SEED, market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
+        msg!("User {} claimed {} lamports", ctx.accounts.user.key(), payout);
 
-    /// CHECK: Vault PDA - will be funded with initial liquidity
-    #[account(
-        mut,
-        seeds = [VAULT_SEED, market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub vault: UncheckedAccount<'info>,
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+    // Permissionless crank: anyone may settle a position on a resolved market, and the
+    // payout always goes to the position's owner. Positions with nothing to claim are a
+    // no-op so a batch can sweep every position of a market without filtering first
+    pub fn push_claim(ctx: Context<PushClaim>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let market = &mut ctx.accounts.market;
+        let position = &mut ctx.accounts.user_position;
 
-    pub system_program: Program<'info, System>,
-}
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+        require!(market.resolved, ErrorCode::MarketNotResolved);
 
-#[derive(Accounts)]
-pub struct BuyShares<'info> {
-    #[account(
-        seeds = [b"config"],
-        bump = config.bump
-    )]
-    pub config: Account<'info, Config>,
+        if position.claimed || position.winning_shares(market)? == 0 {
+            msg!("Position of {} has nothing to claim", position.user);
+            return Ok(());
+        }
+        market.require_claimable(now)?;
 
-    #[account(
-        mut,
-        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
-        bump = market.bump
-    )]
-    pub market: Account<'info, Market>,
+        let payout = settle_position(
+            market,
+            position,
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            ctx.accounts.insurance_vault.as_ref().map(|v| v.as_ref()),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
 
-    /// CHECK: Vault PDA validated by seeds
-    #[account(
-        mut,
-        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
-        bump = market.vault_bump
-    )]
-    pub vault: UncheckedAccount<'info>,
+        emit!(ClaimWinningsEvent {
+            version: ClaimWinningsEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            user: position.user,
+            payout,
+            total_claimed: market.total_claimed,
+            timestamp: now,
+        });
 
-    /// CHECK: Fee vault PDA validated by seeds - initialized in initialize()
-    #[account(
-        mut,
-        seeds = [FEE_VAULT_SEED],
-        bump = config.fee_vault_bump
-    )]
-    pub fee_vault: UncheckedAccount<'info>,
+        msg!(
+            "{} pushed a claim of {} lamports to {}",
+            ctx.accounts.caller.key(),
+            payout,
+            position.user
+        );
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + UserPosition::LEN,
-        seeds = [
-            USER_POSITION_SEED,
-            user.key().as_ref(),
-            market.market_id.to_le_bytes().as_ref()
-        ],
-        bump
-    )]
-    pub user_position: Account<'info, UserPosition>,
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+    pub fn claim_partial(ctx: Context<ClaimWinnings>, shares: u64) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let market = &mut ctx.accounts.market;
+        let position = &mut ctx.accounts.user_position;
 
-    pub system_program: Program<'info, System>,
-}
+        require!(
+            position.user == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
 
-#[derive(Accounts)]
-pub struct ResolveMarket<'info> {
-    #[account(
-        seeds = [b"config"],
-        bump = config.bump
-    )]
-    pub config: Account<'info, Config>,
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+        require!(market.resolved, ErrorCode::MarketNotResolved);
+        require!(market.settlement_value.is_none(), ErrorCode::ScalarMarket);
+        require!(!position.claimed, ErrorCode::AlreadyClaimed);
+        require!(shares > 0, ErrorCode::InvalidAmount);
+        market.require_claimable(now)?;
 
-    #[account(
-        mut,
-        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
-        bump = market.bump
-    )]
-    pub market: Account<'info, Market>,
+        let winning_shares = position.winning_shares(market)?;
+        require!(winning_shares > 0, ErrorCode::NoWinningShares);
+        require!(shares <= winning_shares, ErrorCode::InsufficientShares);
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
-}
+        let (yes_shares, no_shares) = if market.outcome == Some(true) { (shares, 0) } else { (0, shares) };
+        let payout = settle_winnings(
+            market,
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            ctx.accounts.insurance_vault.as_ref().map(|v| v.as_ref()),
+            &ctx.accounts.system_program.to_account_info(),
+            yes_shares,
+            no_shares,
+        )?;
+        // A partial claim that pays nothing would only burn shares
+        require!(payout > 0, ErrorCode::NoWinningShares);
 
-#[derive(Accounts)]
-pub struct ClaimWinnings<'info> {
-    #[account(
-        mut,
-        seeds = [MARKET_SEED, user_position.market_id.to_le_bytes().as_ref()],
-        bump = market.bump
-    )]
-    pub market: Account<'info, Market>,
+        let remaining = winning_shares - shares;
+        if market.outcome == Some(true) {
+            position.yes_shares = remaining;
+        } else {
+            position.no_shares = remaining;
+        }
+        if remaining == 0 {
+            position.yes_shares = 0;
+            position.no_shares = 0;
+            position.claimed = true;
+        }
 
-    /// CHECK: Vault PDA validated by seeds
-    #[account(
-        mut,
-        seeds = [VAULT_SEED, user_position.market_id.to_le_bytes().as_ref()],
-        bump = market.vault_bump
-    )]
-    pub vault: UncheckedAccount<'info>,
+        emit!(ClaimWinningsEvent {
+            version: ClaimWinningsEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            user: ctx.accounts.user.key(),
+            payout,
+            total_claimed: market.total_claimed,
+            timestamp: now,
+        });
 
-    #[account(
-        mut,
-        seeds = [
-            USER_POSITION_SEED,
-            user.key().as_ref(),
-            user_position.market_id.to_le_bytes().as_ref()
-        ],
-        bump = user_position.bump
-    )]
-    pub user_position: Account<'info, UserPosition>,
+        msg!(
+            "User {} claimed {} lamports for {} shares ({} remaining)",
+            ctx.accounts.user.key(),
+            payout,
+            shares,
+            remaining
+        );
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+        Ok(())
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    // Deepens a constant-product pool by scaling both reserves by the same factor, so the
+    // implied price stays put. The lamports join the creator's seed and come back the
+    // same way, through withdraw_creator_liquidity.
+    //
+    // With `rebalance`, REBALANCE_FEE_BPS of the lamports go to the fee vault and the
+    // rest lifts the smaller reserve toward the larger, nudging a lopsided pool back
+    // toward even odds. Either way the deposit enters the vault and the reserves in
+    // equal measure, so the vault's margin over its reserves, which is what backs
+    // outstanding shares, is the same as after a proportional top-up. Because the
+    // price does move, a rebalance also checks that selling back every outstanding
+    // share of either side stays within the vault
+    pub fn authority_add_liquidity(
+        ctx: Context<AuthorityAddLiquidity>,
+        lamports: u64,
+        rebalance: bool,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(lamports > 0, ErrorCode::InvalidAmount);
 
-#[derive(Accounts)]
-pub struct WithdrawFees<'info> {
-    #[account(
-        seeds = [b"config"],
-        bump = config.bump
-    )]
-    pub config: Account<'info, Config>,
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+        require!(market.market_type != MarketType::Lmsr, ErrorCode::LiquidityTopUpUnsupported);
 
-    /// CHECK: Fee vault PDA validated by seeds
-    #[account(
-        mut,
-        seeds = [FEE_VAULT_SEED],
-        bump = config.fee_vault_bump
-    )]
-    pub fee_vault: UncheckedAccount<'info>,
+        let fee = if rebalance { lamports * REBALANCE_FEE_BPS / BPS_DENOMINATOR } else { 0 };
+        let deposit = lamports - fee;
+        require!(deposit > 0, ErrorCode::InvalidAmount);
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        let (yes_liquidity, no_liquidity) = if rebalance {
+            rebalanced_reserves(market.yes_liquidity, market.no_liquidity, deposit)?
+        } else {
+            proportional_reserves(market.yes_liquidity, market.no_liquidity, deposit)?
+        };
+        market.yes_liquidity = yes_liquidity;
+        market.no_liquidity = no_liquidity;
 
-    pub system_program: Program<'info, System>,
-}
+        let precision = market.precision();
+        market.k_constant = (market.yes_liquidity as u128)
+            .checked_mul(precision)
+            .and_then(|k| k.checked_mul(market.no_liquidity as u128))
+            .and_then(|k| k.checked_mul(precision))
+            .ok_or(ErrorCode::MathOverflow)?;
+        market.creator_deposit = market.creator_deposit
+            .checked_add(deposit)
+            .ok_or(ErrorCode::MathOverflow)?;
 
+        if rebalance {
+            let distributable = ctx.accounts.vault.lamports()
+                .checked_add(deposit)
+                .ok_or(ErrorCode::MathOverflow)?
+                .saturating_sub(vault_rent_floor()?);
+            market.require_sell_back_covered(distributable)?;
+        }
 
-#[account]
-pub struct Config {
-    pub authority: Pubkey,
-    pub market_count: u64,
-    pub fee_percentage: u16,
-    pub bump: u8,
-    pub fee_vault_bump: u8,
-}
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.authority.to_account_info(),
+                to: ctx.accounts.vault.to_account_info(),
+            },
+        );
+        system_program::transfer(cpi_context, deposit)?;
 
-impl Config {
-    pub const LEN: usize = 32 + 8 + 2 + 1 + 1;
-}
+        if fee > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.authority.to_account_info(),
+                    to: ctx.accounts.fee_vault.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_context, fee)?;
+        }
 
-#[account]
-pub struct Market {
-    pub market_id: u64,
-    pub authority: Pubkey,
-    pub question: String,
-    pub description: String,
-    pub category: String,
+        msg!(
+            "Market #{} topped up with {} lamports (fee: {}); reserves now {} / {}",
+            market.market_id,
+            deposit,
+            fee,
+            market.yes_liquidity,
+            market.no_liquidity
+        );
+        Ok(())
+    }
+
+    pub fn withdraw_creator_liquidity(ctx: Context<WithdrawCreatorLiquidity>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+
+        require!(
+            ctx.accounts.creator.key() == market.creator,
+            ErrorCode::Unauthorized
+        );
+        require!(market.resolved || market.cancelled, ErrorCode::MarketNotResolved);
+        require!(market.creator_deposit > 0, ErrorCode::NoRemainingFunds);
+
+        // Winners (or refunds) are paid first; the seed can only come out of what they leave behind
+        if market.cancelled {
+            require!(market.total_contributed == 0, ErrorCode::RefundsOutstanding);
+        } else {
+            require!(
+                market.total_winning_shares()? == 0,
+                ErrorCode::UnclaimedWinningsOutstanding
+            );
+        }
+
+        let surplus = ctx.accounts.vault.lamports().saturating_sub(vault_rent_floor()?);
+        let amount = market.creator_deposit.min(surplus);
+        require!(amount > 0, ErrorCode::NoRemainingFunds);
+
+        vault_transfer(
+            market,
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        market.creator_deposit = 0;
+
+        msg!(
+            "Creator {} recovered {} lamports of seed liquidity from market #{}",
+            ctx.accounts.creator.key(),
+            amount,
+            market.market_id
+        );
+
+        Ok(())
+    }
+
+    // Once a market's claim deadline has passed, moves what its unclaimed winning shares
+    // would have been paid to the treasury. Only the winners' portion goes: the seed a
+    // fixed redemption leaves behind stays for the creator. Afterwards close_market works
+    pub fn sweep_unclaimed(ctx: Context<SweepUnclaimed>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &mut ctx.accounts.market;
+        require!(market.resolved, ErrorCode::MarketNotResolved);
+        let distributable = ctx.accounts.vault.lamports().saturating_sub(vault_rent_floor()?);
+        let (shares, amount) = market.sweepable_unclaimed(now, distributable)?;
+
+        market.snapshot_payout_per_share(distributable)?;
+        let (yes_weight, no_weight) = market.payout_weights()?;
+        if yes_weight > 0 {
+            market.total_yes_shares = 0;
+        }
+        if no_weight > 0 {
+            market.total_no_shares = 0;
+        }
+
+        vault_transfer(
+            market,
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.treasury.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+            action: AuditAction::UnclaimedSwept,
+            actor: ctx.accounts.authority.key(),
+            market_id: market.market_id,
+            amount,
+            timestamp: now,
+        });
+
+        emit!(UnclaimedSweptEvent {
+            version: UnclaimedSweptEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            shares,
+            lamports: amount,
+            timestamp: now,
+        });
+
+        msg!(
+            "Market #{}: {} lamports for {} unclaimed shares swept to the treasury",
+            market.market_id,
+            amount,
+            shares
+        );
+        Ok(())
+    }
+
+    // Retires a fully settled market: resolved with every winner paid, or cancelled with
+    // every refund taken. Rounding residual in the vault is protocol revenue and goes to
+    // the fee vault; only the rent of the closed accounts returns to the authority.
+    pub fn close_market(ctx: Context<CloseMarket>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &ctx.accounts.market;
+        if market.cancelled {
+            require!(market.total_contributed == 0, ErrorCode::RefundsOutstanding);
+        } else {
+            require!(market.resolved, ErrorCode::MarketNotResolved);
+            require!(
+                market.total_winning_shares()? == 0,
+                ErrorCode::UnclaimedWinningsOutstanding
+            );
+        }
+
+        let vault = ctx.accounts.vault.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        let rent_floor = vault_rent_floor()?;
+        let residual = vault.lamports().saturating_sub(rent_floor);
+        // Whatever is left above the floor still belongs to the creator until withdrawn
+        require!(
+            market.creator_deposit == 0 || residual == 0,
+            ErrorCode::CreatorLiquidityOutstanding
+        );
+
+        vault_transfer(market, &vault, &ctx.accounts.fee_vault.to_account_info(), &system_program, residual)?;
+        vault_transfer(market, &vault, &ctx.accounts.authority.to_account_info(), &system_program, vault.lamports())?;
+
+        // Its seeds die with the market, so the insurance vault has to close now or never
+        let insurance_vault = ctx.accounts.insurance_vault.to_account_info();
+        if insurance_vault.owner == &crate::ID {
+            close_pda_account(&insurance_vault, &ctx.accounts.fee_vault.to_account_info())?;
+        } else {
+            // A legacy market that never opened one; open_insurance_vault adopts stray lamports
+            require!(insurance_vault.lamports() == 0, ErrorCode::InsuranceVaultNotEmpty);
+        }
+
+        // Cancelling already took the market off the active count
+        if !market.cancelled {
+            let config = &mut ctx.accounts.config;
+            config.active_markets = config.active_markets.saturating_sub(1);
+        }
+
+        ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+            action: AuditAction::MarketClosed,
+            actor: ctx.accounts.authority.key(),
+            market_id: market.market_id,
+            amount: residual,
+            timestamp: now,
+        });
+
+        msg!(
+            "Market #{} closed, {} lamports of residual sent to the fee vault",
+            market.market_id,
+            residual
+        );
+        Ok(())
+    }
+
+    // Grows a Config written by an older program to the current layout, giving the
+    // fields it predates their initialize defaults. Run it before migrate_market, which
+    // needs a Config that deserializes; on a current Config it does nothing
+    pub fn migrate_config(ctx: Context<MigrateConfig>) -> Result<()> {
+        let config_info = ctx.accounts.config.to_account_info();
+        let config = read_stored_config(&config_info.try_borrow_data()?)?;
+        require!(
+            ctx.accounts.authority.key() == config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let target_len = 8 + Config::LEN;
+        if config_info.data_len() >= target_len {
+            msg!("Config already at the current layout");
+            return Ok(());
+        }
+
+        let old_len = config_info.data_len();
+        resize_with_rent(
+            &config_info,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            target_len,
+        )?;
+        config.try_serialize(&mut &mut config_info.try_borrow_mut_data()?[..])?;
+
+        msg!("Config migrated from {} to {} bytes", old_len, target_len);
+        Ok(())
+    }
+
+    // Brings a market up to the current layout. A market still in the original layout,
+    // with its question, description and category inline, passes the MarketMetadata
+    // PDA they move to as the first remaining account. Markets never filed under a
+    // category pass metadata, category stats and the category's current index page
+    pub fn migrate_market<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MigrateMarket<'info>>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market_info = ctx.accounts.market.to_account_info();
+
+        // The account may not deserialize yet, so check it by hand
+        let market_id = {
+            let data = market_info.try_borrow_data()?;
+            require!(
+                data.len() >= 16 && &data[..8] == Market::DISCRIMINATOR,
+                ErrorCode::InvalidMarketAccount
+            );
+            u64::from_le_bytes(data[8..16].try_into().unwrap())
+        };
+        let (expected_market, _) = Pubkey::find_program_address(
+            &[MARKET_SEED, market_id.to_le_bytes().as_ref()],
+            ctx.program_id,
+        );
+        require_keys_eq!(expected_market, market_info.key(), ErrorCode::InvalidMarketAccount);
+
+        let legacy = LegacyMarket::read(&market_info.try_borrow_data()?);
+        if let Some(legacy) = legacy {
+            let id_bytes = market_id.to_le_bytes();
+            let metadata_info = ctx
+                .remaining_accounts
+                .first()
+                .ok_or(ErrorCode::MigrationAccountMismatch)?;
+            let metadata_bump = supplied_pda_bump(
+                metadata_info,
+                &[METADATA_SEED, &id_bytes],
+                ErrorCode::MigrationAccountMismatch,
+            )?;
+            create_pda_account(
+                &ctx.accounts.authority.to_account_info(),
+                metadata_info,
+                &ctx.accounts.system_program.to_account_info(),
+                8 + MarketMetadata::LEN,
+                &[METADATA_SEED, &id_bytes, &[metadata_bump]],
+            )?;
+
+            let (market, metadata) = legacy.split(metadata_bump)?;
+            store_account(&metadata, metadata_info)?;
+
+            // Without the strings the account shrinks; any spare rent stays with it
+            resize_with_rent(
+                &market_info,
+                &ctx.accounts.authority.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                8 + Market::LEN,
+            )?;
+            store_account(&market, &market_info)?;
+            msg!("Market #{} moved its question, description and category to metadata", market_id);
+        }
+
+        let target_len = 8 + Market::LEN;
+        if market_info.data_len() < target_len {
+            resize_with_rent(
+                &market_info,
+                &ctx.accounts.authority.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                target_len,
+            )?;
+        }
+
+        let upgraded = upgrade_market_data(&mut market_info.try_borrow_mut_data()?)?;
+
+        // Trading finds category stats through category_hash, which markets from
+        // before v3 never got
+        let mut market = Market::try_deserialize(&mut &market_info.try_borrow_data()?[..])?;
+        let uncategorised = market.category_hash == [0; 32];
+        if uncategorised {
+            let [metadata_info, stats_info, index_info, ..] = ctx.remaining_accounts else {
+                return err!(ErrorCode::MigrationAccountMismatch);
+            };
+            let authority = ctx.accounts.authority.to_account_info();
+            let system_program = ctx.accounts.system_program.to_account_info();
+
+            supplied_pda_bump(
+                metadata_info,
+                &[METADATA_SEED, &market_id.to_le_bytes()],
+                ErrorCode::MigrationAccountMismatch,
+            )?;
+            let metadata = MarketMetadata::try_deserialize(&mut &metadata_info.try_borrow_data()?[..])?;
+            let category_hash = hash(metadata.category.as_bytes()).to_bytes();
+
+            let stats_bump = supplied_pda_bump(
+                stats_info,
+                &[CATEGORY_STATS_SEED, &category_hash],
+                ErrorCode::MigrationAccountMismatch,
+            )?;
+            let mut category_stats: CategoryStats = load_or_create_pda(
+                &authority,
+                stats_info,
+                &system_program,
+                8 + CategoryStats::LEN,
+                &[CATEGORY_STATS_SEED, &category_hash, &[stats_bump]],
+            )?;
+            let page_bytes = (category_stats.market_count / CATEGORY_PAGE_SIZE).to_le_bytes();
+            let index_bump = supplied_pda_bump(
+                index_info,
+                &[CATEGORY_INDEX_SEED, &category_hash, &page_bytes],
+                ErrorCode::MigrationAccountMismatch,
+            )?;
+            let mut category_index: CategoryIndex = load_or_create_pda(
+                &authority,
+                index_info,
+                &system_program,
+                8 + CategoryIndex::LEN,
+                &[CATEGORY_INDEX_SEED, &category_hash, &page_bytes, &[index_bump]],
+            )?;
+
+            file_under_category(
+                &ctx.accounts.config,
+                &mut market,
+                &metadata.category,
+                &mut category_stats,
+                stats_bump,
+                &mut category_index,
+                index_bump,
+            )?;
+            store_account(&category_stats, stats_info)?;
+            store_account(&category_index, index_info)?;
+            store_account(&market, &market_info)?;
+            msg!("Market #{} filed under category {}", market_id, metadata.category);
+        }
+
+        if upgraded {
+            msg!("Market #{} migrated to layout v{}", market_id, MARKET_VERSION);
+        } else if !uncategorised {
+            msg!("Market #{} already at layout v{}", market_id, MARKET_VERSION);
+        }
+        Ok(())
+    }
+
+    // Voids an unresolved market. Trading stops and every position can reclaim the
+    // lamports it actually put in through claim_refund
+    pub fn cancel_market(ctx: Context<UpdateMarket>) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+
+        market.cancelled = true;
+        market.resolution_source = ResolutionSource::Manual;
+        let config = &mut ctx.accounts.config;
+        config.active_markets = config.active_markets.saturating_sub(1);
+
+        msg!(
+            "Market #{} cancelled with {} lamports of contributions to refund",
+            market.market_id,
+            market.total_contributed
+        );
+        Ok(())
+    }
+
+    // Records on the market that trading has ended, so readers need not work it out from
+    // the clock. Anyone may call it once the trading cutoff has passed
+    pub fn close_trading(ctx: Context<CloseTrading>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let open = ctx.accounts.config.trading_open(ctx.accounts.market.resolution_time, now)?;
+        let market = &mut ctx.accounts.market;
+        require!(!market.trading_closed, ErrorCode::TradingClosed);
+        require!(!open, ErrorCode::TradingStillOpen);
+
+        market.trading_closed = true;
+
+        emit!(TradingClosedEvent {
+            version: TradingClosedEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            timestamp: now,
+        });
+
+        msg!("Market #{} closed to trading by {}", market.market_id, ctx.accounts.caller.key());
+        Ok(())
+    }
+
+    // Lets anyone void a market the authority never resolved, once
+    // resolve_grace_period has passed since resolution_time
+    pub fn enable_refunds(ctx: Context<EnableRefunds>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+
+        let deadline = refund_deadline(market.resolution_time, ctx.accounts.config.resolve_grace_period)?;
+        require!(
+            now >= deadline,
+            ErrorCode::ResolveDeadlineNotReached
+        );
+
+        market.cancelled = true;
+        market.resolution_source = ResolutionSource::AutoRefund;
+        let config = &mut ctx.accounts.config;
+        config.active_markets = config.active_markets.saturating_sub(1);
+
+        msg!(
+            "Market #{} unresolved past {}; refunds enabled by {}",
+            market.market_id,
+            deadline,
+            ctx.accounts.caller.key()
+        );
+        Ok(())
+    }
+
+    pub fn claim_refund(ctx: Context<ClaimWinnings>) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let position = &mut ctx.accounts.user_position;
+
+        require!(
+            position.user == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
+        require!(market.cancelled, ErrorCode::MarketNotCancelled);
+        require!(!position.claimed, ErrorCode::AlreadyClaimed);
+
+        let refund = position.lamports_contributed;
+        require!(refund > 0, ErrorCode::NothingToRefund);
+
+        vault_transfer(
+            market,
+            &ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            refund,
+        )?;
+
+        market.total_contributed = market.total_contributed.saturating_sub(refund);
+        market.total_yes_shares = market.total_yes_shares.saturating_sub(position.yes_shares as u128);
+        market.total_no_shares = market.total_no_shares.saturating_sub(position.no_shares as u128);
+        position.yes_shares = 0;
+        position.no_shares = 0;
+        position.lamports_contributed = 0;
+        position.claimed = true;
+
+        msg!("User {} refunded {} lamports", ctx.accounts.user.key(), refund);
+
+        Ok(())
+    }
+
+    pub fn set_trade_cooldown(ctx: Context<UpdateMarket>, trade_cooldown: i64) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(trade_cooldown >= 0, ErrorCode::InvalidCooldown);
+
+        let market = &mut ctx.accounts.market;
+        market.trade_cooldown = trade_cooldown;
+
+        msg!("Market #{} trade cooldown set to {} seconds", market.market_id, trade_cooldown);
+        Ok(())
+    }
+
+    pub fn set_max_markets_per_category(
+        ctx: Context<UpdateConfig>,
+        max_markets_per_category: u64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        ctx.accounts.config.max_markets_per_category = max_markets_per_category;
+
+        msg!("Max markets per category set to {}", max_markets_per_category);
+        Ok(())
+    }
+
+    pub fn set_require_trades_to_resolve(
+        ctx: Context<UpdateConfig>,
+        require_trades_to_resolve: bool,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        ctx.accounts.config.require_trades_to_resolve = require_trades_to_resolve;
+
+        msg!("Require trades to resolve set to {}", require_trades_to_resolve);
+        Ok(())
+    }
+
+    pub fn set_min_market_duration(
+        ctx: Context<UpdateConfig>,
+        min_market_duration: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(min_market_duration >= 0, ErrorCode::InvalidMarketDuration);
+
+        ctx.accounts.config.min_market_duration = min_market_duration;
+
+        msg!("Minimum market duration set to {} seconds", min_market_duration);
+        Ok(())
+    }
+
+    pub fn set_max_market_duration(
+        ctx: Context<UpdateConfig>,
+        max_market_duration: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(max_market_duration >= 0, ErrorCode::InvalidMarketDuration);
+
+        ctx.accounts.config.max_market_duration = max_market_duration;
+
+        msg!("Maximum market duration set to {} seconds", max_market_duration);
+        Ok(())
+    }
+
+    pub fn set_resolve_grace_period(
+        ctx: Context<UpdateConfig>,
+        resolve_grace_period: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(resolve_grace_period >= 0, ErrorCode::InvalidGracePeriod);
+
+        ctx.accounts.config.resolve_grace_period = resolve_grace_period;
+
+        msg!("Resolve grace period set to {} seconds", resolve_grace_period);
+        Ok(())
+    }
+
+    pub fn set_revert_window(ctx: Context<UpdateConfig>, revert_window: i64) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(revert_window >= 0, ErrorCode::InvalidRevertWindow);
+
+        ctx.accounts.config.revert_window = revert_window;
+
+        msg!("Resolution revert window set to {} seconds", revert_window);
+        Ok(())
+    }
+
+    pub fn set_create_cooldown(ctx: Context<UpdateConfig>, create_cooldown: i64) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(create_cooldown >= 0, ErrorCode::InvalidCreateCooldown);
+
+        ctx.accounts.config.create_cooldown = create_cooldown;
+
+        msg!("Market creation cooldown set to {} seconds", create_cooldown);
+        Ok(())
+    }
+
+    // Zero sends the whole trade fee to the fee vault
+    pub fn set_insurance_fee(ctx: Context<UpdateConfig>, insurance_fee_bps: u16) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            insurance_fee_bps as u64 <= BPS_DENOMINATOR,
+            ErrorCode::InvalidInsuranceFee
+        );
+
+        ctx.accounts.config.insurance_fee_bps = insurance_fee_bps;
+
+        msg!("Insurance fee set to {} bps of each trade fee", insurance_fee_bps);
+        Ok(())
+    }
+
+    pub fn set_treasury(ctx: Context<UpdateConfig>, treasury: Pubkey) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(treasury != Pubkey::default(), ErrorCode::InvalidTreasury);
+
+        ctx.accounts.config.treasury = treasury;
+
+        msg!("Treasury set to {}", treasury);
+        Ok(())
+    }
+
+    // Applies to markets resolved from now on; each market keeps the deadline it got
+    pub fn set_claim_window(ctx: Context<UpdateConfig>, claim_window: i64) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            claim_window == 0 || claim_window >= MIN_CLAIM_WINDOW,
+            ErrorCode::InvalidClaimWindow
+        );
+
+        ctx.accounts.config.claim_window = claim_window;
+
+        msg!("Claim window set to {} seconds", claim_window);
+        Ok(())
+    }
+
+    // Lets the authority and treasury buy fee-free, for the protocol's own market making
+    pub fn set_house_fee_exempt(ctx: Context<UpdateConfig>, exempt: bool) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        ctx.accounts.config.house_fee_exempt = exempt;
+
+        msg!("House fee exemption {}", if exempt { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    pub fn add_resolver(ctx: Context<UpdateConfig>, resolver: Pubkey) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let resolvers = &mut ctx.accounts.config.resolvers;
+        require!(!resolvers.contains(&resolver), ErrorCode::ResolverExists);
+        require!(resolvers.len() < MAX_RESOLVERS, ErrorCode::TooManyResolvers);
+        resolvers.push(resolver);
+
+        msg!("Resolver {} added", resolver);
+        Ok(())
+    }
+
+    pub fn remove_resolver(ctx: Context<UpdateConfig>, resolver: Pubkey) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let resolvers = &mut ctx.accounts.config.resolvers;
+        let index = resolvers
+            .iter()
+            .position(|r| *r == resolver)
+            .ok_or(ErrorCode::ResolverNotFound)?;
+        resolvers.swap_remove(index);
+
+        msg!("Resolver {} removed", resolver);
+        Ok(())
+    }
+
+    pub fn set_max_fee_bps(ctx: Context<UpdateConfig>, max_fee_bps: u16) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(max_fee_bps as u64 <= BPS_DENOMINATOR, ErrorCode::FeeTooHigh);
+
+        ctx.accounts.config.max_fee_bps = max_fee_bps;
+
+        msg!("Maximum fee set to {} bps", max_fee_bps);
+        Ok(())
+    }
+
+    pub fn set_fee(ctx: Context<SetFee>, fee_percentage: u16) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(fee_percentage <= ctx.accounts.config.max_fee_bps, ErrorCode::FeeTooHigh);
+
+        ctx.accounts.config.fee_percentage = fee_percentage;
+        ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+            action: AuditAction::FeeSet,
+            actor: ctx.accounts.authority.key(),
+            market_id: 0,
+            amount: fee_percentage as u64,
+            timestamp: now,
+        });
+
+        msg!("Trading fee set to {} bps", fee_percentage);
+        Ok(())
+    }
+
+    // None returns the market to the global fee
+    pub fn set_market_fee(ctx: Context<UpdateMarket>, fee_bps: Option<u16>) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        if let Some(fee_bps) = fee_bps {
+            require!(fee_bps <= ctx.accounts.config.max_fee_bps, ErrorCode::FeeTooHigh);
+        }
+
+        let market = &mut ctx.accounts.market;
+        market.fee_bps = fee_bps;
+
+        msg!("Market #{} fee set to {:?} bps", market.market_id, fee_bps);
+        Ok(())
+    }
+
+    // Chooses between a parimutuel split and a fixed redemption per winning share.
+    // Fixed before trading so buyers know how they will be paid
+    pub fn set_payout_mode(ctx: Context<UpdateMarket>, payout_mode: PayoutMode) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        if let PayoutMode::FixedRedemption { lamports_per_share } = payout_mode {
+            require!(lamports_per_share > 0, ErrorCode::InvalidPayoutMode);
+        }
+
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.total_yes_shares == 0 && market.total_no_shares == 0 && market.trade_sequence == 0,
+            ErrorCode::MarketAlreadyTraded
+        );
+        market.payout_mode = payout_mode;
+
+        msg!("Market #{} payout mode set to {:?}", market.market_id, payout_mode);
+        Ok(())
+    }
+
+    // Makes a scalar market settle over/under `threshold.value`, with `at_threshold`
+    // deciding what a settlement exactly on it pays; None restores linear payouts.
+    // Fixed before trading, since it changes what every share is worth
+    pub fn set_scalar_threshold(
+        ctx: Context<UpdateMarket>,
+        threshold: Option<ScalarThreshold>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &mut ctx.accounts.market;
+        let (lower_bound, upper_bound) = market.scalar_bounds().ok_or(ErrorCode::NotScalarMarket)?;
+        if let Some(threshold) = threshold {
+            require!(
+                threshold.value > lower_bound && threshold.value < upper_bound,
+                ErrorCode::InvalidScalarThreshold
+            );
+        }
+        require!(
+            market.total_yes_shares == 0 && market.total_no_shares == 0 && market.trade_sequence == 0,
+            ErrorCode::MarketAlreadyTraded
+        );
+        market.scalar_threshold = threshold;
+
+        msg!("Market #{} scalar threshold set to {:?}", market.market_id, threshold);
+        Ok(())
+    }
+
+    // Restricts buying to the users listed under `access_gate`; None makes the market public
+    pub fn set_access_gate(ctx: Context<UpdateMarket>, access_gate: Option<Pubkey>) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.access_gate = access_gate;
+
+        msg!("Market #{} access gate set to {:?}", market.market_id, access_gate);
+        Ok(())
+    }
+
+    // Points the market at a program to notify on resolution. With `required` set the
+    // market cannot resolve unless that program is passed in as a remaining account
+    pub fn set_resolution_callback(
+        ctx: Context<UpdateMarket>,
+        callback: Option<Pubkey>,
+        required: bool,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        market.resolution_callback = callback;
+        market.callback_required = required && callback.is_some();
+
+        msg!(
+            "Market #{} resolution callback set to {:?} (required: {})",
+            market.market_id,
+            callback,
+            market.callback_required
+        );
+        Ok(())
+    }
+
+    pub fn add_to_whitelist(ctx: Context<AddToWhitelist>, gate: Pubkey, user: Pubkey) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let entry = &mut ctx.accounts.whitelist_entry;
+        entry.gate = gate;
+        entry.user = user;
+        entry.bump = ctx.bumps.whitelist_entry;
+
+        msg!("User {} whitelisted under gate {}", user, gate);
+        Ok(())
+    }
+
+    // Positions already held stay tradable; only further buys are blocked
+    pub fn remove_from_whitelist(ctx: Context<RemoveFromWhitelist>) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let entry = &ctx.accounts.whitelist_entry;
+        msg!("User {} removed from gate {}", entry.user, entry.gate);
+        Ok(())
+    }
+
+    // Positions on this market hold 10^share_decimals shares per lamport of pool
+    // liquidity. Only settable before any shares exist, and not for LMSR, which prices
+    // straight from the share totals.
+    pub fn set_share_decimals(ctx: Context<UpdateMarket>, share_decimals: u8) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &mut ctx.accounts.market;
+        require!(
+            share_decimals <= MAX_SHARE_DECIMALS && market.market_type != MarketType::Lmsr,
+            ErrorCode::InvalidShareDecimals
+        );
+        require!(
+            market.total_yes_shares == 0 && market.total_no_shares == 0 && market.trade_sequence == 0,
+            ErrorCode::MarketAlreadyTraded
+        );
+        market.share_decimals = share_decimals;
+
+        msg!("Market #{} share decimals set to {}", market.market_id, share_decimals);
+        Ok(())
+    }
+
+    // Zero disables the circuit breaker
+    pub fn set_max_price_move(ctx: Context<UpdateMarket>, max_price_move_bps: u16) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(
+            max_price_move_bps as u64 <= BPS_DENOMINATOR,
+            ErrorCode::InvalidMaxPriceMove
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.max_price_move_bps = max_price_move_bps;
+
+        msg!("Market #{} max price move set to {} bps", market.market_id, max_price_move_bps);
+        Ok(())
+    }
+
+    // The creator's own limit, enforced alongside any circuit breaker; zero removes it
+    pub fn set_max_slippage(ctx: Context<SetMaxSlippage>, max_slippage_bps: u16) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            ctx.accounts.creator.key() == market.creator,
+            ErrorCode::Unauthorized
+        );
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+        require!(
+            max_slippage_bps as u64 <= BPS_DENOMINATOR,
+            ErrorCode::InvalidMaxPriceMove
+        );
+
+        market.max_slippage_bps = max_slippage_bps;
+
+        msg!("Market #{} max slippage set to {} bps", market.market_id, max_slippage_bps);
+        Ok(())
+    }
+
+    pub fn set_max_total_volume(ctx: Context<UpdateMarket>, max_total_volume: u64) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.max_total_volume = max_total_volume;
+
+        msg!("Market #{} volume cap set to {} lamports", market.market_id, max_total_volume);
+        Ok(())
+    }
+
+    pub fn set_trading_buffer(ctx: Context<UpdateConfig>, trading_buffer: i64) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(trading_buffer >= 0, ErrorCode::InvalidTradingBuffer);
+
+        ctx.accounts.config.trading_buffer = trading_buffer;
+
+        msg!("Trading buffer set to {} seconds", trading_buffer);
+        Ok(())
+    }
+
+    pub fn init_fee_bucket(ctx: Context<InitFeeBucket>, fee_bucket: u16) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        // initialize() sets up bucket 0; this only re-funds it after close_fee_vault
+        let min_rent = Rent::get()?.minimum_balance(0);
+        let top_up = min_rent.saturating_sub(ctx.accounts.fee_vault.lamports());
+        if top_up > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.authority.to_account_info(),
+                    to: ctx.accounts.fee_vault.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_context, top_up)?;
+        }
+
+        msg!("Fee bucket {} initialized at: {}", fee_bucket, ctx.accounts.fee_vault.key());
+        Ok(())
+    }
+
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64, fee_bucket: u16) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let fee_vault_balance = ctx.accounts.fee_vault.lamports();
+        if amount > fee_vault_balance {
+            msg!("Fee vault holds {} lamports, {} requested", fee_vault_balance, amount);
+            return err!(ErrorCode::InsufficientFunds);
+        }
+
+        fee_vault_transfer(
+            &ctx.accounts.fee_vault,
+            &ctx.accounts.treasury,
+            &ctx.accounts.system_program,
+            fee_bucket,
+            ctx.bumps.fee_vault,
+            amount,
+        )?;
+
+        ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+            action: AuditAction::FeesWithdrawn,
+            actor: ctx.accounts.authority.key(),
+            market_id: 0,
+            amount,
+            timestamp: now,
+        });
+
+        msg!(
+            "Authority withdrew {} lamports in fees from bucket {} to treasury {}",
+            amount,
+            fee_bucket,
+            ctx.accounts.treasury.key()
+        );
+
+        Ok(())
+    }
+
+    // Sweeps a fee vault's whole balance, rent included, to the treasury so the PDA is
+    // deallocated; init_fee_bucket brings it back
+    pub fn close_fee_vault(ctx: Context<CloseFeeVault>, fee_bucket: u16) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        // Sells, trigger fills and close_market always pay bucket 0, so it must stay
+        // while any market can still trade or close. Other buckets are opt-in per buy.
+        if fee_bucket == 0 {
+            require!(ctx.accounts.config.active_markets == 0, ErrorCode::FeeVaultInUse);
+        }
+
+        let balance = ctx.accounts.fee_vault.lamports();
+        require!(balance > 0, ErrorCode::FeeVaultNotInitialized);
+
+        fee_vault_transfer(
+            &ctx.accounts.fee_vault,
+            &ctx.accounts.treasury,
+            &ctx.accounts.system_program,
+            fee_bucket,
+            ctx.bumps.fee_vault,
+            balance,
+        )?;
+
+        ctx.accounts.audit_log.record(ctx.bumps.audit_log, AuditEntry {
+            action: AuditAction::FeeVaultClosed,
+            actor: ctx.accounts.authority.key(),
+            market_id: 0,
+            amount: balance,
+            timestamp: now,
+        });
+
+        msg!("Fee bucket {} closed, {} lamports swept to the treasury", fee_bucket, balance);
+
+        Ok(())
+    }
+
+    pub fn sell_shares(
+        ctx: Context<SellShares>,
+        is_yes: bool,
+        shares: u64,
+        min_lamports_out: u64,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let market = &mut ctx.accounts.market;
+        let position = &mut ctx.accounts.user_position;
+
+        require!(
+            position.user == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
+        require!(
+            ctx.accounts.fee_vault.lamports() > 0,
+            ErrorCode::FeeVaultNotInitialized
+        );
+
+        let (fee, amount_out) = execute_sell(
+            market,
+            position,
+            &ctx.accounts.config,
+            is_yes,
+            shares,
+            now,
+        )?;
+        ctx.accounts.category_stats.record_volume(fee + amount_out)?;
+
+        if amount_out < min_lamports_out {
+            msg!(
+                "Slippage: sell returns {} lamports, minimum was {} (short by {})",
+                amount_out,
+                min_lamports_out,
+                min_lamports_out - amount_out
+            );
+            return err!(ErrorCode::SlippageExceeded);
+        }
+
+        let vault = ctx.accounts.vault.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        pay_fee_from_vault(
+            market,
+            &ctx.accounts.config,
+            &vault,
+            &ctx.accounts.fee_vault.to_account_info(),
+            ctx.accounts.insurance_vault.as_ref().map(|v| v.as_ref()),
+            &system_program,
+            fee,
+        )?;
+        vault_transfer(market, &vault, &ctx.accounts.user.to_account_info(), &system_program, amount_out)?;
+
+        emit!(SellSharesEvent {
+            version: SellSharesEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            user: ctx.accounts.user.key(),
+            is_yes,
+            shares,
+            lamports_out: amount_out,
+            yes_liquidity: market.yes_liquidity,
+            no_liquidity: market.no_liquidity,
+            sequence: market.trade_sequence,
+            timestamp: now,
+        });
+
+        msg!(
+            "User {} sold {} {} shares for {} lamports (fee: {})",
+            ctx.accounts.user.key(),
+            shares,
+            if is_yes { "YES" } else { "NO" },
+            amount_out,
+            fee
+        );
+
+        Ok(())
+    }
+
+    pub fn transfer_shares(ctx: Context<TransferShares>, is_yes: bool, shares: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let sender_position = &mut ctx.accounts.sender_position;
+        let recipient_position = &mut ctx.accounts.recipient_position;
+
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+        require!(shares > 0, ErrorCode::InvalidAmount);
+
+        let held = if is_yes { sender_position.yes_shares } else { sender_position.no_shares };
+        require!(shares <= held, ErrorCode::InsufficientShares);
+
+        if recipient_position.user == Pubkey::default() {
+            recipient_position.user = ctx.accounts.recipient.key();
+            recipient_position.market_id = market.market_id;
+            recipient_position.yes_shares = 0;
+            recipient_position.no_shares = 0;
+            recipient_position.claimed = false;
+            recipient_position.bump = ctx.bumps.recipient_position;
+        }
+
+        // Totals are unchanged: shares, and the lamports that bought them, only change hands
+        let contribution = sender_position.release_contribution(shares)?;
+        recipient_position.lamports_contributed = recipient_position.lamports_contributed
+            .checked_add(contribution)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        if is_yes {
+            sender_position.yes_shares = held - shares;
+            recipient_position.yes_shares = recipient_position.yes_shares
+                .checked_add(shares)
+                .ok_or(ErrorCode::MathOverflow)?;
+        } else {
+            sender_position.no_shares = held - shares;
+            recipient_position.no_shares = recipient_position.no_shares
+                .checked_add(shares)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        msg!(
+            "User {} transferred {} {} shares to {} in market #{}",
+            ctx.accounts.sender.key(),
+            shares,
+            if is_yes { "YES" } else { "NO" },
+            ctx.accounts.recipient.key(),
+            market.market_id
+        );
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_trigger_order(
+        ctx: Context<PlaceTriggerOrder>,
+        order_id: u64,
+        is_yes: bool,
+        shares: u64,
+        trigger_price_bps: u16,
+        direction: TriggerDirection,
+        keeper_reward: u64,
+    ) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let market = &ctx.accounts.market;
+        let position = &ctx.accounts.user_position;
+
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(
+            now < market.resolution_time,
+            ErrorCode::MarketExpired
+        );
+        require!(shares > 0, ErrorCode::InvalidAmount);
+        require!(
+            trigger_price_bps > 0 && (trigger_price_bps as u64) < BPS_DENOMINATOR,
+            ErrorCode::InvalidTriggerPrice
+        );
+
+        let held = if is_yes { position.yes_shares } else { position.no_shares };
+        require!(shares <= held, ErrorCode::InsufficientShares);
+
+        let order = &mut ctx.accounts.trigger_order;
+        order.owner = ctx.accounts.owner.key();
+        order.market_id = market.market_id;
+        order.order_id = order_id;
+        order.is_yes = is_yes;
+        order.shares = shares;
+        order.trigger_price_bps = trigger_price_bps;
+        order.direction = direction;
+        order.keeper_reward = keeper_reward;
+        order.bump = ctx.bumps.trigger_order;
+
+        // Keeper reward is escrowed on the order account on top of its rent
+        if keeper_reward > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.owner.to_account_info(),
+                    to: ctx.accounts.trigger_order.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_context, keeper_reward)?;
+        }
+
+        msg!(
+            "Trigger order #{} placed on market #{}: sell {} {} shares when price {} {} bps",
+            order_id,
+            market.market_id,
+            shares,
+            if is_yes { "YES" } else { "NO" },
+            match direction {
+                TriggerDirection::Above => ">=",
+                TriggerDirection::Below => "<=",
+            },
+            trigger_price_bps
+        );
+
+        Ok(())
+    }
+
+    pub fn cancel_trigger_order(ctx: Context<CancelTriggerOrder>) -> Result<()> {
+        msg!(
+            "Trigger order #{} cancelled by {}",
+            ctx.accounts.trigger_order.order_id,
+            ctx.accounts.owner.key()
+        );
+        Ok(())
+    }
+
+    pub fn execute_trigger(ctx: Context<ExecuteTrigger>) -> Result<()> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        let market = &mut ctx.accounts.market;
+        let position = &mut ctx.accounts.user_position;
+        let order = &ctx.accounts.trigger_order;
+
+        require!(!market.resolved, ErrorCode::MarketResolved);
+
+        let yes_bps = market.implied_yes_bps();
+        let side_price_bps = if order.is_yes {
+            yes_bps
+        } else {
+            BPS_DENOMINATOR as u16 - yes_bps
+        };
+        let triggered = match order.direction {
+            TriggerDirection::Above => side_price_bps >= order.trigger_price_bps,
+            TriggerDirection::Below => side_price_bps <= order.trigger_price_bps,
+        };
+        require!(triggered, ErrorCode::TriggerNotReached);
+        require!(
+            ctx.accounts.fee_vault.lamports() > 0,
+            ErrorCode::FeeVaultNotInitialized
+        );
+
+        // The owner may have reduced the position since placing the order
+        let held = if order.is_yes { position.yes_shares } else { position.no_shares };
+        let shares = order.shares.min(held);
+
+        let (fee, amount_out) = execute_sell(
+            market,
+            position,
+            &ctx.accounts.config,
+            order.is_yes,
+            shares,
+            now,
+        )?;
+        ctx.accounts.category_stats.record_volume(fee + amount_out)?;
+
+        let vault = ctx.accounts.vault.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        pay_fee_from_vault(
+            market,
+            &ctx.accounts.config,
+            &vault,
+            &ctx.accounts.fee_vault.to_account_info(),
+            ctx.accounts.insurance_vault.as_ref().map(|v| v.as_ref()),
+            &system_program,
+            fee,
+        )?;
+        vault_transfer(market, &vault, &ctx.accounts.owner.to_account_info(), &system_program, amount_out)?;
+
+        // Pay the keeper out of the escrowed reward; the rest goes back to the owner on close
+        let reward = order.keeper_reward;
+        if reward > 0 {
+            **ctx.accounts.trigger_order.to_account_info().try_borrow_mut_lamports()? -= reward;
+            **ctx.accounts.keeper.to_account_info().try_borrow_mut_lamports()? += reward;
+        }
+
+        emit!(SellSharesEvent {
+            version: SellSharesEvent::VERSION,
+            market_pubkey: market.key(),
+            market_id: market.market_id,
+            user: order.owner,
+            is_yes: order.is_yes,
+            shares,
+            lamports_out: amount_out,
+            yes_liquidity: market.yes_liquidity,
+            no_liquidity: market.no_liquidity,
+            sequence: market.trade_sequence,
+            timestamp: now,
+        });
+
+        msg!(
+            "Trigger order #{} executed by keeper {}: sold {} shares for {} lamports at {} bps",
+            order.order_id,
+            ctx.accounts.keeper.key(),
+            shares,
+            amount_out,
+            side_price_bps
+        );
+
+        Ok(())
+    }
+
+    pub fn get_claimable_amount(ctx: Context<GetClaimableAmount>) -> Result<u64> {
+        claimable_amount(
+            &ctx.accounts.market,
+            &ctx.accounts.user_position,
+            ctx.accounts.vault.lamports(),
+        )
+    }
+
+    // A position is worth its mark-to-market value while live and its claimable payout once
+    // resolved. A user who never traded gets an all-zero view
+    pub fn get_user_position(ctx: Context<GetUserPosition>) -> Result<PositionView> {
+        let market = &ctx.accounts.market;
+        let info = ctx.accounts.user_position.to_account_info();
+        if info.data_is_empty() {
+            return Ok(PositionView::default());
+        }
+        let position = UserPosition::try_deserialize(&mut &info.data.borrow()[..])?;
+
+        let (current_value_if_live, claimable_if_resolved) = if market.resolved {
+            (0, claimable_amount(market, &position, ctx.accounts.vault.lamports())?)
+        } else {
+            (market.mark_to_market(position.yes_shares, position.no_shares)?, 0)
+        };
+
+        Ok(PositionView {
+            yes_shares: position.yes_shares,
+            no_shares: position.no_shares,
+            claimed: position.claimed,
+            current_value_if_live,
+            claimable_if_resolved,
+        })
+    }
+
+    // remaining_accounts: (market, vault, user_position) triples. Live positions are
+    // marked at the implied price, resolved ones at what a claim would pay out.
+    pub fn get_portfolio_value<'info>(
+        ctx: Context<'_, '_, 'info, 'info, GetPortfolioValue<'info>>,
+    ) -> Result<u64> {
+        require!(
+            ctx.remaining_accounts.len() % 3 == 0,
+            ErrorCode::InvalidPortfolioAccounts
+        );
+
+        let mut total: u64 = 0;
+        for accounts in ctx.remaining_accounts.chunks(3) {
+            let market = Account::<Market>::try_from(&accounts[0])?;
+            let position = Account::<UserPosition>::try_from(&accounts[2])?;
+            require!(
+                position.market_id == market.market_id,
+                ErrorCode::InvalidPortfolioAccounts
+            );
+
+            let expected_vault = Pubkey::create_program_address(
+                &[VAULT_SEED, market.market_id.to_le_bytes().as_ref(), &[market.vault_bump]],
+                ctx.program_id,
+            )
+            .map_err(|_| ErrorCode::InvalidPortfolioAccounts)?;
+            require_keys_eq!(expected_vault, accounts[1].key(), ErrorCode::InvalidPortfolioAccounts);
+
+            if position.user != ctx.accounts.user.key() {
+                continue;
+            }
+
+            let value = if market.resolved {
+                claimable_amount(&market, &position, accounts[1].lamports())?
+            } else {
+                market.mark_to_market(position.yes_shares, position.no_shares)?
+            };
+            total = total.checked_add(value).ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        Ok(total)
+    }
+
+    pub fn get_amm_state(ctx: Context<GetAmmState>) -> Result<AmmState> {
+        let market = &ctx.accounts.market;
+
+        Ok(AmmState {
+            yes_liquidity: market.yes_liquidity,
+            no_liquidity: market.no_liquidity,
+            k_constant: market.k_constant,
+            invariant_ok: market.amm_invariant_holds(),
+        })
+    }
+
+    // Signed seconds until resolution_time by the cluster clock, negative once it has passed
+    pub fn get_resolution_countdown(ctx: Context<GetResolutionCountdown>) -> Result<i64> {
+        let now = unix_now(ctx.remaining_accounts)?;
+        ctx.accounts.market.seconds_to_resolution(now)
+    }
+
+    // Test builds only: pins the time unix_now reports to instructions that are passed the
+    // MockClock account. Zero goes back to the real clock
+    #[cfg(feature = "test-clock")]
+    pub fn set_mock_time(ctx: Context<SetMockTime>, unix_timestamp: i64) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+
+        let mock_clock = &mut ctx.accounts.mock_clock;
+        mock_clock.unix_timestamp = unix_timestamp;
+        mock_clock.bump = ctx.bumps.mock_clock;
+
+        msg!("Mock clock set to {}", unix_timestamp);
+        Ok(())
+    }
+
+    // Monitoring check that the vault still covers what the market owes. Reports rather
+    // than fails so a bot can read the shortfall
+    pub fn assert_solvency(ctx: Context<AssertSolvency>) -> Result<SolvencyReport> {
+        let distributable = ctx.accounts.vault.lamports().saturating_sub(vault_rent_floor()?);
+        ctx.accounts.market.solvency(distributable)
+    }
+
+    // The fee and net amount buy_shares would split `amount_lamports` into right now
+    pub fn get_fee_breakdown(ctx: Context<GetFeeBreakdown>, amount_lamports: u64) -> Result<FeeBreakdown> {
+        ctx.accounts.config.fee_breakdown(&ctx.accounts.market, amount_lamports)
+    }
+
+    // Bps the implied YES price would move if `amount_lamports` were bought on one side now
+    pub fn get_price_impact(
+        ctx: Context<GetPriceImpact>,
+        is_yes: bool,
+        amount_lamports: u64,
+    ) -> Result<u16> {
+        let market = &ctx.accounts.market;
+        let FeeBreakdown { amount_after_fee, .. } =
+            ctx.accounts.config.fee_breakdown(market, amount_lamports)?;
+        market.price_impact_bps(is_yes, amount_after_fee)
+    }
+
+    // Implied probability of each outcome in bps, YES first
+    pub fn get_outcome_prices(ctx: Context<GetOutcomePrices>) -> Result<Vec<u16>> {
+        Ok(ctx.accounts.market.outcome_prices_bps())
+    }
+
+    // Round-trip spread of each outcome at a `probe_lamports` trade size, YES first
+    pub fn get_outcome_spreads(ctx: Context<GetOutcomePrices>, probe_lamports: u64) -> Result<Vec<u16>> {
+        let market = &ctx.accounts.market;
+        let fee_bps = ctx.accounts.config.fee_bps_for(market);
+        Ok(vec![
+            market.round_trip_spread_bps(true, probe_lamports, fee_bps)?,
+            market.round_trip_spread_bps(false, probe_lamports, fee_bps)?,
+        ])
+    }
+
+    // Withdrawable protocol revenue in a fee bucket, net of the vault's rent floor
+    pub fn get_fee_balance(ctx: Context<GetFeeBalance>, _fee_bucket: u16) -> Result<u64> {
+        Ok(ctx.accounts.fee_vault.lamports().saturating_sub(vault_rent_floor()?))
+    }
+
+    // Fixes a typo in the question. Only until the first trade, so nobody has bet on
+    // wording that later changes
+    pub fn edit_question(ctx: Context<EditQuestion>, new_question: String) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.authority,
+            ErrorCode::Unauthorized
+        );
+        require!(new_question.len() <= 200, ErrorCode::QuestionTooLong);
+
+        let market = &ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+        require!(market.total_volume == 0, ErrorCode::QuestionLocked);
+
+        let metadata = &mut ctx.accounts.metadata;
+        msg!("Market #{} question changed from {:?} to {:?}", market.market_id, metadata.question, new_question);
+        metadata.question = new_question;
+        Ok(())
+    }
+
+    // Moves a miscategorized market. The market leaves its old index page and joins the
+    // new category's current one. The old category's market_count is also its page
+    // cursor, so it is not decremented; its page just holds one id fewer
+    pub fn update_category(ctx: Context<UpdateCategory>, new_category: String) -> Result<()> {
+        require!(
+            ctx.accounts.config.can_resolve(&ctx.accounts.authority.key()),
+            ErrorCode::Unauthorized
+        );
+        require!(new_category.len() <= 50, ErrorCode::CategoryTooLong);
+
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, ErrorCode::MarketResolved);
+        require!(!market.cancelled, ErrorCode::MarketCancelled);
+
+        let category_hash = hash(new_category.as_bytes()).to_bytes();
+        require!(category_hash != market.category_hash, ErrorCode::CategoryUnchanged);
+
+        let old_index = &mut ctx.accounts.old_category_index;
+        let slot = old_index.market_ids
+            .iter()
+            .position(|id| *id == market.market_id)
+            .ok_or(ErrorCode::MarketNotInCategoryPage)?;
+        old_index.market_ids.remove(slot);
+
+        add_to_category(
+            &ctx.accounts.config,
+            &mut ctx.accounts.new_category_stats,
+            ctx.bumps.new_category_stats,
+            &mut ctx.accounts.new_category_index,
+            ctx.bumps.new_category_index,
+            category_hash,
+            market.market_id,
+        )?;
+
+        let metadata = &mut ctx.accounts.metadata;
+        msg!(
+            "Market #{} moved from {} to {}",
+            market.market_id,
+            metadata.category,
+            new_category
+        );
+        market.category_hash = category_hash;
+        metadata.category = new_category;
+        Ok(())
+    }
+
+    pub fn get_market_summary(ctx: Context<GetMarketSummary>) -> Result<MarketSummary> {
+        let market = &ctx.accounts.market;
+        let metadata = &ctx.accounts.metadata;
+
+        Ok(MarketSummary {
+            market_id: market.market_id,
+            question_truncated: truncate_utf8(&metadata.question, SUMMARY_QUESTION_LEN),
+            category: metadata.category.clone(),
+            implied_yes_bps: market.implied_yes_bps(),
+            total_volume: market.total_volume,
+            resolved: market.resolved,
+            outcome: market.outcome,
+            resolution_time: market.resolution_time,
+            total_claimed: market.total_claimed,
+        })
+    }
+
+    // The audit log's entries, oldest first
+    pub fn get_audit_log(ctx: Context<GetAuditLog>) -> Result<Vec<AuditEntry>> {
+        Ok(ctx.accounts.audit_log.in_order())
+    }
+
+    // Protocol-level parameters, so clients need not decode the raw Config layout
+    pub fn get_config(ctx: Context<GetConfig>) -> Result<ConfigView> {
+        Ok(ctx.accounts.config.view())
+    }
+
+    // One page of a category's market ids, oldest first, and the page after it if any
+    pub fn get_category_page(ctx: Context<GetCategoryPage>, page: u64) -> Result<CategoryPage> {
+        let indexed = ctx.accounts.category_stats.market_count;
+        let next_page = page.checked_add(1).filter(|next| {
+            next.saturating_mul(CATEGORY_PAGE_SIZE) < indexed
+        });
+
+        Ok(CategoryPage {
+            page,
+            market_ids: ctx.accounts.category_index.market_ids.clone(),
+            next_page,
+        })
+    }
+
+    // Debugging aid: re-derives every PDA buy_shares expects and fails naming the
+    // first account that does not match, instead of a bare seeds violation mid-trade
+    pub fn verify_accounts(ctx: Context<VerifyAccounts>, fee_bucket: Option<u16>) -> Result<()> {
+        let accounts = &ctx.accounts;
+        let config = &accounts.config;
+        let market = &accounts.market;
+        let market_id = market.market_id.to_le_bytes();
+
+        let config_pda = Pubkey::create_program_address(&[b"config", &[config.bump]], &crate::ID)
+            .map_err(|_| error!(ErrorCode::MisderivedConfig))?;
+        require_keys_eq!(config.key(), config_pda, ErrorCode::MisderivedConfig);
+
+        let market_pda =
+            Pubkey::create_program_address(&[MARKET_SEED, &market_id, &[market.bump]], &crate::ID)
+                .map_err(|_| error!(ErrorCode::MisderivedMarket))?;
+        require_keys_eq!(market.key(), market_pda, ErrorCode::MisderivedMarket);
+
+        let vault_pda =
+            Pubkey::create_program_address(&[VAULT_SEED, &market_id, &[market.vault_bump]], &crate::ID)
+                .map_err(|_| error!(ErrorCode::MisderivedVault))?;
+        require_keys_eq!(accounts.vault.key(), vault_pda, ErrorCode::MisderivedVault);
+
+        require_keys_eq!(
+            accounts.fee_vault.key(),
+            fee_vault_address(config, fee_bucket.unwrap_or(0))?,
+            ErrorCode::InvalidFeeVault
+        );
+
+        let (category_stats_pda, _) = Pubkey::find_program_address(
+            &[CATEGORY_STATS_SEED, market.category_hash.as_ref()],
+            &crate::ID,
+        );
+        require_keys_eq!(
+            accounts.category_stats.key(),
+            category_stats_pda,
+            ErrorCode::MisderivedCategoryStats
+        );
+
+        let (position_pda, _) = Pubkey::find_program_address(
+            &[USER_POSITION_SEED, accounts.user.key().as_ref(), &market_id],
+            &crate::ID,
+        );
+        require_keys_eq!(
+            accounts.user_position.key(),
+            position_pda,
+            ErrorCode::MisderivedUserPosition
+        );
+
+        msg!("All buy_shares accounts for market #{} derive correctly", market.market_id);
+        Ok(())
+    }
+}
+
+// What claim_winnings would pay this position right now, 0 if nothing is claimable
+fn claimable_amount(market: &Market, position: &UserPosition, vault_lamports: u64) -> Result<u64> {
+    if !market.resolved || position.claimed {
+        return Ok(0);
+    }
+
+    let winning_shares = position.winning_shares(market)?;
+    if winning_shares == 0 {
+        return Ok(0);
+    }
+
+    let distributable = vault_lamports.saturating_sub(vault_rent_floor()?);
+    market.payout_for_shares(winning_shares, distributable)
+}
+
+struct BuyReceipt {
+    amount_after_fee: u64,
+    shares_out: u64,
+    share_scale: u64,
+}
+
+impl BuyReceipt {
+    // Average price per share as a probability in bps, rounded up against the buyer
+    fn avg_price_bps(&self) -> Result<u64> {
+        if self.shares_out == 0 {
+            return Ok(u64::MAX);
+        }
+        let avg = (self.amount_after_fee as u128)
+            .checked_mul(BPS_DENOMINATOR as u128 * self.share_scale as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .div_ceil(self.shares_out as u128);
+        Ok(u64::try_from(avg).unwrap_or(u64::MAX))
+    }
+}
+
+// Checks shared by binary and scalar resolution
+fn check_resolvable(accounts: &ResolveMarket, now: i64) -> Result<()> {
+    require!(
+        accounts.config.can_resolve(&accounts.authority.key()),
+        ErrorCode::Unauthorized
+    );
+
+    let market = &accounts.market;
+
+    require!(!market.resolved, ErrorCode::MarketResolved);
+    require!(!market.cancelled, ErrorCode::MarketCancelled);
+    require!(
+        market.resolution_reached(now),
+        ErrorCode::MarketNotExpired
+    );
+    // A market nobody traded has nothing to settle; resolving it only locks the seed liquidity
+    if accounts.config.require_trades_to_resolve {
+        require!(
+            market.total_yes_shares > 0 || market.total_no_shares > 0,
+            ErrorCode::NoTradesToResolve
+        );
+    }
+    Ok(())
+}
+
+// Shared gate for oracle-driven resolution: the feed must have published at most
+// `max_age` seconds before `now`, and not after it, and its confidence interval must
+// be at most `max_conf_bps` of the price. Returns the validated price. No instruction
+// reads an oracle yet; resolution paths that do should route their feed through this.
+pub fn validate_oracle_price(
+    price: i64,
+    publish_time: i64,
+    conf: u64,
+    max_age: i64,
+    max_conf_bps: u16,
+    now: i64,
+) -> Result<u64> {
+    let age = now.checked_sub(publish_time).ok_or(ErrorCode::TimeOverflow)?;
+    require!(age >= 0, ErrorCode::OracleFromFuture);
+    require!(age <= max_age, ErrorCode::StaleOracle);
+    require!(price > 0, ErrorCode::UnreliableOracle);
+
+    let price = price as u64;
+    let max_conf = price as u128 * max_conf_bps as u128 / BPS_DENOMINATOR as u128;
+    require!(conf as u128 <= max_conf, ErrorCode::UnreliableOracle);
+
+    Ok(price)
+}
+
+fn process_resolve(
+    accounts: &mut ResolveMarket,
+    outcome_yes: bool,
+    evidence_hash: [u8; 32],
+    evidence_uri: String,
+    source: ResolutionSource,
+    audit_bump: u8,
+    now: i64,
+) -> Result<()> {
+    check_resolvable(accounts, now)?;
+    require!(evidence_uri.len() <= EVIDENCE_URI_LEN, ErrorCode::EvidenceUriTooLong);
+
+    let market = &mut accounts.market;
+    require!(market.scalar_bounds().is_none(), ErrorCode::ScalarMarket);
+
+    market.resolved = true;
+    market.resolved_at = now;
+    market.outcome = Some(outcome_yes);
+    market.evidence_hash = evidence_hash;
+    market.evidence_uri = evidence_uri;
+    market.resolution_source = source;
+    market.claim_deadline = claim_deadline(now, accounts.config.claim_window)?;
+
+    accounts.audit_log.record(audit_bump, AuditEntry {
+        action: if outcome_yes { AuditAction::ResolvedYes } else { AuditAction::ResolvedNo },
+        actor: accounts.authority.key(),
+        market_id: market.market_id,
+        amount: 0,
+        timestamp: now,
+    });
+
+    emit!(MarketResolvedEvent {
+        version: MarketResolvedEvent::VERSION,
+        market_pubkey: market.key(),
+        market_id: market.market_id,
+        outcome: outcome_yes,
+        evidence_hash: market.evidence_hash,
+        evidence_uri: market.evidence_uri.clone(),
+        resolution_source: source,
+        timestamp: now,
+    });
+
+    msg!(
+        "Market #{} resolved - Outcome: {}",
+        market.market_id,
+        if outcome_yes { "YES" } else { "NO" }
+    );
+
+    Ok(())
+}
+
+// Instruction a resolution callback program exposes, named the way Anchor derives
+// discriminators so the callback can be an ordinary Anchor program
+const RESOLUTION_CALLBACK_IX: &[u8] = b"global:on_market_resolved";
+
+// CPIs the market's resolution callback, passing the market read-only and
+// (market_id, outcome_yes) as instruction data. A failing callback still aborts the
+// whole transaction, so only a callback missing from the remaining accounts can be
+// skipped, and only when callback_required is off
+fn notify_resolution_callback<'info>(
+    market: &mut Account<'info, Market>,
+    remaining_accounts: &[AccountInfo<'info>],
+    outcome_yes: bool,
+) -> Result<()> {
+    let Some(callback) = market.resolution_callback else {
+        return Ok(());
+    };
+    let Some(program) = remaining_accounts
+        .iter()
+        .find(|info| info.key() == callback && info.executable)
+    else {
+        if market.callback_required {
+            msg!("Market #{} needs its resolution callback {} passed in", market.market_id, callback);
+            return err!(ErrorCode::ResolutionCallbackMissing);
+        }
+        msg!("Resolution callback {} not passed in, market #{} resolved without it", callback, market.market_id);
+        return Ok(());
+    };
+
+    // Write the resolved state out first so the callback reads it from the account
+    market.exit(&crate::ID)?;
+
+    let mut data = hash(RESOLUTION_CALLBACK_IX).to_bytes()[..8].to_vec();
+    data.extend_from_slice(&market.market_id.to_le_bytes());
+    data.push(outcome_yes as u8);
+    let callback_ix = anchor_lang::solana_program::instruction::Instruction {
+        program_id: callback,
+        accounts: vec![AccountMeta::new_readonly(market.key(), false)],
+        data,
+    };
+    anchor_lang::solana_program::program::invoke(&callback_ix, &[market.to_account_info(), program.clone()])?;
+
+    msg!("Market #{} notified resolution callback {}", market.market_id, callback);
+    Ok(())
+}
+
+// Where a buy's lamports come from: the signing wallet, or a program-owned escrow
+enum BuyFunding<'a, 'info> {
+    Wallet(&'a AccountInfo<'info>),
+    Escrow(&'a AccountInfo<'info>),
+}
+
+impl<'info> BuyFunding<'_, 'info> {
+    fn pay(&self, to: &AccountInfo<'info>, system_program: &AccountInfo<'info>, amount: u64) -> Result<()> {
+        match self {
+            BuyFunding::Wallet(from) => {
+                let cpi_context = CpiContext::new(
+                    system_program.clone(),
+                    system_program::Transfer {
+                        from: (*from).clone(),
+                        to: to.clone(),
+                    },
+                );
+                system_program::transfer(cpi_context, amount)
+            }
+            // The escrow is owned by this program, so it is debited directly
+            BuyFunding::Escrow(escrow) => {
+                **escrow.try_borrow_mut_lamports()? -= amount;
+                **to.try_borrow_mut_lamports()? += amount;
+                Ok(())
+            }
+        }
+    }
+}
+
+// The accounts process_buy touches, shared by the wallet and delegated buy paths
+struct BuyAccounts<'a, 'info> {
+    config: &'a Account<'info, Config>,
+    market: &'a mut Account<'info, Market>,
+    vault: &'a AccountInfo<'info>,
+    fee_vault: &'a AccountInfo<'info>,
+    category_stats: &'a mut Account<'info, CategoryStats>,
+    user_position: &'a mut Account<'info, UserPosition>,
+    user: Pubkey,
+    // Extra signer offered for the position's cosign check
+    cosigner: Option<Pubkey>,
+    // The buyer's entry, for markets behind an access gate
+    whitelist_entry: Option<&'a WhitelistEntry>,
+    insurance_vault: Option<&'a AccountInfo<'info>>,
+    now: i64,
+    funding: BuyFunding<'a, 'info>,
+    system_program: &'a AccountInfo<'info>,
+}
+
+// Core of buy_shares: prices the trade, moves the buyer's lamports and credits the position
+fn process_buy(
+    accounts: BuyAccounts,
+    is_yes: bool,
+    amount_lamports: u64,
+    min_shares_out: u64,
+    fee_bucket: u16,
+) -> Result<BuyReceipt> {
+    require_keys_eq!(
+        accounts.fee_vault.key(),
+        fee_vault_address(accounts.config, fee_bucket)?,
+        ErrorCode::InvalidFeeVault
+    );
+
+    let market = &mut *accounts.market;
+
+    require!(!market.resolved, ErrorCode::MarketResolved);
+    require!(!market.cancelled, ErrorCode::MarketCancelled);
+    require!(!market.trading_closed, ErrorCode::TradingClosed);
+    require!(
+        accounts.config.trading_open(market.resolution_time, accounts.now)?,
+        ErrorCode::MarketExpired
+    );
+    require!(amount_lamports > 0, ErrorCode::InvalidAmount);
+    if market.max_total_volume > 0 {
+        let volume_after = market.total_volume
+            .checked_add(amount_lamports)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            volume_after <= market.max_total_volume,
+            ErrorCode::MarketVolumeCapReached
+        );
+    }
+
+    market.require_access(accounts.whitelist_entry, &accounts.user)?;
+    accounts.user_position.require_cosigner(accounts.cosigner, amount_lamports)?;
+    accounts.user_position.record_trade(market, accounts.now)?;
+
+    // initialize() and init_fee_bucket() seed fee vaults with rent; an empty one was never set up
+    require!(
+        accounts.fee_vault.lamports() > 0,
+        ErrorCode::FeeVaultNotInitialized
+    );
+
+    let fee_bps = accounts.config.fee_bps_for(market);
+    // The protocol's own trades would only pay the fee to itself
+    let house_trade = match accounts.funding {
+        BuyFunding::Wallet(payer) => accounts.config.fee_exempt(payer.key),
+        BuyFunding::Escrow(_) => false,
+    };
+    let FeeBreakdown { fee, amount_after_fee } = if house_trade {
+        FeeBreakdown { fee: 0, amount_after_fee: amount_lamports }
+    } else {
+        accounts.config.fee_breakdown(market, amount_lamports)?
+    };
+
+    // High-precision AMM calculation
+    let (shares_out, new_yes_liquidity, new_no_liquidity) = if market.market_type == MarketType::Lmsr {
+        let (bought, other) = if is_yes {
+            (market.total_yes_shares, market.total_no_shares)
+        } else {
+            (market.total_no_shares, market.total_yes_shares)
+        };
+        let shares = amm::lmsr_shares_for_cost(market.initial_liquidity, bought, other, amount_after_fee)?;
+        (shares, market.yes_liquidity, market.no_liquidity)
+    } else {
+        let (reserve_in, reserve_out) = if is_yes {
+            (market.yes_liquidity, market.no_liquidity)
+        } else {
+            (market.no_liquidity, market.yes_liquidity)
+        };
+
+        let (shares, new_in, new_out) =
+            amm::swap(reserve_in, reserve_out, market.k_constant, amount_after_fee, market.precision())?;
+
+        // Near zero the price degenerates, so keep a floor under the reserve being bought out
+        if new_out < MIN_RESERVE {
+            msg!(
+                "Trade exceeds liquidity: at most {} lamports can be spent on this side",
+                market.max_buy_lamports(is_yes, fee_bps).unwrap_or(0)
+            );
+            return err!(ErrorCode::TradeExceedsLiquidity);
+        }
+
+        if is_yes {
+            (shares, new_in, new_out)
+        } else {
+            (shares, new_out, new_in)
+        }
+    };
+
+    // The pool trades in lamport-scale units; positions hold them at the market's share scale
+    let shares_out = shares_out
+        .checked_mul(market.share_scale())
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Rounding can eat a tiny buy whole; revert rather than take lamports for nothing
+    require!(shares_out > 0, ErrorCode::ZeroSharesOut);
+    if shares_out < min_shares_out {
+        msg!(
+            "Slippage: buy returns {} shares, minimum was {} (short by {})",
+            shares_out,
+            min_shares_out,
+            min_shares_out - shares_out
+        );
+        return err!(ErrorCode::SlippageExceeded);
+    }
+
+    // Circuit breaker and creator slippage limit: one buy may not move the implied YES
+    // price further than either allows
+    if market.max_price_move_bps > 0 || market.max_slippage_bps > 0 {
+        let mut after = market.clone();
+        after.yes_liquidity = new_yes_liquidity;
+        after.no_liquidity = new_no_liquidity;
+        if is_yes {
+            after.total_yes_shares = after.total_yes_shares.saturating_add(shares_out as u128);
+        } else {
+            after.total_no_shares = after.total_no_shares.saturating_add(shares_out as u128);
+        }
+        let moved = after.implied_yes_bps().abs_diff(market.implied_yes_bps());
+        if market.max_slippage_bps > 0 && moved > market.max_slippage_bps {
+            msg!(
+                "Trade would move the price {} bps (market limit {})",
+                moved,
+                market.max_slippage_bps
+            );
+            return err!(ErrorCode::MarketSlippageExceeded);
+        }
+        if market.max_price_move_bps > 0 && moved > market.max_price_move_bps {
+            msg!(
+                "Trade would move the price {} bps (limit {}); split it into smaller orders",
+                moved,
+                market.max_price_move_bps
+            );
+            return err!(ErrorCode::CircuitBreakerTripped);
+        }
+    }
+
+    // Send fees to protocol fee vault, less the insurance slice
+    let (protocol_fee, insurance_fee) =
+        split_trade_fee(accounts.config, fee, accounts.insurance_vault.is_some())?;
+    accounts.funding.pay(accounts.fee_vault, accounts.system_program, protocol_fee)?;
+    if let Some(insurance_vault) = accounts.insurance_vault {
+        accounts.funding.pay(insurance_vault, accounts.system_program, insurance_fee)?;
+    }
+
+    // Transfer net amount to market vault
+    accounts.funding.pay(accounts.vault, accounts.system_program, amount_after_fee)?;
+
+    market.yes_liquidity = new_yes_liquidity;
+    market.no_liquidity = new_no_liquidity;
+    market.require_min_k()?;
+    market.total_volume += amount_lamports;
+    market.trade_sequence += 1;
+    accounts.category_stats.record_volume(amount_lamports)?;
+
+    let position = &mut *accounts.user_position;
+    if is_yes {
+        position.yes_shares = position.yes_shares
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        position.no_shares = position.no_shares
+            .checked_add(shares_out)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    position.lamports_contributed = position.lamports_contributed
+        .checked_add(amount_after_fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+    market.total_contributed = market.total_contributed
+        .checked_add(amount_after_fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    if is_yes {
+        market.total_yes_shares = market.total_yes_shares
+            .checked_add(shares_out as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        market.total_no_shares = market.total_no_shares
+            .checked_add(shares_out as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    emit!(BuySharesEvent {
+        version: BuySharesEvent::VERSION,
+        market_pubkey: market.key(),
+        market_id: market.market_id,
+        user: accounts.user,
+        is_yes,
+        shares: shares_out,
+        yes_liquidity: market.yes_liquidity,
+        no_liquidity: market.no_liquidity,
+        sequence: market.trade_sequence,
+        timestamp: accounts.now,
+    });
+
+    msg!(
+        "User {} bought {} {} shares for {} lamports (fee: {})",
+        accounts.user,
+        shares_out,
+        if is_yes { "YES" } else { "NO" },
+        amount_lamports,
+        fee
+    );
+
+    Ok(BuyReceipt {
+        amount_after_fee,
+        shares_out,
+        share_scale: market.share_scale(),
+    })
+}
+
+// Runs the inverse AMM trade for selling `shares` back to the pool and updates
+// market/position state. Returns (fee, lamports owed to the seller).
+fn execute_sell(
+    market: &mut Market,
+    position: &mut UserPosition,
+    config: &Config,
+    is_yes: bool,
+    shares: u64,
+    now: i64,
+) -> Result<(u64, u64)> {
+    require!(!market.resolved, ErrorCode::MarketResolved);
+    require!(!market.cancelled, ErrorCode::MarketCancelled);
+    require!(!market.trading_closed, ErrorCode::TradingClosed);
+    require!(
+        config.trading_open(market.resolution_time, now)?,
+        ErrorCode::MarketExpired
+    );
+    require!(shares > 0, ErrorCode::InvalidAmount);
+
+    let held = if is_yes { position.yes_shares } else { position.no_shares };
+    require!(shares <= held, ErrorCode::InsufficientShares);
+
+    let released = position.release_contribution(shares)?;
+    market.total_contributed = market.total_contributed.saturating_sub(released);
+
+    position.record_trade(market, now)?;
+    market.trade_sequence += 1;
+
+    if market.market_type == MarketType::Lmsr {
+        let (q_yes, q_no) = (market.total_yes_shares, market.total_no_shares);
+        let outstanding = if is_yes { q_yes } else { q_no };
+        if (shares as u128) > outstanding {
+            msg!("Pool has {} shares outstanding, sell needs {}", outstanding, shares);
+            return err!(ErrorCode::InsufficientLiquidity);
+        }
+        let (after_yes, after_no) = if is_yes {
+            (q_yes.checked_sub(shares as u128), Some(q_no))
+        } else {
+            (Some(q_yes), q_no.checked_sub(shares as u128))
+        };
+        let b = market.initial_liquidity;
+        let gross_out = amm::lmsr_cost(b, q_yes, q_no)?.saturating_sub(amm::lmsr_cost(
+            b,
+            after_yes.ok_or(ErrorCode::InsufficientLiquidity)?,
+            after_no.ok_or(ErrorCode::InsufficientLiquidity)?,
+        )?);
+        let gross_out = u64::try_from(gross_out).map_err(|_| error!(ErrorCode::MathOverflow))?;
+        let fee = gross_out
+            .checked_mul(config.fee_bps_for(market))
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(BPS_DENOMINATOR)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let amount_out = gross_out.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+        require!(amount_out > 0, ErrorCode::InvalidAmount);
+
+        if is_yes {
+            position.yes_shares -= shares;
+            market.total_yes_shares = q_yes - shares as u128;
+        } else {
+            position.no_shares -= shares;
+            market.total_no_shares = q_no - shares as u128;
+        }
+        market.total_volume += gross_out;
+        return Ok((fee, amount_out));
+    }
+
+    // Shares return to the opposite reserve, lamports come out of the same-side reserve
+    let (reserve_in, reserve_out) = if is_yes {
+        (market.no_liquidity, market.yes_liquidity)
+    } else {
+        (market.yes_liquidity, market.no_liquidity)
+    };
+
+    // A fraction of a pool unit left over at a finer share scale is sold for nothing
+    let pool_units = shares / market.share_scale();
+    require!(pool_units > 0, ErrorCode::InvalidAmount);
+    let (gross_out, new_in, new_out) =
+        amm::swap(reserve_in, reserve_out, market.k_constant, pool_units, market.precision())?;
+
+    let fee = gross_out
+        .checked_mul(config.fee_bps_for(market))
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(BPS_DENOMINATOR)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let amount_out = gross_out.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+
+    require!(amount_out > 0, ErrorCode::InvalidAmount);
+
+    if is_yes {
+        market.no_liquidity = new_in;
+        market.yes_liquidity = new_out;
+        position.yes_shares -= shares;
+        market.total_yes_shares = market.total_yes_shares
+            .checked_sub(shares as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        market.yes_liquidity = new_in;
+        market.no_liquidity = new_out;
+        position.no_shares -= shares;
+        market.total_no_shares = market.total_no_shares
+            .checked_sub(shares as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    market.require_min_k()?;
+    market.total_volume += gross_out;
+
+    Ok((fee, amount_out))
+}
+
+// Pays out `shares` winning shares at the market's payout-per-share snapshot and
+// removes them from the market totals. The caller updates the position itself.
+fn settle_winnings<'info>(
+    market: &mut Market,
+    vault: &AccountInfo<'info>,
+    user: &AccountInfo<'info>,
+    insurance_vault: Option<&AccountInfo<'info>>,
+    system_program: &AccountInfo<'info>,
+    yes_shares: u64,
+    no_shares: u64,
+) -> Result<u64> {
+    let (yes_weight, no_weight) = market.payout_weights()?;
+    let shares = market.weighted_shares(yes_shares as u128, no_shares as u128)?;
+    let shares = u64::try_from(shares).map_err(|_| error!(ErrorCode::MathOverflow))?;
+    // The vault keeps its rent-exempt floor so rounding dust never strands it below rent
+    let distributable = vault.lamports().saturating_sub(vault_rent_floor()?);
+    market.snapshot_payout_per_share(distributable)?;
+    let owed = market.owed_for_shares(shares, distributable)?;
+    let mut payout = owed.min(distributable);
+
+    // Dust positions whose share truncates to zero are still settled, just for nothing
+    if payout > 0 {
+        vault_transfer(market, vault, user, system_program, payout)?;
+    }
+
+    // Insurance makes up what the vault could not, as far as it goes
+    if let Some(insurance_vault) = insurance_vault {
+        let drawn = insurance_draw(owed, payout, escrow_available(insurance_vault)?);
+        if drawn > 0 {
+            **insurance_vault.try_borrow_mut_lamports()? -= drawn;
+            **user.try_borrow_mut_lamports()? += drawn;
+            msg!("Vault was {} lamports short; {} drawn from insurance", owed - payout, drawn);
+            payout += drawn;
+        }
+    }
+    market.total_claimed = market.total_claimed
+        .checked_add(payout)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Only sides that pay out count toward the remaining winning shares
+    if yes_weight > 0 {
+        market.total_yes_shares = market.total_yes_shares
+            .checked_sub(yes_shares as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+    if no_weight > 0 {
+        market.total_no_shares = market.total_no_shares
+            .checked_sub(no_shares as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+    }
+
+    Ok(payout)
+}
+
+// Settles every share a position holds and marks it claimed
+fn settle_position<'info>(
+    market: &mut Market,
+    position: &mut UserPosition,
+    vault: &AccountInfo<'info>,
+    user: &AccountInfo<'info>,
+    insurance_vault: Option<&AccountInfo<'info>>,
+    system_program: &AccountInfo<'info>,
+) -> Result<u64> {
+    let payout = settle_winnings(
+        market,
+        vault,
+        user,
+        insurance_vault,
+        system_program,
+        position.yes_shares,
+        position.no_shares,
+    )?;
+    position.yes_shares = 0;
+    position.no_shares = 0;
+    position.claimed = true;
+    Ok(payout)
+}
+
+// Counts a market into a category and appends it to the category's current index page
+fn add_to_category(
+    config: &Config,
+    category_stats: &mut CategoryStats,
+    category_stats_bump: u8,
+    category_index: &mut CategoryIndex,
+    category_index_bump: u8,
+    category_hash: [u8; 32],
+    market_id: u64,
+) -> Result<()> {
+    if category_stats.category_hash != category_hash {
+        category_stats.category_hash = category_hash;
+        category_stats.bump = category_stats_bump;
+    }
+    let max_markets = config.max_markets_per_category;
+    require!(
+        max_markets == 0 || category_stats.market_count < max_markets,
+        ErrorCode::CategoryFull
+    );
+
+    // Markets fill pages in creation order; the page comes from the count before this one
+    if category_index.market_ids.is_empty() {
+        category_index.category_hash = category_hash;
+        category_index.page = category_stats.market_count / CATEGORY_PAGE_SIZE;
+        category_index.bump = category_index_bump;
+    }
+    category_index.market_ids.push(market_id);
+    category_stats.market_count += 1;
+    Ok(())
+}
+
+// Files a migrated market that predates category tracking under its metadata's category
+fn file_under_category(
+    config: &Config,
+    market: &mut Market,
+    category: &str,
+    category_stats: &mut CategoryStats,
+    category_stats_bump: u8,
+    category_index: &mut CategoryIndex,
+    category_index_bump: u8,
+) -> Result<()> {
+    let category_hash = hash(category.as_bytes()).to_bytes();
+    add_to_category(
+        config,
+        category_stats,
+        category_stats_bump,
+        category_index,
+        category_index_bump,
+        category_hash,
+        market.market_id,
+    )?;
+    market.category_hash = category_hash;
+    Ok(())
+}
+
+// Checks create_market applies to a new market's parameters and returns the AMM
+// precision it will use
+fn validate_market_params(
+    config: &Config,
+    params: &MarketParams,
+    house_shares: u64,
+    now: i64,
+) -> Result<u64> {
+    require!(params.question.len() <= 200, ErrorCode::QuestionTooLong);
+    require!(params.description.len() <= 1000, ErrorCode::DescriptionTooLong);
+    require!(params.category.len() <= 50, ErrorCode::CategoryTooLong);
+    let resolution_time = params.resolution_time;
+    require!(
+        resolution_time > now,
+        ErrorCode::InvalidResolutionTime
+    );
+    let earliest_resolution = now
+        .checked_add(config.min_market_duration)
+        .ok_or(ErrorCode::TimeOverflow)?;
+    require!(
+        resolution_time >= earliest_resolution,
+        ErrorCode::MarketDurationTooShort
+    );
+    // Zero leaves the horizon uncapped
+    let max_duration = config.max_market_duration;
+    if max_duration > 0 {
+        let latest_resolution = now
+            .checked_add(max_duration)
+            .ok_or(ErrorCode::TimeOverflow)?;
+        require!(
+            resolution_time <= latest_resolution,
+            ErrorCode::ResolutionTimeTooFar
+        );
+    }
+    require!(
+        params.initial_liquidity_lamports >= 10_000_000,
+        ErrorCode::InsufficientInitialLiquidity
+    );
+    // Refuse a resolution_time whose cutoff or refund deadline cannot be represented
+    config.trading_cutoff(resolution_time)?;
+    refund_deadline(resolution_time, config.resolve_grace_period)?;
+    if let MarketType::Scalar { lower_bound, upper_bound } = params.market_type {
+        require!(lower_bound < upper_bound, ErrorCode::InvalidScalarBounds);
+    }
+    // Zero keeps the default PRECISION
+    let amm_precision = if params.amm_precision == 0 { PRECISION as u64 } else { params.amm_precision };
+    require!(
+        (MIN_AMM_PRECISION..=MAX_AMM_PRECISION).contains(&amm_precision),
+        ErrorCode::InvalidAmmPrecision
+    );
+    // House shares are backed by the seed deposit, one of each side per lamport
+    require!(
+        house_shares <= params.initial_liquidity_lamports,
+        ErrorCode::InvalidHouseShares
+    );
+    Ok(amm_precision)
+}
+
+// Moves the creator's seed into a new market's vault. Callers run it after every check
+// and state write, so any failure reverts the whole transaction, account inits included
+fn fund_market_vault<'info>(
+    authority: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    deposit: u64,
+) -> Result<()> {
+    let balance = authority.lamports();
+    if balance < deposit {
+        msg!("Creator holds {} lamports but the deposit needs {}", balance, deposit);
+        return err!(ErrorCode::InsufficientFunds);
+    }
+    let vault_before = vault.lamports();
+
+    let cpi_context = CpiContext::new(
+        system_program.clone(),
+        system_program::Transfer {
+            from: authority.clone(),
+            to: vault.clone(),
+        },
+    );
+    system_program::transfer(cpi_context, deposit)?;
+
+    // The market only goes live once the vault holds the full seed
+    require!(
+        vault.lamports() == vault_before.checked_add(deposit).ok_or(ErrorCode::MathOverflow)?,
+        ErrorCode::VaultUnderfunded
+    );
+    Ok(())
+}
+
+fn emit_market_created(market_pubkey: Pubkey, market: &Market, metadata: &MarketMetadata) {
+    emit!(MarketCreatedEvent {
+        version: MarketCreatedEvent::VERSION,
+        market_pubkey,
+        market_id: market.market_id,
+        authority: market.authority,
+        category: metadata.category.clone(),
+        resolution_time: market.resolution_time,
+        initial_liquidity: market.initial_liquidity,
+        created_at: market.created_at,
+    });
+}
+
+// Bump of a caller-supplied account, which must sit at the PDA for `seeds`
+fn supplied_pda_bump(info: &AccountInfo, seeds: &[&[u8]], mismatch: ErrorCode) -> Result<u8> {
+    let (expected, bump) = Pubkey::find_program_address(seeds, &crate::ID);
+    require_keys_eq!(info.key(), expected, mismatch);
+    Ok(bump)
+}
+
+fn batch_pda_bump(info: &AccountInfo, seeds: &[&[u8]]) -> Result<u8> {
+    supplied_pda_bump(info, seeds, ErrorCode::BatchAccountMismatch)
+}
+
+// Allocates a program-owned PDA as Anchor's `init` would, including at an address
+// someone has already sent lamports to
+fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    target: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space);
+    let signer = &[seeds];
+    if target.lamports() == 0 {
+        system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::CreateAccount { from: payer.clone(), to: target.clone() },
+                signer,
+            ),
+            rent,
+            space as u64,
+            &crate::ID,
+        )?;
+        return Ok(());
+    }
+
+    let top_up = rent.saturating_sub(target.lamports());
+    if top_up > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer { from: payer.clone(), to: target.clone() },
+            ),
+            top_up,
+        )?;
+    }
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Allocate { account_to_allocate: target.clone() },
+            signer,
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            system_program::Assign { account_to_assign: target.clone() },
+            signer,
+        ),
+        &crate::ID,
+    )?;
+    Ok(())
+}
+
+// Closes a program-owned account as Anchor's `close` would, lamports to `destination`
+fn close_pda_account<'info>(target: &AccountInfo<'info>, destination: &AccountInfo<'info>) -> Result<()> {
+    let lamports = target.lamports();
+    **destination.try_borrow_mut_lamports()? =
+        destination.lamports().checked_add(lamports).ok_or(ErrorCode::MathOverflow)?;
+    **target.try_borrow_mut_lamports()? = 0;
+    target.assign(&system_program::ID);
+    target.resize(0)?;
+    Ok(())
+}
+
+// The batch counterpart of `init_if_needed`: decodes the account, first creating it
+// zeroed if the program does not own it yet
+fn load_or_create_pda<'info, T: AccountDeserialize>(
+    payer: &AccountInfo<'info>,
+    target: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> Result<T> {
+    if target.owner == &crate::ID {
+        return T::try_deserialize(&mut &target.try_borrow_data()?[..]);
+    }
+    create_pda_account(payer, target, system_program, space, seeds)?;
+    T::try_deserialize_unchecked(&mut &target.try_borrow_data()?[..])
+}
+
+// Writes an account, discriminator included, back to a batch-supplied AccountInfo
+fn store_account<T: AccountSerialize>(account: &T, info: &AccountInfo) -> Result<()> {
+    let mut data = info.try_borrow_mut_data()?;
+    let mut writer: &mut [u8] = &mut data;
+    account.try_serialize(&mut writer)
+}
+
+// Claim deadline for a market resolved at `resolved_at`, zero when claims never expire
+fn claim_deadline(resolved_at: i64, claim_window: i64) -> Result<i64> {
+    if claim_window == 0 {
+        return Ok(0);
+    }
+    Ok(resolved_at
+        .checked_add(claim_window)
+        .ok_or(ErrorCode::TimeOverflow)?)
+}
+
+// When enable_refunds becomes callable for a market resolving at `resolution_time`
+fn refund_deadline(resolution_time: i64, resolve_grace_period: i64) -> Result<i64> {
+    Ok(resolution_time
+        .checked_add(resolve_grace_period)
+        .ok_or(ErrorCode::TimeOverflow)?)
+}
+
+// Lamports insurance pays toward a claim that was owed `owed` but only got `paid`
+fn insurance_draw(owed: u64, paid: u64, insurance_available: u64) -> u64 {
+    owed.saturating_sub(paid).min(insurance_available)
+}
+
+// Splits a trade fee into (fee vault, insurance vault) slices. A non-zero insurance
+// slice needs the market's insurance vault, so no client can route around it
+fn split_trade_fee(config: &Config, fee: u64, has_insurance_vault: bool) -> Result<(u64, u64)> {
+    let insurance = (fee as u128)
+        .checked_mul(config.insurance_fee_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / BPS_DENOMINATOR as u128;
+    let insurance = u64::try_from(insurance).map_err(|_| error!(ErrorCode::MathOverflow))?;
+    require!(
+        insurance == 0 || has_insurance_vault,
+        ErrorCode::InsuranceVaultRequired
+    );
+    Ok((fee - insurance, insurance))
+}
+
+// Pays a sell's fee out of the market vault, split as split_trade_fee decides
+fn pay_fee_from_vault<'info>(
+    market: &Market,
+    config: &Config,
+    vault: &AccountInfo<'info>,
+    fee_vault: &AccountInfo<'info>,
+    insurance_vault: Option<&AccountInfo<'info>>,
+    system_program: &AccountInfo<'info>,
+    fee: u64,
+) -> Result<()> {
+    let (protocol_fee, insurance_fee) = split_trade_fee(config, fee, insurance_vault.is_some())?;
+    vault_transfer(market, vault, fee_vault, system_program, protocol_fee)?;
+    if let Some(insurance_vault) = insurance_vault {
+        vault_transfer(market, vault, insurance_vault, system_program, insurance_fee)?;
+    }
+    Ok(())
+}
+
+// Extra seed for a fee bucket's vault. Bucket 0 adds nothing, so it is the original
+// [FEE_VAULT_SEED] vault.
+fn fee_bucket_seed(fee_bucket: u16) -> Vec<u8> {
+    if fee_bucket == 0 {
+        Vec::new()
+    } else {
+        fee_bucket.to_le_bytes().to_vec()
+    }
+}
+
+fn fee_vault_address(config: &Config, fee_bucket: u16) -> Result<Pubkey> {
+    if fee_bucket == 0 {
+        return Pubkey::create_program_address(&[FEE_VAULT_SEED, &[config.fee_vault_bump]], &crate::ID)
+            .map_err(|_| error!(ErrorCode::InvalidFeeVault));
+    }
+    let seed = fee_bucket_seed(fee_bucket);
+    Ok(Pubkey::find_program_address(&[FEE_VAULT_SEED, &seed], &crate::ID).0)
+}
+
+// Signed transfer out of a fee bucket's vault
+fn fee_vault_transfer<'info>(
+    fee_vault: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    fee_bucket: u16,
+    bump: u8,
+    amount: u64,
+) -> Result<()> {
+    let bucket_seed = fee_bucket_seed(fee_bucket);
+    let seeds = &[FEE_VAULT_SEED, bucket_seed.as_slice(), &[bump]];
+    let signer = &[&seeds[..]];
+
+    let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+        fee_vault.key,
+        to.key,
+        amount,
+    );
+
+    anchor_lang::solana_program::program::invoke_signed(
+        &transfer_ix,
+        &[fee_vault.clone(), to.clone(), system_program.clone()],
+        signer,
+    )?;
+    Ok(())
+}
+
+// Escrow lamports above its rent-exempt minimum
+fn escrow_available(escrow: &AccountInfo) -> Result<u64> {
+    let rent_floor = Rent::get()?.minimum_balance(escrow.data_len());
+    Ok(escrow.lamports().saturating_sub(rent_floor))
+}
+
+// Unix time behind every time-gated check and timestamp. Builds with the test-clock
+// feature read it from the MockClock account when it is among the remaining accounts
+// and set; everything else, and every production build, uses the Clock sysvar.
+fn unix_now(remaining_accounts: &[AccountInfo]) -> Result<i64> {
+    #[cfg(feature = "test-clock")]
+    {
+        let (mock_clock, _) = Pubkey::find_program_address(&[MOCK_CLOCK_SEED], &crate::ID);
+        if let Some(info) = remaining_accounts.iter().find(|info| info.key() == mock_clock) {
+            require_keys_eq!(*info.owner, crate::ID, ErrorCode::Unauthorized);
+            let mock = MockClock::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            if mock.unix_timestamp != 0 {
+                return Ok(mock.unix_timestamp);
+            }
+        }
+    }
+    #[cfg(not(feature = "test-clock"))]
+    let _ = remaining_accounts;
+
+    Ok(Clock::get()?.unix_timestamp)
+}
+
+// Lamports a data-less vault PDA must keep to stay rent-exempt
+fn vault_rent_floor() -> Result<u64> {
+    Ok(Rent::get()?.minimum_balance(0))
+}
+
+// Moves lamports out of a market vault PDA, signing with the vault seeds
+fn vault_transfer<'info>(
+    market: &Market,
+    vault: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let market_id_bytes = market.market_id.to_le_bytes();
+    let seeds = &[
+        VAULT_SEED,
+        market_id_bytes.as_ref(),
+        &[market.vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+        vault.key,
+        to.key,
+        amount,
+    );
+
+    anchor_lang::solana_program::program::invoke_signed(
+        &transfer_ix,
+        &[vault.clone(), to.clone(), system_program.clone()],
+        signer,
+    )?;
+
+    Ok(())
+}
+
+// Both reserves grown by the same factor, so `deposit` keeps the price where it is
+fn proportional_reserves(yes: u64, no: u64, deposit: u64) -> Result<(u64, u64)> {
+    let reserves = yes as u128 + no as u128;
+    let scaled = |reserve: u64| -> Result<u64> {
+        let value = (reserve as u128)
+            .checked_mul(reserves + deposit as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / reserves;
+        u64::try_from(value).map_err(|_| error!(ErrorCode::MathOverflow))
+    };
+    Ok((scaled(yes)?, scaled(no)?))
+}
+
+// `deposit` goes to the smaller reserve until it matches the larger one; anything past
+// that is split evenly, so the pool moves toward even odds and never beyond them
+fn rebalanced_reserves(yes: u64, no: u64, deposit: u64) -> Result<(u64, u64)> {
+    let (small, large) = (yes.min(no), yes.max(no));
+    let lift = (large - small).min(deposit);
+    let rest = deposit - lift;
+    let small = small
+        .checked_add(lift + rest - rest / 2)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let large = large.checked_add(rest / 2).ok_or(ErrorCode::MathOverflow)?;
+    Ok(if yes <= no { (small, large) } else { (large, small) })
+}
+
+// Resizes a program-owned account, first topping its rent up from `payer` if it grows
+fn resize_with_rent<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    len: usize,
+) -> Result<()> {
+    let required_rent = Rent::get()?.minimum_balance(len);
+    let top_up = required_rent.saturating_sub(account.lamports());
+    if top_up > 0 {
+        let cpi_context = CpiContext::new(
+            system_program.clone(),
+            system_program::Transfer {
+                from: payer.clone(),
+                to: account.clone(),
+            },
+        );
+        system_program::transfer(cpi_context, top_up)?;
+    }
+    account.resize(len)?;
+    Ok(())
+}
+
+fn read_config_field<T: AnchorDeserialize>(bytes: &mut &[u8]) -> Result<T> {
+    T::deserialize(bytes).map_err(|_| error!(ErrorCode::InvalidConfigAccount))
+}
+
+// Config only ever grew by appending fields, and initialize allocated 8 + Config::LEN
+// for the layout of its day, so an account's length tells which fields it predates.
+// Those get their initialize defaults instead of being read from whatever follows a
+// short resolvers list, which may be zeroes or stale keys from a longer one
+fn read_stored_config(data: &[u8]) -> Result<Config> {
+    const BASE_LEN: usize = 32 + 8 + 2 + 1 + 1;
+    require!(
+        data.len() >= 8 + BASE_LEN && data[..8] == *Config::DISCRIMINATOR,
+        ErrorCode::InvalidConfigAccount
+    );
+    let stored_len = data.len() - 8;
+    let mut allocated = BASE_LEN;
+    let mut has_field = |size: usize| {
+        allocated += size;
+        allocated <= stored_len
+    };
+
+    let bytes = &mut &data[8..];
+    let authority: Pubkey = read_config_field(bytes)?;
+    let mut config = Config {
+        authority,
+        market_count: read_config_field(bytes)?,
+        fee_percentage: read_config_field(bytes)?,
+        bump: read_config_field(bytes)?,
+        fee_vault_bump: read_config_field(bytes)?,
+        trading_buffer: 0,
+        max_markets_per_category: 0,
+        require_trades_to_resolve: false,
+        min_market_duration: 0,
+        max_market_duration: 0,
+        max_fee_bps: 1_000,
+        resolve_grace_period: DEFAULT_RESOLVE_GRACE_PERIOD,
+        resolvers: Vec::new(),
+        revert_window: 0,
+        active_markets: 0,
+        create_cooldown: 0,
+        insurance_fee_bps: 0,
+        treasury: authority,
+        claim_window: 0,
+        house_fee_exempt: false,
+    };
+
+    if has_field(8) { config.trading_buffer = read_config_field(bytes)?; }
+    if has_field(8) { config.max_markets_per_category = read_config_field(bytes)?; }
+    if has_field(1) { config.require_trades_to_resolve = read_config_field(bytes)?; }
+    if has_field(8) { config.min_market_duration = read_config_field(bytes)?; }
+    if has_field(8) { config.max_market_duration = read_config_field(bytes)?; }
+    if has_field(2) { config.max_fee_bps = read_config_field(bytes)?; }
+    if has_field(8) { config.resolve_grace_period = read_config_field(bytes)?; }
+    if has_field(4 + 32 * MAX_RESOLVERS) { config.resolvers = read_config_field(bytes)?; }
+    if has_field(8) { config.revert_window = read_config_field(bytes)?; }
+    if has_field(8) { config.active_markets = read_config_field(bytes)?; }
+    if has_field(8) { config.create_cooldown = read_config_field(bytes)?; }
+    if has_field(2) { config.insurance_fee_bps = read_config_field(bytes)?; }
+    if has_field(32) { config.treasury = read_config_field(bytes)?; }
+    if has_field(8) { config.claim_window = read_config_field(bytes)?; }
+    if has_field(1) { config.house_fee_exempt = read_config_field(bytes)?; }
+    Ok(config)
+}
+
+// Brings a zero-extended Market buffer up to MARKET_VERSION. New fields read as zero
+// from the extension, so only those needing a non-zero default are touched here.
+// Returns false when the account was already current.
+fn upgrade_market_data(data: &mut [u8]) -> Result<bool> {
+    let mut market = Market::try_deserialize(&mut &data[..])?;
+    if market.version >= MARKET_VERSION {
+        return Ok(false);
+    }
+
+    // v1: creator tracking
+    if market.creator == Pubkey::default() {
+        market.creator = market.authority;
+        market.creator_deposit = market.initial_liquidity.saturating_mul(2);
+    }
+
+    // v2: trade_cooldown, zero (disabled) by default
+    // v3: category_hash, which needs the metadata account; migrate_market files the
+    //     market under its category once this returns
+    // v4: market_type, where zero is ConstantProduct
+    // v5: trade_sequence, counting from zero
+    // v6: evidence_hash and evidence_uri, empty until resolution
+    // v7: cancelled and total_contributed; positions bought before v7 carry no contribution
+    // v8: settlement_value, None until a scalar market resolves
+
+    // v9: amm_precision; older k_constants were all scaled by PRECISION
+    if market.amm_precision == 0 {
+        market.amm_precision = PRECISION as u64;
+    }
+
+    // v10: fee_bps, None so the global fee keeps applying
+    // v11: max_price_move_bps, zero (disabled) by default
+    // v12: total_claimed; claims paid before v12 are not counted
+    // v13: resolved_at, zero for markets resolved before v13 so they cannot be reverted
+    // v14: share_decimals, zero keeps shares at lamport scale
+    // v15: max_total_volume, zero (uncapped) by default
+    // v16: trading_closed, false until someone calls close_trading
+    // v17: payout_mode; zeroed bytes decode as Parimutuel, the only mode before v17
+    // v18: access_gate, None so existing markets stay public
+    // v19: max_slippage_bps, zero (no creator limit) by default
+    // v20: resolution_source; zeroed bytes decode as Manual, the only path before v20
+    // v21: claim_deadline, zero so claims on existing markets never expire
+    // v22: resolution_callback and callback_required; zeroed bytes decode as no callback
+    // v23: scalar_threshold, None keeps scalar payouts linear
+
+    market.version = MARKET_VERSION;
+    market.try_serialize(&mut &mut data[..])?;
+    Ok(true)
+}
+
+// Cuts a string to at most `max_len` bytes without splitting a UTF-8 character
+fn truncate_utf8(s: &str, max_len: usize) -> String {
+    if s.len() <= max_len {
+        return s.to_string();
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    s[..end].to_string()
+}
+
+// CORRECT FIX: Use UncheckedAccount and manually initialize in the function
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Config::LEN,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: Fee vault PDA - manually initialized in initialize() function
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64, question: String, description: String, category: String)]
+pub struct CreateMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Market::LEN,
+        seeds = [MARKET_SEED, market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + MarketMetadata::LEN,
+        seeds = [METADATA_SEED, market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub metadata: Account<'info, MarketMetadata>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + CategoryStats::LEN,
+        seeds = [CATEGORY_STATS_SEED, hash(category.as_bytes()).as_ref()],
+        bump
+    )]
+    pub category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + CategoryIndex::LEN,
+        seeds = [
+            CATEGORY_INDEX_SEED,
+            hash(category.as_bytes()).as_ref(),
+            (category_stats.market_count / CATEGORY_PAGE_SIZE).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + CreatorStats::LEN,
+        seeds = [CREATOR_STATS_SEED, authority.key().as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    // Only needed when reserving house shares
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + UserPosition::LEN,
+        seeds = [
+            USER_POSITION_SEED,
+            authority.key().as_ref(),
+            market_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub house_position: Option<Account<'info, UserPosition>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + InsuranceVault::LEN,
+        seeds = [INSURANCE_VAULT_SEED, market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub insurance_vault: Box<Account<'info, InsuranceVault>>,
+
+    /// CHECK: Vault PDA - will be funded with initial liquidity
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateMarketsBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + CreatorStats::LEN,
+        seeds = [CREATOR_STATS_SEED, authority.key().as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenInsuranceVault<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + InsuranceVault::LEN,
+        seeds = [INSURANCE_VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub insurance_vault: Account<'info, InsuranceVault>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenPosition<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserPosition::LEN,
+        seeds = [
+            USER_POSITION_SEED,
+            user.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPositionCosigner<'info> {
+    #[account(
+        mut,
+        seeds = [
+            USER_POSITION_SEED,
+            user.key().as_ref(),
+            user_position.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    pub user: Signer<'info>,
+
+    // Required while the position already has a cosigner
+    pub current_cosigner: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct BuyShares<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Fee vault for the trade's fee bucket, validated in process_buy
+    #[account(mut)]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [CATEGORY_STATS_SEED, market.category_hash.as_ref()],
+        bump = category_stats.bump
+    )]
+    pub category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_POSITION_SEED,
+            user.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // Only needed for buys above the position's cosign threshold
+    pub cosigner: Option<Signer<'info>>,
+
+    // Only needed on markets behind an access gate
+    pub whitelist_entry: Option<Account<'info, WhitelistEntry>>,
+
+    // Required whenever the config sends part of the fee to insurance
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = insurance_vault.bump
+    )]
+    pub insurance_vault: Option<Account<'info, InsuranceVault>>,
+}
+
+impl<'info> BuyShares<'info> {
+    fn buy_accounts(&mut self, now: i64) -> BuyAccounts<'_, 'info> {
+        BuyAccounts {
+            config: &self.config,
+            market: &mut self.market,
+            vault: self.vault.as_ref(),
+            fee_vault: self.fee_vault.as_ref(),
+            category_stats: &mut self.category_stats,
+            user_position: &mut self.user_position,
+            user: self.user.key(),
+            cosigner: self.cosigner.as_ref().map(|c| c.key()),
+            whitelist_entry: self.whitelist_entry.as_deref(),
+            insurance_vault: self.insurance_vault.as_ref().map(|v| v.as_ref()),
+            now,
+            funding: BuyFunding::Wallet(self.user.as_ref()),
+            system_program: self.system_program.as_ref(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct OpenTradingEscrow<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + TradingEscrow::LEN,
+        seeds = [TRADING_ESCROW_SEED, owner.key().as_ref()],
+        bump
+    )]
+    pub escrow: Account<'info, TradingEscrow>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BuySharesDelegated<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Fee vault validated in process_buy
+    #[account(mut)]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [CATEGORY_STATS_SEED, market.category_hash.as_ref()],
+        bump = category_stats.bump
+    )]
+    pub category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        mut,
+        seeds = [TRADING_ESCROW_SEED, owner.key().as_ref()],
+        bump = escrow.bump,
+        has_one = owner,
+        has_one = operator @ ErrorCode::Unauthorized
+    )]
+    pub escrow: Account<'info, TradingEscrow>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_POSITION_SEED,
+            owner.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    /// CHECK: Escrow owner, validated by has_one
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub operator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // Only needed for buys above the position's cosign threshold
+    pub cosigner: Option<Signer<'info>>,
+
+    // Only needed on markets behind an access gate
+    pub whitelist_entry: Option<Account<'info, WhitelistEntry>>,
+
+    // Required whenever the config sends part of the fee to insurance
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = insurance_vault.bump
+    )]
+    pub insurance_vault: Option<Account<'info, InsuranceVault>>,
+}
+
+impl<'info> BuySharesDelegated<'info> {
+    fn buy_accounts(&mut self, now: i64) -> BuyAccounts<'_, 'info> {
+        BuyAccounts {
+            config: &self.config,
+            market: &mut self.market,
+            vault: self.vault.as_ref(),
+            fee_vault: self.fee_vault.as_ref(),
+            category_stats: &mut self.category_stats,
+            user_position: &mut self.user_position,
+            user: self.owner.key(),
+            cosigner: self.cosigner.as_ref().map(|c| c.key()),
+            whitelist_entry: self.whitelist_entry.as_deref(),
+            insurance_vault: self.insurance_vault.as_ref().map(|v| v.as_ref()),
+            now,
+            funding: BuyFunding::Escrow(self.escrow.as_ref()),
+            system_program: self.system_program.as_ref(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct BuySharesFor<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Fee vault validated in process_buy
+    #[account(mut)]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [CATEGORY_STATS_SEED, market.category_hash.as_ref()],
+        bump = category_stats.bump
+    )]
+    pub category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserPosition::LEN,
+        seeds = [
+            USER_POSITION_SEED,
+            beneficiary.as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // Only needed for buys above the beneficiary position's cosign threshold
+    pub cosigner: Option<Signer<'info>>,
+
+    // The beneficiary's entry, on markets behind an access gate
+    pub whitelist_entry: Option<Account<'info, WhitelistEntry>>,
+
+    // Required whenever the config sends part of the fee to insurance
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = insurance_vault.bump
+    )]
+    pub insurance_vault: Option<Account<'info, InsuranceVault>>,
+}
+
+impl<'info> BuySharesFor<'info> {
+    fn buy_accounts(&mut self, now: i64) -> BuyAccounts<'_, 'info> {
+        BuyAccounts {
+            config: &self.config,
+            market: &mut self.market,
+            vault: self.vault.as_ref(),
+            fee_vault: self.fee_vault.as_ref(),
+            category_stats: &mut self.category_stats,
+            user: self.user_position.user,
+            user_position: &mut self.user_position,
+            cosigner: self.cosigner.as_ref().map(|c| c.key()),
+            whitelist_entry: self.whitelist_entry.as_deref(),
+            insurance_vault: self.insurance_vault.as_ref().map(|v| v.as_ref()),
+            now,
+            funding: BuyFunding::Wallet(self.payer.as_ref()),
+            system_program: self.system_program.as_ref(),
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct WithdrawEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [TRADING_ESCROW_SEED, owner.key().as_ref()],
+        bump = escrow.bump,
+        has_one = owner
+    )]
+    pub escrow: Account<'info, TradingEscrow>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveMarket<'info> {
+    // Mutable so a scalar market cancelled on its threshold leaves the active count
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + AuditLog::LEN,
+        seeds = [AUDIT_LOG_SEED],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimWinnings<'info> {
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_POSITION_SEED,
+            user.key().as_ref(),
+            user_position.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // Tapped only if the vault cannot cover the payout; claims work without it
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = insurance_vault.bump
+    )]
+    pub insurance_vault: Option<Account<'info, InsuranceVault>>,
+}
+
+#[derive(Accounts)]
+pub struct PushClaim<'info> {
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        has_one = user,
+        seeds = [
+            USER_POSITION_SEED,
+            user.key().as_ref(),
+            user_position.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    /// CHECK: Position owner, checked against user_position.user; receives the payout
+    #[account(mut)]
+    pub user: UncheckedAccount<'info>,
+
+    pub caller: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // Tapped only if the vault cannot cover the payout; claims work without it
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = insurance_vault.bump
+    )]
+    pub insurance_vault: Option<Account<'info, InsuranceVault>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawCreatorLiquidity<'info> {
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SweepUnclaimed<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Lamport destination only, matched against config.treasury
+    #[account(mut)]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + AuditLog::LEN,
+        seeds = [AUDIT_LOG_SEED],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct CloseMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [METADATA_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = metadata.bump
+    )]
+    pub metadata: Account<'info, MarketMetadata>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Fee vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump = config.fee_vault_bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Insurance vault PDA validated by seeds. Whatever insurance was never needed
+    /// goes to the fee vault with the account's rent; legacy markets pass the empty address
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub insurance_vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + AuditLog::LEN,
+        seeds = [AUDIT_LOG_SEED],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateConfig<'info> {
+    /// CHECK: May still be in an older layout; read by hand in migrate_config()
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateMarket<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: May still be in an older layout; discriminator and PDA are checked in migrate_market()
+    #[account(mut, owner = crate::ID)]
+    pub market: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxSlippage<'info> {
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AuthorityAddLiquidity<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Fee vault PDA validated by seeds; receives the fee on rebalancing top-ups
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump = config.fee_vault_bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(gate: Pubkey, user: Pubkey)]
+pub struct AddToWhitelist<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + WhitelistEntry::LEN,
+        seeds = [WHITELIST_SEED, gate.as_ref(), user.as_ref()],
+        bump
+    )]
+    pub whitelist_entry: Account<'info, WhitelistEntry>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFromWhitelist<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        close = authority,
+        seeds = [WHITELIST_SEED, whitelist_entry.gate.as_ref(), whitelist_entry.user.as_ref()],
+        bump = whitelist_entry.bump
+    )]
+    pub whitelist_entry: Account<'info, WhitelistEntry>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseTrading<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EnableRefunds<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFee<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + AuditLog::LEN,
+        seeds = [AUDIT_LOG_SEED],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct GetAuditLog<'info> {
+    #[account(
+        seeds = [AUDIT_LOG_SEED],
+        bump = audit_log.bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(fee_bucket: u16)]
+pub struct InitFeeBucket<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: Fee bucket vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, fee_bucket_seed(fee_bucket).as_ref()],
+        bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount: u64, fee_bucket: u16)]
+pub struct WithdrawFees<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: Fee vault PDA for the bucket, validated by seeds
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, fee_bucket_seed(fee_bucket).as_ref()],
+        bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Lamport destination only, matched against config.treasury
+    #[account(mut)]
+    pub treasury: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + AuditLog::LEN,
+        seeds = [AUDIT_LOG_SEED],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+}
+
+#[derive(Accounts)]
+#[instruction(fee_bucket: u16)]
+pub struct CloseFeeVault<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = treasury @ ErrorCode::InvalidTreasury
+    )]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: Fee vault PDA for the bucket, validated by seeds
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED, fee_bucket_seed(fee_bucket).as_ref()],
+        bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: Lamport destination only, matched against config.treasury
+    #[account(mut)]
+    pub treasury: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + AuditLog::LEN,
+        seeds = [AUDIT_LOG_SEED],
+        bump
+    )]
+    pub audit_log: Account<'info, AuditLog>,
+}
+
+#[derive(Accounts)]
+pub struct SellShares<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Fee vault PDA validated by seeds - initialized in initialize()
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump = config.fee_vault_bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [CATEGORY_STATS_SEED, market.category_hash.as_ref()],
+        bump = category_stats.bump
+    )]
+    pub category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_POSITION_SEED,
+            user.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // Required whenever the config sends part of the fee to insurance
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = insurance_vault.bump
+    )]
+    pub insurance_vault: Option<Account<'info, InsuranceVault>>,
+}
+
+#[derive(Accounts)]
+pub struct TransferShares<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_POSITION_SEED,
+            sender.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump = sender_position.bump
+    )]
+    pub sender_position: Account<'info, UserPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = sender,
+        space = 8 + UserPosition::LEN,
+        seeds = [
+            USER_POSITION_SEED,
+            recipient.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub recipient_position: Account<'info, UserPosition>,
+
+    /// CHECK: Only used as the recipient position's seed
+    #[account(constraint = recipient.key() != sender.key() @ ErrorCode::SelfTransfer)]
+    pub recipient: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: u64)]
+pub struct PlaceTriggerOrder<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [
+            USER_POSITION_SEED,
+            owner.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + TriggerOrder::LEN,
+        seeds = [
+            TRIGGER_ORDER_SEED,
+            owner.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref(),
+            order_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub trigger_order: Account<'info, TriggerOrder>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelTriggerOrder<'info> {
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        seeds = [
+            TRIGGER_ORDER_SEED,
+            owner.key().as_ref(),
+            trigger_order.market_id.to_le_bytes().as_ref(),
+            trigger_order.order_id.to_le_bytes().as_ref()
+        ],
+        bump = trigger_order.bump
+    )]
+    pub trigger_order: Account<'info, TriggerOrder>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTrigger<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, trigger_order.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        mut,
+        seeds = [VAULT_SEED, trigger_order.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Fee vault PDA validated by seeds - initialized in initialize()
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump = config.fee_vault_bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [CATEGORY_STATS_SEED, market.category_hash.as_ref()],
+        bump = category_stats.bump
+    )]
+    pub category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        seeds = [
+            TRIGGER_ORDER_SEED,
+            owner.key().as_ref(),
+            trigger_order.market_id.to_le_bytes().as_ref(),
+            trigger_order.order_id.to_le_bytes().as_ref()
+        ],
+        bump = trigger_order.bump
+    )]
+    pub trigger_order: Account<'info, TriggerOrder>,
+
+    #[account(
+        mut,
+        seeds = [
+            USER_POSITION_SEED,
+            owner.key().as_ref(),
+            trigger_order.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+
+    /// CHECK: Order owner, checked against trigger_order.owner; receives proceeds and rent
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // Required whenever the config sends part of the fee to insurance
+    #[account(
+        mut,
+        seeds = [INSURANCE_VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = insurance_vault.bump
+    )]
+    pub insurance_vault: Option<Account<'info, InsuranceVault>>,
+}
+
+#[derive(Accounts)]
+pub struct GetClaimableAmount<'info> {
+    #[account(
+        seeds = [MARKET_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        seeds = [VAULT_SEED, user_position.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [
+            USER_POSITION_SEED,
+            user_position.user.as_ref(),
+            user_position.market_id.to_le_bytes().as_ref()
+        ],
+        bump = user_position.bump
+    )]
+    pub user_position: Account<'info, UserPosition>,
+}
+
+#[derive(Accounts)]
+pub struct GetUserPosition<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds
+    #[account(
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Position PDA validated by seeds; may not exist yet
+    #[account(
+        seeds = [
+            USER_POSITION_SEED,
+            user.key().as_ref(),
+            market.market_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub user_position: UncheckedAccount<'info>,
+
+    /// CHECK: Any wallet; only used to derive the position
+    pub user: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetPortfolioValue<'info> {
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetAmmState<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct GetFeeBreakdown<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct GetPriceImpact<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct GetOutcomePrices<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+}
+
+#[derive(Accounts)]
+pub struct GetConfig<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct GetResolutionCountdown<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+}
+
+#[cfg(feature = "test-clock")]
+#[derive(Accounts)]
+pub struct SetMockTime<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + MockClock::LEN,
+        seeds = [MOCK_CLOCK_SEED],
+        bump
+    )]
+    pub mock_clock: Account<'info, MockClock>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AssertSolvency<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Vault PDA validated by seeds; only its balance is read
+    #[account(
+        seeds = [VAULT_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub vault: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(fee_bucket: u16)]
+pub struct GetFeeBalance<'info> {
+    /// CHECK: Fee vault PDA for the bucket, validated by seeds
+    #[account(
+        seeds = [FEE_VAULT_SEED, fee_bucket_seed(fee_bucket).as_ref()],
+        bump
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetMarketSummary<'info> {
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [METADATA_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = metadata.bump
+    )]
+    pub metadata: Account<'info, MarketMetadata>,
+}
+
+#[derive(Accounts)]
+pub struct EditQuestion<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [METADATA_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = metadata.bump
+    )]
+    pub metadata: Account<'info, MarketMetadata>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_category: String)]
+pub struct UpdateCategory<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [MARKET_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [METADATA_SEED, market.market_id.to_le_bytes().as_ref()],
+        bump = metadata.bump
+    )]
+    pub metadata: Account<'info, MarketMetadata>,
+
+    // The page of the current category that lists this market
+    #[account(
+        mut,
+        seeds = [
+            CATEGORY_INDEX_SEED,
+            market.category_hash.as_ref(),
+            old_category_index.page.to_le_bytes().as_ref()
+        ],
+        bump = old_category_index.bump
+    )]
+    pub old_category_index: Account<'info, CategoryIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + CategoryStats::LEN,
+        seeds = [CATEGORY_STATS_SEED, hash(new_category.as_bytes()).as_ref()],
+        bump
+    )]
+    pub new_category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + CategoryIndex::LEN,
+        seeds = [
+            CATEGORY_INDEX_SEED,
+            hash(new_category.as_bytes()).as_ref(),
+            (new_category_stats.market_count / CATEGORY_PAGE_SIZE).to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub new_category_index: Account<'info, CategoryIndex>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(page: u64)]
+pub struct GetCategoryPage<'info> {
+    #[account(
+        seeds = [CATEGORY_STATS_SEED, category_stats.category_hash.as_ref()],
+        bump = category_stats.bump
+    )]
+    pub category_stats: Account<'info, CategoryStats>,
+
+    #[account(
+        seeds = [
+            CATEGORY_INDEX_SEED,
+            category_stats.category_hash.as_ref(),
+            page.to_le_bytes().as_ref()
+        ],
+        bump = category_index.bump
+    )]
+    pub category_index: Account<'info, CategoryIndex>,
+}
+
+// Same account set as BuyShares, without the seeds constraints, so verify_accounts can
+// check each derivation itself
+#[derive(Accounts)]
+pub struct VerifyAccounts<'info> {
+    pub config: Account<'info, Config>,
+
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Re-derived in verify_accounts
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: Re-derived in verify_accounts
+    pub fee_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Re-derived in verify_accounts
+    pub category_stats: UncheckedAccount<'info>,
+
+    /// CHECK: Re-derived in verify_accounts; may not exist before a first buy
+    pub user_position: UncheckedAccount<'info>,
+
+    /// CHECK: Only its key seeds the position address
+    pub user: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[account]
+pub struct Config {
+    pub authority: Pubkey,
+    pub market_count: u64,
+    pub fee_percentage: u16,
+    pub bump: u8,
+    pub fee_vault_bump: u8,
+    pub trading_buffer: i64,
+    pub max_markets_per_category: u64,
+    pub require_trades_to_resolve: bool,
+    pub min_market_duration: i64,
+    pub max_market_duration: i64,
+    // Ceiling for every fee, global or per market
+    pub max_fee_bps: u16,
+    // How long after resolution_time the authority has before anyone can enable refunds
+    pub resolve_grace_period: i64,
+    // Keys besides the authority allowed to resolve markets, at most MAX_RESOLVERS
+    pub resolvers: Vec<Pubkey>,
+    // Seconds after resolving during which the authority may revert_resolution
+    pub revert_window: i64,
+    // Markets not yet closed or cancelled; bucket 0 cannot close while any remain
+    pub active_markets: u64,
+    // Seconds a creator must wait between markets, zero for no limit
+    pub create_cooldown: i64,
+    // Slice of every trade fee, in bps of the fee, paid into the market's insurance vault
+    pub insurance_fee_bps: u16,
+    // Receives fee withdrawals and closed fee vaults; the authority until changed
+    pub treasury: Pubkey,
+    // Seconds after resolution that winners have to claim, zero for no deadline
+    pub claim_window: i64,
+    // Buys paid by the authority or treasury wallet skip the trade fee
+    pub house_fee_exempt: bool,
+}
+
+impl Config {
+    pub const LEN: usize = 32 + 8 + 2 + 1 + 1 + 8 + 8 + 1 + 8 + 8 + 2 + 8 + (4 + 32 * MAX_RESOLVERS) + 8 + 8 + 8 + 2 + 32 + 8 + 1;
+
+    pub fn view(&self) -> ConfigView {
+        ConfigView {
+            authority: self.authority,
+            treasury: self.treasury,
+            market_count: self.market_count,
+            active_markets: self.active_markets,
+            fee_bps: self.fee_percentage.min(self.max_fee_bps),
+            max_fee_bps: self.max_fee_bps,
+            insurance_fee_bps: self.insurance_fee_bps,
+            trading_buffer: self.trading_buffer,
+            min_market_duration: self.min_market_duration,
+            max_market_duration: self.max_market_duration,
+            max_markets_per_category: self.max_markets_per_category,
+            create_cooldown: self.create_cooldown,
+            require_trades_to_resolve: self.require_trades_to_resolve,
+            resolvers: self.resolvers.clone(),
+            resolve_grace_period: self.resolve_grace_period,
+            revert_window: self.revert_window,
+            claim_window: self.claim_window,
+            house_fee_exempt: self.house_fee_exempt,
+        }
+    }
+
+    pub fn fee_exempt(&self, payer: &Pubkey) -> bool {
+        self.house_fee_exempt && (*payer == self.authority || *payer == self.treasury)
+    }
+
+    pub fn can_resolve(&self, key: &Pubkey) -> bool {
+        *key == self.authority || self.resolvers.contains(key)
+    }
+
+    // Fee a trade on `market` pays, in bps. Clamped so lowering the cap takes effect
+    // on markets that were priced above it
+    pub fn fee_bps_for(&self, market: &Market) -> u64 {
+        market.fee_bps.unwrap_or(self.fee_percentage).min(self.max_fee_bps) as u64
+    }
+
+    // How a buy of `amount_lamports` on `market` splits between the fee and the pool
+    pub fn fee_breakdown(&self, market: &Market, amount_lamports: u64) -> Result<FeeBreakdown> {
+        let fee = amount_lamports
+            .checked_mul(self.fee_bps_for(market))
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(BPS_DENOMINATOR)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let amount_after_fee = amount_lamports
+            .checked_sub(fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(FeeBreakdown { fee, amount_after_fee })
+    }
+
+    // Trading stops `trading_buffer` seconds ahead of resolution_time
+    pub fn trading_cutoff(&self, resolution_time: i64) -> Result<i64> {
+        Ok(resolution_time
+            .checked_sub(self.trading_buffer)
+            .ok_or(ErrorCode::TimeOverflow)?)
+    }
+
+    // Whole unix seconds from the clock sysvar. Trading is open strictly before the
+    // cutoff and resolution from resolution_time on, so at resolution_time - 1 only
+    // trading is allowed and at resolution_time only resolution is. trading_buffer is
+    // never negative, so the two windows cannot overlap and no extra closed second is
+    // needed. Transactions landing in the same slot all see the same second
+    pub fn trading_open(&self, resolution_time: i64, now: i64) -> Result<bool> {
+        Ok(now < self.trading_cutoff(resolution_time)?)
+    }
+}
+
+#[account]
+pub struct Market {
+    pub market_id: u64,
+    pub authority: Pubkey,
     pub resolution_time: i64,
     pub created_at: i64,
     pub initial_liquidity: u64,
@@ -641,13 +5638,818 @@ pub struct Market {
     pub total_no_shares: u128,
     pub bump: u8,
     pub vault_bump: u8,
+    pub payout_per_share: u128,
+    pub creator: Pubkey,
+    pub creator_deposit: u64,
+    pub version: u8,
+    pub trade_cooldown: i64,
+    pub category_hash: [u8; 32],
+    // Lmsr markets use initial_liquidity as the liquidity parameter b
+    pub market_type: MarketType,
+    // Bumped on every buy and sell so indexers can order trade events and spot gaps
+    pub trade_sequence: u64,
+    // Set at resolution; empty when resolved without evidence
+    pub evidence_hash: [u8; 32],
+    pub evidence_uri: String,
+    // A cancelled market never resolves; positions reclaim their contributions instead
+    pub cancelled: bool,
+    // Sum of lamports_contributed over all positions, i.e. what refunds still owe
+    pub total_contributed: u64,
+    // Scalar markets only: the resolved value, clamped to the market's bounds
+    pub settlement_value: Option<i64>,
+    // Scaling for k_constant and the swap math; zero on markets created before v9
+    pub amm_precision: u64,
+    // Overrides Config::fee_percentage for this market when set
+    pub fee_bps: Option<u16>,
+    // Largest move in implied YES price one buy may cause, zero for no limit
+    pub max_price_move_bps: u16,
+    // Lamports paid out by winnings claims so far
+    pub total_claimed: u64,
+    // When the market was resolved, zero while unresolved
+    pub resolved_at: i64,
+    // Shares are tracked at 10^share_decimals per lamport of pool liquidity
+    pub share_decimals: u8,
+    // Cap on total_volume that buys may reach, zero for no cap
+    pub max_total_volume: u64,
+    // Set by close_trading once the trading cutoff has passed
+    pub trading_closed: bool,
+    // How the vault is split among winning shares at settlement
+    pub payout_mode: PayoutMode,
+    // When set, only users with a WhitelistEntry under this gate may buy
+    pub access_gate: Option<Pubkey>,
+    // Creator's cap on the implied YES move of one buy, zero for no limit
+    pub max_slippage_bps: u16,
+    // Which mechanism resolved or voided the market; meaningless while it is still open
+    pub resolution_source: ResolutionSource,
+    // Claims close and unclaimed winnings become sweepable at this time; zero for never
+    pub claim_deadline: i64,
+    // Program CPI-notified with the outcome when the market resolves
+    pub resolution_callback: Option<Pubkey>,
+    // Refuse to resolve unless the callback program is passed in and invoked
+    pub callback_required: bool,
+    // Settles a scalar market as over/under this threshold instead of linearly
+    pub scalar_threshold: Option<ScalarThreshold>,
+}
+
+impl Market {
+    // Fills in a newly created market. The caller sets the PDA bumps
+    pub fn init_new(
+        &mut self,
+        params: &MarketParams,
+        amm_precision: u64,
+        house_shares: u64,
+        authority: Pubkey,
+        now: i64,
+    ) -> Result<()> {
+        let liquidity = params.initial_liquidity_lamports;
+        self.market_id = params.market_id;
+        self.authority = authority;
+        self.resolution_time = params.resolution_time;
+        self.created_at = now;
+        self.initial_liquidity = liquidity;
+        self.yes_liquidity = liquidity;
+        self.no_liquidity = liquidity;
+
+        // High-precision k constant, scaled by the market's own precision
+        self.amm_precision = amm_precision;
+        self.k_constant = (liquidity as u128)
+            .checked_mul(amm_precision as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_mul(liquidity as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_mul(amm_precision as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        self.total_volume = 0;
+        self.resolved = false;
+        self.outcome = None;
+        self.total_yes_shares = house_shares as u128;
+        self.total_no_shares = house_shares as u128;
+        self.payout_per_share = 0;
+        self.creator = authority;
+        self.creator_deposit = self.seed_deposit()?;
+        self.trade_cooldown = 0;
+        self.category_hash = hash(params.category.as_bytes()).to_bytes();
+        self.market_type = params.market_type;
+        self.trade_sequence = 0;
+        self.evidence_hash = [0; 32];
+        self.evidence_uri = String::new();
+        self.cancelled = false;
+        self.total_contributed = 0;
+        self.settlement_value = None;
+        self.fee_bps = None;
+        self.max_price_move_bps = 0;
+        self.total_claimed = 0;
+        self.resolved_at = 0;
+        self.share_decimals = 0;
+        self.max_total_volume = 0;
+        self.trading_closed = false;
+        self.payout_mode = PayoutMode::Parimutuel;
+        self.access_gate = None;
+        self.max_slippage_bps = 0;
+        self.resolution_source = ResolutionSource::Manual;
+        self.claim_deadline = 0;
+        self.resolution_callback = None;
+        self.callback_required = false;
+        self.scalar_threshold = None;
+        self.version = MARKET_VERSION;
+        Ok(())
+    }
+
+    pub const LEN: usize = 8 + 32
+        + 8 + 8 + 8 + 8 + 8 + 16 + 8 + 1 + (1 + 1)
+        + 16 + 16
+        + 1 + 1
+        + 16
+        + 32 + 8
+        + 1
+        + 8
+        + 32
+        + (1 + 8 + 8)
+        + 8
+        + 32 + (4 + EVIDENCE_URI_LEN)
+        + 1 + 8
+        + (1 + 8)
+        + 8
+        + (1 + 2)
+        + 2
+        + 8
+        + 8
+        + 1
+        + 8
+        + 1
+        + (1 + 8)
+        + (1 + 32)
+        + 2
+        + 1
+        + 8
+        + (1 + 32)
+        + 1
+        + (1 + 8 + 1);
+
+    // Shares per lamport-scale pool unit
+    pub fn share_scale(&self) -> u64 {
+        10u64.pow(self.share_decimals as u32)
+    }
+
+    pub fn precision(&self) -> u128 {
+        if self.amm_precision == 0 {
+            PRECISION
+        } else {
+            self.amm_precision as u128
+        }
+    }
+
+    pub fn scalar_bounds(&self) -> Option<(i64, i64)> {
+        match self.market_type {
+            MarketType::Scalar { lower_bound, upper_bound } => Some((lower_bound, upper_bound)),
+            _ => None,
+        }
+    }
+
+    // What a YES and a NO share redeem for, in bps of a full winning share. Binary
+    // markets pay one side in full; scalar markets pay YES (settlement - lower) /
+    // (upper - lower) and NO the complement, unless a threshold makes them over/under
+    pub fn payout_weights(&self) -> Result<(u128, u128)> {
+        let denominator = BPS_DENOMINATOR as u128;
+        let yes_weight = match (self.settlement_value, self.scalar_bounds(), self.scalar_threshold) {
+            (Some(value), Some(_), Some(threshold)) => {
+                match value.cmp(&threshold.value) {
+                    std::cmp::Ordering::Greater => denominator,
+                    std::cmp::Ordering::Less => 0,
+                    // A refund tie cancels the market on resolution, so it never pays here
+                    std::cmp::Ordering::Equal => match threshold.at_threshold {
+                        ThresholdTie::Split => denominator / 2,
+                        ThresholdTie::Refund => return err!(ErrorCode::MarketCancelled),
+                    },
+                }
+            }
+            (Some(value), Some((lower, upper)), None) => {
+                ((value as i128 - lower as i128) * denominator as i128 / (upper as i128 - lower as i128)) as u128
+            }
+            _ => match self.outcome.ok_or(ErrorCode::MarketNotResolved)? {
+                true => denominator,
+                false => 0,
+            },
+        };
+        Ok((yes_weight, denominator - yes_weight))
+    }
+
+    // Whether settling at `value` lands exactly on a threshold configured to refund
+    pub fn refunds_at(&self, value: i64) -> bool {
+        matches!(
+            self.scalar_threshold,
+            Some(ScalarThreshold { value: threshold, at_threshold: ThresholdTie::Refund }) if threshold == value
+        )
+    }
+
+    // YES and NO shares expressed as full winning shares
+    pub fn weighted_shares(&self, yes_shares: u128, no_shares: u128) -> Result<u128> {
+        let (yes_weight, no_weight) = self.payout_weights()?;
+        let weighted = yes_shares
+            .checked_mul(yes_weight)
+            .and_then(|yes| no_shares.checked_mul(no_weight).and_then(|no| yes.checked_add(no)))
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(weighted / BPS_DENOMINATOR as u128)
+    }
+
+    pub fn total_winning_shares(&self) -> Result<u128> {
+        self.weighted_shares(self.total_yes_shares, self.total_no_shares)
+    }
+
+    // Lamports the vault must still be able to pay out. Refunds on a cancelled market;
+    // otherwise a lamport per share_scale shares that can win (either side while
+    // unresolved), or the snapshotted rate once claims have started. The creator's
+    // seed is excluded: it only comes out of what is left afterwards.
+    pub fn outstanding_obligations(&self) -> Result<u64> {
+        let owed = if self.cancelled {
+            self.total_contributed as u128
+        } else if self.resolved {
+            let rate = if self.payout_per_share != 0 {
+                self.payout_per_share
+            } else {
+                self.fixed_payout_per_share().unwrap_or(PRECISION)
+            };
+            self.total_winning_shares()?
+                .checked_mul(rate)
+                .ok_or(ErrorCode::MathOverflow)?
+                / self.payout_scale()
+        } else {
+            self.total_yes_shares
+                .max(self.total_no_shares)
+                .div_ceil(self.share_scale() as u128)
+        };
+        u64::try_from(owed).map_err(|_| error!(ErrorCode::MathOverflow))
+    }
+
+    // Gated markets only take buys from users listed under the gate
+    pub fn require_access(&self, entry: Option<&WhitelistEntry>, user: &Pubkey) -> Result<()> {
+        if let Some(gate) = self.access_gate {
+            let listed = entry.is_some_and(|entry| entry.gate == gate && entry.user == *user);
+            require!(listed, ErrorCode::NotWhitelisted);
+        }
+        Ok(())
+    }
+
+    // Resolution opens at resolution_time itself; see Config::trading_open for the boundary
+    pub fn resolution_reached(&self, now: i64) -> bool {
+        now >= self.resolution_time
+    }
+
+    pub fn seconds_to_resolution(&self, now: i64) -> Result<i64> {
+        Ok(self
+            .resolution_time
+            .checked_sub(now)
+            .ok_or(ErrorCode::TimeOverflow)?)
+    }
+
+    pub fn solvency(&self, distributable: u64) -> Result<SolvencyReport> {
+        let obligations = self.outstanding_obligations()?;
+        Ok(SolvencyReport {
+            market_id: self.market_id,
+            vault_balance: distributable,
+            obligations,
+            solvent: distributable >= obligations,
+        })
+    }
+
+    // Fixed-point scale of payout_per_share. The share scale is folded in so finer
+    // shares keep the same lamport precision per claim
+    pub fn payout_scale(&self) -> u128 {
+        PRECISION * self.share_scale() as u128
+    }
+
+    // Lamports per winning share (scaled by payout_scale), fixed at the first claim so
+    // full and partial claims all redeem at the same rate
+    pub fn snapshot_payout_per_share(&mut self, vault_balance: u64) -> Result<u128> {
+        if self.payout_per_share == 0 {
+            self.payout_per_share = self.current_payout_per_share(vault_balance)?;
+        }
+        Ok(self.payout_per_share)
+    }
+
+    // The snapshot if one was taken, otherwise what the first claim would fix it at
+    pub fn current_payout_per_share(&self, vault_balance: u64) -> Result<u128> {
+        if self.payout_per_share != 0 {
+            return Ok(self.payout_per_share);
+        }
+
+        let total_winning_shares = self.total_winning_shares()?;
+        require!(total_winning_shares > 0, ErrorCode::NoWinningShares);
+
+        let payout_per_share = (vault_balance as u128)
+            .checked_mul(self.payout_scale())
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(total_winning_shares)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // A fixed redemption is capped at what the vault can pay every winner
+        Ok(match self.fixed_payout_per_share() {
+            Some(fixed) => fixed.min(payout_per_share),
+            None => payout_per_share,
+        })
+    }
+
+    // The FixedRedemption rate at payout_scale, None for parimutuel markets
+    pub fn fixed_payout_per_share(&self) -> Option<u128> {
+        match self.payout_mode {
+            PayoutMode::Parimutuel => None,
+            PayoutMode::FixedRedemption { lamports_per_share } => {
+                Some(lamports_per_share as u128 * PRECISION)
+            }
+        }
+    }
+
+    // Lamports owed for `shares` winning shares. When the whole vault is being split,
+    // the last claimant takes whatever is left so per-share truncation never strands
+    // dust above the rent floor. An uncapped fixed redemption leaves the rest behind.
+    pub fn payout_for_shares(&self, shares: u64, distributable: u64) -> Result<u64> {
+        Ok(self.owed_for_shares(shares, distributable)?.min(distributable))
+    }
+
+    // What `shares` are owed at the snapshot rate, even past what the vault holds. Above
+    // payout_for_shares only when the vault has come up short
+    pub fn owed_for_shares(&self, shares: u64, distributable: u64) -> Result<u64> {
+        let rate = self.current_payout_per_share(distributable)?;
+        let owed = (shares as u128)
+            .checked_mul(rate)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(self.payout_scale())
+            .ok_or(ErrorCode::MathOverflow)?;
+        let owed = u64::try_from(owed).map_err(|_| error!(ErrorCode::MathOverflow))?;
+
+        let splits_vault = match self.fixed_payout_per_share() {
+            Some(fixed) => rate < fixed,
+            None => true,
+        };
+        if splits_vault && shares as u128 == self.total_winning_shares()? {
+            return Ok(owed.max(distributable));
+        }
+        Ok(owed)
+    }
+
+    // Winners may claim until claim_deadline, if the market has one
+    pub fn require_claimable(&self, now: i64) -> Result<()> {
+        if self.claim_deadline > 0 && now >= self.claim_deadline {
+            msg!("Claims closed at {}, now {}", self.claim_deadline, now);
+            return err!(ErrorCode::ClaimDeadlinePassed);
+        }
+        Ok(())
+    }
+
+    // Winning shares still unclaimed past the claim deadline, and the lamports they
+    // would have been paid out of `distributable`
+    pub fn sweepable_unclaimed(&self, now: i64, distributable: u64) -> Result<(u64, u64)> {
+        require!(self.claim_deadline > 0, ErrorCode::NoClaimDeadline);
+        require!(now >= self.claim_deadline, ErrorCode::ClaimDeadlineNotReached);
+        let shares = u64::try_from(self.total_winning_shares()?)
+            .map_err(|_| error!(ErrorCode::MathOverflow))?;
+        require!(shares > 0, ErrorCode::NoWinningShares);
+        Ok((shares, self.payout_for_shares(shares, distributable)?))
+    }
+
+    // Lamports the creator seeds the vault with: both reserves for constant product,
+    // the worst-case LMSR loss b * ln 2 otherwise
+    pub fn seed_deposit(&self) -> Result<u64> {
+        match self.market_type {
+            MarketType::ConstantProduct | MarketType::Scalar { .. } => self.initial_liquidity
+                .checked_mul(2)
+                .ok_or(error!(ErrorCode::MathOverflow)),
+            MarketType::Lmsr => {
+                let bound = (self.initial_liquidity as u128 * amm::LN_2).div_ceil(PRECISION);
+                u64::try_from(bound).map_err(|_| error!(ErrorCode::MathOverflow))
+            }
+        }
+    }
+
+    // Trades round the reserves up (see cpmm_reserve_after), so yes * no sits above
+    // k / PRECISION^2 by less than yes + no. Markets traded under the old floor rounding
+    // may sit below it by the same margin. Anything beyond that is real drift.
+    pub fn amm_invariant_holds(&self) -> bool {
+        let product = self.yes_liquidity as u128 * self.no_liquidity as u128;
+        let k = self.k_constant / (self.precision() * self.precision());
+        let tolerance = self.yes_liquidity as u128 + self.no_liquidity as u128 + 1;
+        product.abs_diff(k) <= tolerance
+    }
+
+    // Post-trade guard: a swap may leave the pool richer than k but never poorer beyond
+    // the rounding tolerance above. LMSR markets do not trade against k.
+    pub fn require_min_k(&self) -> Result<()> {
+        if self.market_type == MarketType::Lmsr {
+            return Ok(());
+        }
+        let product = self.yes_liquidity as u128 * self.no_liquidity as u128;
+        let k = self.k_constant / (self.precision() * self.precision());
+        let tolerance = self.yes_liquidity as u128 + self.no_liquidity as u128 + 1;
+        if product.saturating_add(tolerance) < k {
+            msg!("Invariant: reserves multiply to {}, k is {} (tolerance {})", product, k, tolerance);
+            return err!(ErrorCode::InvariantViolation);
+        }
+        Ok(())
+    }
+
+    // Fails if selling every outstanding share of one side back into the pool would pay
+    // out more than the `distributable` lamports the vault holds above its rent floor
+    pub fn require_sell_back_covered(&self, distributable: u64) -> Result<()> {
+        let sides = [
+            (self.total_yes_shares, self.no_liquidity, self.yes_liquidity),
+            (self.total_no_shares, self.yes_liquidity, self.no_liquidity),
+        ];
+        for (outstanding, reserve_in, reserve_out) in sides {
+            let pool_units = outstanding / self.share_scale() as u128;
+            if pool_units == 0 {
+                continue;
+            }
+            let pool_units = u64::try_from(pool_units).map_err(|_| error!(ErrorCode::MathOverflow))?;
+            let (sell_back, _, _) = amm::swap(reserve_in, reserve_out, self.k_constant, pool_units, self.precision())?;
+            if sell_back > distributable {
+                msg!("Selling back {} pool units pays {}, vault holds {}", pool_units, sell_back, distributable);
+                return err!(ErrorCode::RebalanceUndercollateralized);
+            }
+        }
+        Ok(())
+    }
+
+    // Move in implied YES price, in bps, from buying with `amount_after_fee` on one side
+    pub fn price_impact_bps(&self, is_yes: bool, amount_after_fee: u64) -> Result<u16> {
+        let mut after = self.clone();
+        let shares = if self.market_type == MarketType::Lmsr {
+            let (bought, other) = if is_yes {
+                (self.total_yes_shares, self.total_no_shares)
+            } else {
+                (self.total_no_shares, self.total_yes_shares)
+            };
+            amm::lmsr_shares_for_cost(self.initial_liquidity, bought, other, amount_after_fee)?
+        } else {
+            let (reserve_in, reserve_out) = if is_yes {
+                (self.yes_liquidity, self.no_liquidity)
+            } else {
+                (self.no_liquidity, self.yes_liquidity)
+            };
+            let (shares, new_in, new_out) =
+                amm::swap(reserve_in, reserve_out, self.k_constant, amount_after_fee, self.precision())?;
+            if is_yes {
+                (after.yes_liquidity, after.no_liquidity) = (new_in, new_out);
+            } else {
+                (after.no_liquidity, after.yes_liquidity) = (new_in, new_out);
+            }
+            shares
+        };
+        let shares = (shares as u128)
+            .checked_mul(self.share_scale() as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if is_yes {
+            after.total_yes_shares = after.total_yes_shares.saturating_add(shares);
+        } else {
+            after.total_no_shares = after.total_no_shares.saturating_add(shares);
+        }
+        Ok(after.implied_yes_bps().abs_diff(self.implied_yes_bps()))
+    }
+
+    // Largest buy, fee included, that leaves the reserve being bought out at or above
+    // MIN_RESERVE. None for LMSR, which has no reserves to run dry
+    pub fn max_buy_lamports(&self, is_yes: bool, fee_bps: u64) -> Option<u64> {
+        if self.market_type == MarketType::Lmsr {
+            return None;
+        }
+        let reserve_in = if is_yes { self.yes_liquidity } else { self.no_liquidity };
+        let precision = self.precision();
+        // swap rounds the out side up, so it stays at MIN_RESERVE as long as
+        // new_in * (MIN_RESERVE - 1) * precision^2 < k
+        let max_new_in = self.k_constant.saturating_sub(1) / ((MIN_RESERVE - 1) as u128 * precision * precision);
+        let max_after_fee = max_new_in.saturating_sub(reserve_in as u128);
+        // The fee rounds down, so this gross amount never nets more than max_after_fee
+        let max_amount = (max_after_fee * BPS_DENOMINATOR as u128)
+            .checked_div(BPS_DENOMINATOR.saturating_sub(fee_bps) as u128)
+            .unwrap_or(0);
+        Some(max_amount.min(u64::MAX as u128) as u64)
+    }
+
+    // Implied probability of each outcome in bps, YES first. Markets are binary, so
+    // there are two and they always sum to exactly BPS_DENOMINATOR
+    pub fn outcome_prices_bps(&self) -> Vec<u16> {
+        let yes_bps = self.implied_yes_bps();
+        vec![yes_bps, BPS_DENOMINATOR as u16 - yes_bps]
+    }
+
+    // Bid-ask spread on one outcome: the share of a `probe_lamports` buy, in bps, lost by
+    // selling the shares straight back, with the fee taken both ways
+    pub fn round_trip_spread_bps(&self, is_yes: bool, probe_lamports: u64, fee_bps: u64) -> Result<u16> {
+        let take_fee = |amount: u64| amount - (amount as u128 * fee_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+        let (shares, gross_back) = if self.market_type == MarketType::Lmsr {
+            let (bought, other) = if is_yes {
+                (self.total_yes_shares, self.total_no_shares)
+            } else {
+                (self.total_no_shares, self.total_yes_shares)
+            };
+            let b = self.initial_liquidity;
+            let shares = amm::lmsr_shares_for_cost(b, bought, other, take_fee(probe_lamports))?;
+            let after = bought.checked_add(shares as u128).ok_or(ErrorCode::MathOverflow)?;
+            let back = amm::lmsr_cost(b, after, other)?.saturating_sub(amm::lmsr_cost(b, bought, other)?);
+            (shares, u64::try_from(back).map_err(|_| error!(ErrorCode::MathOverflow))?)
+        } else {
+            let (reserve_in, reserve_out) = if is_yes {
+                (self.yes_liquidity, self.no_liquidity)
+            } else {
+                (self.no_liquidity, self.yes_liquidity)
+            };
+            let precision = self.precision();
+            let (shares, new_in, new_out) =
+                amm::swap(reserve_in, reserve_out, self.k_constant, take_fee(probe_lamports), precision)?;
+            // Selling shares back pushes them into the opposite reserve
+            let (back, _, _) = amm::swap(new_out, new_in, self.k_constant, shares, precision)?;
+            (shares, back)
+        };
+        require!(shares > 0, ErrorCode::ZeroSharesOut);
+
+        let lost = probe_lamports.saturating_sub(take_fee(gross_back)) as u128;
+        let spread = lost * BPS_DENOMINATOR as u128 / probe_lamports as u128;
+        Ok(spread.min(u16::MAX as u128) as u16)
+    }
+
+    // Implied YES probability in basis points, same formula the frontend uses
+    pub fn implied_yes_bps(&self) -> u16 {
+        if self.market_type == MarketType::Lmsr {
+            return amm::lmsr_yes_price_bps(self.initial_liquidity, self.total_yes_shares, self.total_no_shares);
+        }
+        let total = self.yes_liquidity as u128 + self.no_liquidity as u128;
+        if self.yes_liquidity == 0 || self.no_liquidity == 0 {
+            return (BPS_DENOMINATOR / 2) as u16;
+        }
+        (self.yes_liquidity as u128 * BPS_DENOMINATOR as u128 / total) as u16
+    }
+
+    // Lamport value of a YES/NO holding at the implied prices
+    pub fn mark_to_market(&self, yes_shares: u64, no_shares: u64) -> Result<u64> {
+        let yes_bps = self.implied_yes_bps() as u128;
+        let no_bps = BPS_DENOMINATOR as u128 - yes_bps;
+        let value = (yes_shares as u128)
+            .checked_mul(yes_bps)
+            .and_then(|v| v.checked_add((no_shares as u128).checked_mul(no_bps)?))
+            .ok_or(ErrorCode::MathOverflow)?
+            / (BPS_DENOMINATOR as u128 * self.share_scale() as u128);
+        u64::try_from(value).map_err(|_| error!(ErrorCode::MathOverflow))
+    }
 }
 
-impl Market {
-    pub const LEN: usize = 8 + 32 + (4 + 200) + (4 + 1000) + (4 + 50)
+// Market as the original program stored it, with the display strings inline. Only
+// migrate_market reads this layout; such accounts share Market's discriminator
+#[derive(AnchorSerialize, AnchorDeserialize)]
+struct LegacyMarket {
+    market_id: u64,
+    authority: Pubkey,
+    question: String,
+    description: String,
+    category: String,
+    resolution_time: i64,
+    created_at: i64,
+    initial_liquidity: u64,
+    yes_liquidity: u64,
+    no_liquidity: u64,
+    k_constant: u128,
+    total_volume: u64,
+    resolved: bool,
+    outcome: Option<bool>,
+    total_yes_shares: u128,
+    total_no_shares: u128,
+    bump: u8,
+    vault_bump: u8,
+}
+
+impl LegacyMarket {
+    // The size those accounts were allocated with. It misses one of the u64s, but it is
+    // what sized them, so it is what identifies them
+    const LEN: usize = 8 + 32 + (4 + 200) + (4 + 1000) + (4 + 50)
         + 8 + 8 + 8 + 8 + 16 + 8 + 1 + (1 + 1)
         + 16 + 16
         + 1 + 1;
+
+    // None unless `data` is a whole account in the original layout. A current Market
+    // has resolution_time where the question length sat, far past 200
+    fn read(data: &[u8]) -> Option<LegacyMarket> {
+        if data.len() != 8 + Self::LEN || data[..8] != *Market::DISCRIMINATOR {
+            return None;
+        }
+        let question_len = u32::from_le_bytes(data[48..52].try_into().unwrap());
+        if question_len > 200 {
+            return None;
+        }
+        LegacyMarket::deserialize(&mut &data[8..]).ok()
+    }
+
+    // The current Market, left at version 0 for upgrade_market_data to fill in the
+    // fields added since, and the metadata its strings move to
+    fn split(self, metadata_bump: u8) -> Result<(Market, MarketMetadata)> {
+        let mut market = Market::try_deserialize_unchecked(&mut &vec![0u8; 8 + Market::LEN][..])?;
+        market.market_id = self.market_id;
+        market.authority = self.authority;
+        market.resolution_time = self.resolution_time;
+        market.created_at = self.created_at;
+        market.initial_liquidity = self.initial_liquidity;
+        market.yes_liquidity = self.yes_liquidity;
+        market.no_liquidity = self.no_liquidity;
+        market.k_constant = self.k_constant;
+        market.total_volume = self.total_volume;
+        market.resolved = self.resolved;
+        market.outcome = self.outcome;
+        market.total_yes_shares = self.total_yes_shares;
+        market.total_no_shares = self.total_no_shares;
+        market.bump = self.bump;
+        market.vault_bump = self.vault_bump;
+
+        let metadata = MarketMetadata {
+            market_id: self.market_id,
+            question: self.question,
+            description: self.description,
+            category: self.category,
+            bump: metadata_bump,
+        };
+        Ok((market, metadata))
+    }
+}
+
+// Write-once display data, kept out of the hot `Market` account
+#[account]
+pub struct MarketMetadata {
+    pub market_id: u64,
+    pub question: String,
+    pub description: String,
+    pub category: String,
+    pub bump: u8,
+}
+
+impl MarketMetadata {
+    pub const LEN: usize = 8 + (4 + 200) + (4 + 1000) + (4 + 50) + 1;
+}
+
+// Test builds only: the time unix_now reports while set
+#[cfg(feature = "test-clock")]
+#[account]
+pub struct MockClock {
+    pub unix_timestamp: i64,
+    pub bump: u8,
+}
+
+#[cfg(feature = "test-clock")]
+impl MockClock {
+    pub const LEN: usize = 8 + 1;
+}
+
+// Backstop for a market whose vault comes up short at settlement. Funded by
+// Config::insurance_fee_bps of every trade fee; its lamports above rent are the cover
+#[account]
+pub struct InsuranceVault {
+    pub market_id: u64,
+    pub bump: u8,
+}
+
+impl InsuranceVault {
+    pub const LEN: usize = 8 + 1;
+}
+
+// Permission for one user to buy into markets gated by `gate`. The gate is just a key
+// naming the list, so one list can cover many markets
+#[account]
+pub struct WhitelistEntry {
+    pub gate: Pubkey,
+    pub user: Pubkey,
+    pub bump: u8,
+}
+
+impl WhitelistEntry {
+    pub const LEN: usize = 32 + 32 + 1;
+}
+
+// Append-only trail of admin actions, kept as a ring buffer: once full, each new entry
+// overwrites the oldest and bumps `overwritten`. Created by the first action it records
+#[account]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+    // Slot the next entry goes to once the log is full
+    pub next: u16,
+    // Entries ever recorded, including overwritten ones
+    pub total_recorded: u64,
+    pub overwritten: u64,
+    pub bump: u8,
+}
+
+impl AuditLog {
+    pub const LEN: usize = (4 + AuditEntry::LEN * AUDIT_LOG_CAPACITY) + 2 + 8 + 8 + 1;
+
+    pub fn record(&mut self, bump: u8, entry: AuditEntry) {
+        self.bump = bump;
+        if self.entries.len() < AUDIT_LOG_CAPACITY {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next as usize] = entry;
+            self.next = ((self.next as usize + 1) % AUDIT_LOG_CAPACITY) as u16;
+            self.overwritten += 1;
+        }
+        self.total_recorded += 1;
+    }
+
+    pub fn in_order(&self) -> Vec<AuditEntry> {
+        let (newer, older) = self.entries.split_at(self.next as usize);
+        older.iter().chain(newer).cloned().collect()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub action: AuditAction,
+    pub actor: Pubkey,
+    // Zero for protocol-wide actions
+    pub market_id: u64,
+    // Lamports moved by withdrawals, sweeps and closes, the new bps for FeeSet, otherwise zero
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+impl AuditEntry {
+    pub const LEN: usize = 1 + 32 + 8 + 8 + 8;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    ResolvedYes,
+    ResolvedNo,
+    ResolvedScalar,
+    ResolutionReverted,
+    FeesWithdrawn,
+    UnclaimedSwept,
+    FeeSet,
+    MarketClosed,
+    FeeVaultClosed,
+}
+
+// Per-creator counters, used to rate-limit create_market
+#[account]
+pub struct CreatorStats {
+    pub creator: Pubkey,
+    pub markets_created: u64,
+    pub last_created_at: i64,
+    pub bump: u8,
+}
+
+impl CreatorStats {
+    pub const LEN: usize = 32 + 8 + 8 + 1;
+
+    // Counts `markets` new markets created at `now`, enforcing the create cooldown
+    pub fn record_created(
+        &mut self,
+        creator: Pubkey,
+        bump: u8,
+        create_cooldown: i64,
+        markets: u64,
+        now: i64,
+    ) -> Result<()> {
+        if self.creator == Pubkey::default() {
+            self.creator = creator;
+            self.bump = bump;
+        } else {
+            let next_create_at = self
+                .last_created_at
+                .checked_add(create_cooldown)
+                .ok_or(ErrorCode::TimeOverflow)?;
+            require!(now >= next_create_at, ErrorCode::CreateCooldownActive);
+        }
+        self.last_created_at = now;
+        self.markets_created += markets;
+        Ok(())
+    }
+}
+
+// Per-category counters, seeded by the sha256 of the category string
+#[account]
+pub struct CategoryStats {
+    pub category_hash: [u8; 32],
+    pub market_count: u64,
+    pub total_volume: u64,
+    pub bump: u8,
+}
+
+impl CategoryStats {
+    pub const LEN: usize = 32 + 8 + 8 + 1;
+
+    pub fn record_volume(&mut self, lamports: u64) -> Result<()> {
+        self.total_volume = self.total_volume
+            .checked_add(lamports)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+}
+
+// One page of market ids in a category, seeded by the category hash and page number.
+// A page holds CATEGORY_PAGE_SIZE ids; later markets overflow into page + 1
+#[account]
+pub struct CategoryIndex {
+    pub category_hash: [u8; 32],
+    pub page: u64,
+    pub market_ids: Vec<u64>,
+    pub bump: u8,
+}
+
+impl CategoryIndex {
+    pub const LEN: usize = 32 + 8 + (4 + 8 * CATEGORY_PAGE_SIZE as usize) + 1;
 }
 
 #[account]
@@ -658,10 +6460,229 @@ pub struct UserPosition {
     pub no_shares: u64,
     pub claimed: bool,
     pub bump: u8,
+    pub last_trade_time: i64,
+    // Lamports that reached the vault from this position's buys (after fees)
+    pub lamports_contributed: u64,
+    // Second key that must also sign buys above cosign_threshold; None to trade alone
+    pub cosigner: Option<Pubkey>,
+    pub cosign_threshold: u64,
 }
 
 impl UserPosition {
-    pub const LEN: usize = 32 + 8 + 8 + 8 + 1 + 1;
+    pub const LEN: usize = 32 + 8 + 8 + 8 + 1 + 1 + 8 + 8 + (1 + 32) + 8;
+
+    // Buys above the threshold need the position's cosigner among the signers
+    pub fn require_cosigner(&self, signer: Option<Pubkey>, amount_lamports: u64) -> Result<()> {
+        if let Some(cosigner) = self.cosigner {
+            if amount_lamports > self.cosign_threshold {
+                require!(signer == Some(cosigner), ErrorCode::CosignerRequired);
+            }
+        }
+        Ok(())
+    }
+
+    // Takes the share of lamports_contributed backing `shares` out of this position,
+    // pro rata over all shares held on both sides
+    pub fn release_contribution(&mut self, shares: u64) -> Result<u64> {
+        let held = self.yes_shares as u128 + self.no_shares as u128;
+        if held == 0 {
+            return Ok(0);
+        }
+        let released = (self.lamports_contributed as u128)
+            .checked_mul(shares as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            / held;
+        let released = u64::try_from(released).map_err(|_| error!(ErrorCode::MathOverflow))?;
+        self.lamports_contributed -= released;
+        Ok(released)
+    }
+
+    // Enforces the market's per-position trade cooldown and stamps this trade
+    pub fn record_trade(&mut self, market: &Market, now: i64) -> Result<()> {
+        if market.trade_cooldown > 0 && self.last_trade_time > 0 {
+            let next_trade_at = self.last_trade_time
+                .checked_add(market.trade_cooldown)
+                .ok_or(ErrorCode::TimeOverflow)?;
+            require!(now >= next_trade_at, ErrorCode::TradeCooldown);
+        }
+        self.last_trade_time = now;
+        Ok(())
+    }
+
+    pub fn winning_shares(&self, market: &Market) -> Result<u64> {
+        let shares = market.weighted_shares(self.yes_shares as u128, self.no_shares as u128)?;
+        u64::try_from(shares).map_err(|_| error!(ErrorCode::MathOverflow))
+    }
+}
+
+#[account]
+pub struct TriggerOrder {
+    pub owner: Pubkey,
+    pub market_id: u64,
+    pub order_id: u64,
+    pub is_yes: bool,
+    pub shares: u64,
+    pub trigger_price_bps: u16,
+    pub direction: TriggerDirection,
+    pub keeper_reward: u64,
+    pub bump: u8,
+}
+
+impl TriggerOrder {
+    pub const LEN: usize = 32 + 8 + 8 + 1 + 8 + 2 + 1 + 8 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayoutMode {
+    // Winning shares split the whole vault pro rata
+    Parimutuel,
+    // Each whole winning share redeems for a fixed amount while the vault can cover it;
+    // whatever is left over stays with the creator's seed
+    FixedRedemption { lamports_per_share: u64 },
+}
+
+// Over/under settlement for a scalar market: YES takes the pool above `value`, NO
+// below it, and `at_threshold` decides a settlement exactly on it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScalarThreshold {
+    pub value: i64,
+    pub at_threshold: ThresholdTie,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThresholdTie {
+    // YES and NO shares each redeem for half a winning share
+    Split,
+    // The market is cancelled and every position reclaims what it put in
+    Refund,
+}
+
+// Recorded on the market so users can weigh how much to trust an outcome
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolutionSource {
+    // The config authority or an approved resolver
+    Manual,
+    // Reserved for paths that settle from a price feed or a resolver vote
+    Oracle,
+    Committee,
+    // Voided by enable_refunds after the resolve grace period lapsed
+    AutoRefund,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarketType {
+    ConstantProduct,
+    Lmsr,
+    // Constant-product pricing, settled on a value within the bounds instead of YES/NO
+    Scalar { lower_bound: i64, upper_bound: i64 },
+}
+
+// One market's create_market arguments, as create_markets_batch takes them
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MarketParams {
+    pub market_id: u64,
+    pub question: String,
+    pub description: String,
+    pub category: String,
+    pub resolution_time: i64,
+    pub initial_liquidity_lamports: u64,
+    pub market_type: MarketType,
+    pub amm_precision: u64,
+}
+
+// Lamports a user parks for a delegated operator to trade with on their behalf
+#[account]
+pub struct TradingEscrow {
+    pub owner: Pubkey,
+    pub operator: Pubkey,
+    pub bump: u8,
+}
+
+impl TradingEscrow {
+    pub const LEN: usize = 32 + 32 + 1;
+}
+
+// Above fires as a take-profit, Below as a stop-loss, on the order's own side price
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerDirection {
+    Above,
+    Below,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MarketSummary {
+    pub market_id: u64,
+    pub question_truncated: String,
+    pub category: String,
+    pub implied_yes_bps: u16,
+    pub total_volume: u64,
+    pub resolved: bool,
+    pub outcome: Option<bool>,
+    pub resolution_time: i64,
+    pub total_claimed: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CategoryPage {
+    pub page: u64,
+    pub market_ids: Vec<u64>,
+    pub next_page: Option<u64>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PositionView {
+    pub yes_shares: u64,
+    pub no_shares: u64,
+    pub claimed: bool,
+    pub current_value_if_live: u64,
+    pub claimable_if_resolved: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SolvencyReport {
+    pub market_id: u64,
+    // Vault lamports above the rent floor
+    pub vault_balance: u64,
+    pub obligations: u64,
+    pub solvent: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FeeBreakdown {
+    pub fee: u64,
+    pub amount_after_fee: u64,
+}
+
+// Config as clients see it: bumps left out, and fee_bps is the global fee actually
+// charged, already capped by max_fee_bps
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConfigView {
+    pub authority: Pubkey,
+    pub treasury: Pubkey,
+    pub market_count: u64,
+    pub active_markets: u64,
+    pub fee_bps: u16,
+    pub max_fee_bps: u16,
+    pub insurance_fee_bps: u16,
+    pub trading_buffer: i64,
+    pub min_market_duration: i64,
+    pub max_market_duration: i64,
+    pub max_markets_per_category: u64,
+    pub create_cooldown: i64,
+    pub require_trades_to_resolve: bool,
+    pub resolvers: Vec<Pubkey>,
+    pub resolve_grace_period: i64,
+    pub revert_window: i64,
+    pub claim_window: i64,
+    pub house_fee_exempt: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AmmState {
+    pub yes_liquidity: u64,
+    pub no_liquidity: u64,
+    pub k_constant: u128,
+    pub invariant_ok: bool,
 }
 
 #[error_code]
@@ -702,16 +6723,1332 @@ pub enum ErrorCode {
     NoRemainingFunds,
     #[msg("Insufficient funds")]
     InsufficientFunds,
+    #[msg("Insufficient shares")]
+    InsufficientShares,
+    #[msg("Invalid trigger price")]
+    InvalidTriggerPrice,
+    #[msg("Trigger price not reached")]
+    TriggerNotReached,
+    #[msg("Fee vault not initialized - run initialize first")]
+    FeeVaultNotInitialized,
+    #[msg("Winning shares are still unclaimed")]
+    UnclaimedWinningsOutstanding,
+    #[msg("Invalid price cap")]
+    InvalidPriceCap,
+    #[msg("Average price exceeds the price cap")]
+    PriceCapExceeded,
+    #[msg("Invalid trading buffer")]
+    InvalidTradingBuffer,
+    #[msg("Not a valid market account")]
+    InvalidMarketAccount,
+    #[msg("Invalid cooldown")]
+    InvalidCooldown,
+    #[msg("Trade cooldown has not elapsed for this position")]
+    TradeCooldown,
+    #[msg("Category has reached its market limit")]
+    CategoryFull,
+    #[msg("Market has no trades to resolve")]
+    NoTradesToResolve,
+    #[msg("Portfolio accounts must be (market, vault, user_position) triples")]
+    InvalidPortfolioAccounts,
+    #[msg("Cannot transfer shares to yourself")]
+    SelfTransfer,
+    #[msg("Invalid market duration")]
+    InvalidMarketDuration,
+    #[msg("Resolution time is sooner than the minimum market duration")]
+    MarketDurationTooShort,
+    #[msg("House shares cannot exceed the initial liquidity")]
+    InvalidHouseShares,
+    #[msg("House position account is required to reserve house shares")]
+    HousePositionMissing,
+    #[msg("Invalid LMSR liquidity parameter")]
+    InvalidLiquidityParameter,
+    #[msg("Fee vault does not match the fee bucket")]
+    InvalidFeeVault,
+    #[msg("Invalid fee bucket")]
+    InvalidFeeBucket,
+    #[msg("Resolution time is further out than the maximum market duration")]
+    ResolutionTimeTooFar,
+    #[msg("Insufficient escrow balance")]
+    InsufficientEscrow,
+    #[msg("Evidence URI too long")]
+    EvidenceUriTooLong,
+    #[msg("Creator liquidity must be withdrawn before closing")]
+    CreatorLiquidityOutstanding,
+    #[msg("Invalid expected price")]
+    InvalidExpectedPrice,
+    #[msg("Price moved beyond the allowed tolerance")]
+    PriceMoved,
+    #[msg("Trade would drain the opposite reserve below the minimum")]
+    TradeExceedsLiquidity,
+    #[msg("Market has been cancelled")]
+    MarketCancelled,
+    #[msg("Market is not cancelled")]
+    MarketNotCancelled,
+    #[msg("Nothing to refund")]
+    NothingToRefund,
+    #[msg("Refunds are still outstanding")]
+    RefundsOutstanding,
+    #[msg("Scalar bounds must satisfy lower < upper")]
+    InvalidScalarBounds,
+    #[msg("AMM precision out of range")]
+    InvalidAmmPrecision,
+    #[msg("Fee exceeds the protocol cap")]
+    FeeTooHigh,
+    #[msg("Not supported on scalar markets")]
+    ScalarMarket,
+    #[msg("Market is not a scalar market")]
+    NotScalarMarket,
+    #[msg("Vault did not receive the full seed deposit")]
+    VaultUnderfunded,
+    #[msg("Resolve grace period cannot be negative")]
+    InvalidGracePeriod,
+    #[msg("Market is still within its resolve grace period")]
+    ResolveDeadlineNotReached,
+    #[msg("Oracle price is too old")]
+    StaleOracle,
+    #[msg("Oracle price is non-positive or its confidence interval is too wide")]
+    UnreliableOracle,
+    #[msg("Config account is not the config PDA")]
+    MisderivedConfig,
+    #[msg("Market account does not match its market id and bump")]
+    MisderivedMarket,
+    #[msg("Vault is not the PDA for this market")]
+    MisderivedVault,
+    #[msg("Category stats account is not the PDA for this market's category")]
+    MisderivedCategoryStats,
+    #[msg("User position is not the PDA for this user and market")]
+    MisderivedUserPosition,
+    #[msg("Resolver is already whitelisted")]
+    ResolverExists,
+    #[msg("Resolver whitelist is full")]
+    TooManyResolvers,
+    #[msg("Resolver is not whitelisted")]
+    ResolverNotFound,
+    #[msg("Max price move cannot exceed 10000 bps")]
+    InvalidMaxPriceMove,
+    #[msg("Trade moves the price more than the market allows; split the order")]
+    CircuitBreakerTripped,
+    #[msg("Revert window cannot be negative")]
+    InvalidRevertWindow,
+    #[msg("Revert window for this resolution has passed")]
+    RevertWindowClosed,
+    #[msg("Payouts have already started for this resolution")]
+    SettlementStarted,
+    #[msg("Fee vault still receives fees from open markets")]
+    FeeVaultInUse,
+    #[msg("Share decimals must be at most 3 and are not supported on LMSR markets")]
+    InvalidShareDecimals,
+    #[msg("Market already has shares outstanding")]
+    MarketAlreadyTraded,
+    #[msg("Trade would push the market past its volume cap")]
+    MarketVolumeCapReached,
+    #[msg("Creation cooldown cannot be negative")]
+    InvalidCreateCooldown,
+    #[msg("Creator must wait out the cooldown before creating another market")]
+    CreateCooldownActive,
+    #[msg("Trade is above the position's cosign threshold and needs its cosigner")]
+    CosignerRequired,
+    #[msg("Trading on this market has been closed")]
+    TradingClosed,
+    #[msg("Trading cannot be closed before the market's trading cutoff")]
+    TradingStillOpen,
+    #[msg("Fixed redemption must pay a positive amount per share")]
+    InvalidPayoutMode,
+    #[msg("Only constant-product pools can be topped up")]
+    LiquidityTopUpUnsupported,
+    #[msg("Trade is too small to buy any shares")]
+    ZeroSharesOut,
+    #[msg("Market is gated and the buyer is not on its whitelist")]
+    NotWhitelisted,
+    #[msg("Trade would move the price further than the market's slippage limit")]
+    MarketSlippageExceeded,
+    #[msg("Insurance fee cannot exceed 10000 bps of the trade fee")]
+    InvalidInsuranceFee,
+    #[msg("This trade pays into insurance and needs the market's insurance vault")]
+    InsuranceVaultRequired,
+    #[msg("Market is already in that category")]
+    CategoryUnchanged,
+    #[msg("Category index page does not list this market")]
+    MarketNotInCategoryPage,
+    #[msg("Timestamp arithmetic overflowed")]
+    TimeOverflow,
+    #[msg("Trade would leave the pool below its constant-product invariant")]
+    InvariantViolation,
+    #[msg("Treasury does not match the configured treasury")]
+    InvalidTreasury,
+    #[msg("A batch must create between one and MAX_BATCH_MARKETS markets")]
+    InvalidBatchSize,
+    #[msg("Batch accounts are missing or out of order")]
+    BatchAccountMismatch,
+    #[msg("Claim window must be zero or at least MIN_CLAIM_WINDOW")]
+    InvalidClaimWindow,
+    #[msg("The claim deadline for this market has passed")]
+    ClaimDeadlinePassed,
+    #[msg("The claim deadline for this market has not passed yet")]
+    ClaimDeadlineNotReached,
+    #[msg("Market has no claim deadline")]
+    NoClaimDeadline,
+    #[msg("The market's resolution callback program was not passed in")]
+    ResolutionCallbackMissing,
+    #[msg("The question cannot be edited once the market has traded")]
+    QuestionLocked,
+    #[msg("Config account is not in a known layout")]
+    InvalidConfigAccount,
+    #[msg("Migration accounts are missing or are not the market's PDAs")]
+    MigrationAccountMismatch,
+    #[msg("Oracle price is timestamped after the current time")]
+    OracleFromFuture,
+    #[msg("The vault could not cover selling back every outstanding share after this rebalance")]
+    RebalanceUndercollateralized,
+    #[msg("Scalar threshold must lie strictly between the market's bounds")]
+    InvalidScalarThreshold,
+    #[msg("A market without an insurance vault has lamports at the vault's address")]
+    InsuranceVaultNotEmpty,
+}
+
+// Event layouts are versioned so indexers can tell them apart across upgrades:
+// - The discriminator is the first 8 bytes of sha256("event:<StructName>"), so event
+//   structs are never renamed. A breaking redesign gets a new struct name instead.
+// - `version` is always the first field, readable right after the discriminator
+//   before decoding the rest. Bump the struct's VERSION whenever its fields change.
+// - Fields are only appended, so a decoder for version N reads the prefix of N + 1.
+#[event]
+pub struct MarketCreatedEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub authority: Pubkey,
+    pub category: String,
+    pub resolution_time: i64,
+    pub initial_liquidity: u64,
+    pub created_at: i64,
+}
+
+impl MarketCreatedEvent {
+    pub const VERSION: u8 = 1;
+}
+
+#[event]
+pub struct MarketResolvedEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub outcome: bool,
+    pub evidence_hash: [u8; 32],
+    pub evidence_uri: String,
+    pub resolution_source: ResolutionSource,
+    pub timestamp: i64,
+}
+
+impl MarketResolvedEvent {
+    pub const VERSION: u8 = 2;
+}
+
+#[event]
+pub struct ScalarResolvedEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub settlement_value: i64,
+    pub yes_payout_bps: u16,
+    pub timestamp: i64,
+}
+
+impl ScalarResolvedEvent {
+    pub const VERSION: u8 = 1;
+}
+
+#[event]
+pub struct BuySharesEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub user: Pubkey,
+    pub is_yes: bool,
+    pub shares: u64,
+    pub yes_liquidity: u64,
+    pub no_liquidity: u64,
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+impl BuySharesEvent {
+    pub const VERSION: u8 = 1;
 }
 
 #[event]
-pub struct BuySharesEvent {
+pub struct SellSharesEvent {
+    pub version: u8,
     pub market_pubkey: Pubkey,
     pub market_id: u64,
     pub user: Pubkey,
     pub is_yes: bool,
     pub shares: u64,
+    pub lamports_out: u64,
     pub yes_liquidity: u64,
     pub no_liquidity: u64,
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+impl SellSharesEvent {
+    pub const VERSION: u8 = 1;
+}
+
+#[event]
+pub struct ResolutionRevertedEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub timestamp: i64,
+}
+
+impl ResolutionRevertedEvent {
+    pub const VERSION: u8 = 1;
+}
+
+#[event]
+pub struct TradingClosedEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub timestamp: i64,
+}
+
+impl TradingClosedEvent {
+    pub const VERSION: u8 = 1;
+}
+
+#[event]
+pub struct UnclaimedSweptEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub shares: u64,
+    pub lamports: u64,
+    pub timestamp: i64,
+}
+
+impl UnclaimedSweptEvent {
+    pub const VERSION: u8 = 1;
+}
+
+#[event]
+pub struct ClaimWinningsEvent {
+    pub version: u8,
+    pub market_pubkey: Pubkey,
+    pub market_id: u64,
+    pub user: Pubkey,
+    pub payout: u64,
+    pub total_claimed: u64,
     pub timestamp: i64,
-}
\ No newline at end of file
+}
+
+impl ClaimWinningsEvent {
+    pub const VERSION: u8 = 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_market() -> Market {
+        Market {
+            market_id: 42,
+            authority: Pubkey::new_unique(),
+            resolution_time: 1_700_000_000,
+            created_at: 1_699_990_000,
+            initial_liquidity: 100_000_000,
+            yes_liquidity: 120_000_000,
+            no_liquidity: 83_333_333,
+            k_constant: 100_000_000u128 * 100_000_000 * PRECISION * PRECISION,
+            total_volume: 25_000_000,
+            resolved: false,
+            outcome: None,
+            total_yes_shares: 16_666_667,
+            total_no_shares: 0,
+            bump: 254,
+            vault_bump: 253,
+            payout_per_share: 0,
+            creator: Pubkey::new_unique(),
+            creator_deposit: 200_000_000,
+            version: MARKET_VERSION,
+            trade_cooldown: 0,
+            category_hash: hash(b"Crypto").to_bytes(),
+            market_type: MarketType::ConstantProduct,
+            trade_sequence: 7,
+            evidence_hash: [0; 32],
+            evidence_uri: String::new(),
+            cancelled: false,
+            total_contributed: 0,
+            settlement_value: None,
+            amm_precision: PRECISION as u64,
+            fee_bps: None,
+            max_price_move_bps: 0,
+            total_claimed: 0,
+            resolved_at: 0,
+            share_decimals: 0,
+            max_total_volume: 0,
+            trading_closed: false,
+            payout_mode: PayoutMode::Parimutuel,
+            access_gate: None,
+            max_slippage_bps: 0,
+            resolution_source: ResolutionSource::Manual,
+            claim_deadline: 0,
+            resolution_callback: None,
+            callback_required: false,
+            scalar_threshold: None,
+        }
+    }
+
+    fn sample_config() -> Config {
+        Config {
+            authority: Pubkey::default(),
+            market_count: 0,
+            fee_percentage: 0,
+            bump: 0,
+            fee_vault_bump: 0,
+            trading_buffer: 0,
+            max_markets_per_category: 0,
+            require_trades_to_resolve: false,
+            min_market_duration: 0,
+            max_market_duration: 0,
+            max_fee_bps: 0,
+            resolve_grace_period: 0,
+            resolvers: Vec::new(),
+            revert_window: 0,
+            active_markets: 0,
+            create_cooldown: 0,
+            insurance_fee_bps: 0,
+            treasury: Pubkey::default(),
+            claim_window: 0,
+            house_fee_exempt: false,
+        }
+    }
+
+    #[test]
+    fn migrate_config_defaults_fields_a_legacy_layout_predates() {
+        let authority = Pubkey::new_unique();
+        let mut baseline = Config::DISCRIMINATOR.to_vec();
+        (authority, 7u64, 150u16, 254u8, 253u8).serialize(&mut baseline).unwrap();
+        assert_eq!(baseline.len(), 8 + 32 + 8 + 2 + 1 + 1);
+
+        let config = read_stored_config(&baseline).unwrap();
+        assert_eq!(config.authority, authority);
+        assert_eq!(config.market_count, 7);
+        assert_eq!(config.fee_percentage, 150);
+        assert_eq!((config.bump, config.fee_vault_bump), (254, 253));
+        assert_eq!(config.max_fee_bps, 1_000);
+        assert_eq!(config.resolve_grace_period, DEFAULT_RESOLVE_GRACE_PERIOD);
+        assert_eq!(config.treasury, authority);
+        assert!(config.resolvers.is_empty());
+
+        // A layout that ended at revert_window, holding one resolver; the bytes after
+        // it are junk the decoder must not read as the fields that came later
+        let mut current = sample_config();
+        current.authority = authority;
+        current.max_fee_bps = 0;
+        current.resolve_grace_period = 0;
+        current.resolvers = vec![Pubkey::new_unique()];
+        current.revert_window = 60;
+        current.active_markets = 99;
+        current.treasury = Pubkey::new_unique();
+        let mut data = Vec::new();
+        current.try_serialize(&mut data).unwrap();
+        data.resize(8 + Config::LEN, 0xAB);
+        let through_revert_window = 32 + 8 + 2 + 1 + 1 + 8 + 8 + 1 + 8 + 8 + 2 + 8 + (4 + 32 * MAX_RESOLVERS) + 8;
+        data.truncate(8 + through_revert_window);
+
+        let config = read_stored_config(&data).unwrap();
+        assert_eq!(config.max_fee_bps, 0);
+        assert_eq!(config.resolve_grace_period, 0);
+        assert_eq!(config.resolvers, current.resolvers);
+        assert_eq!(config.revert_window, 60);
+        assert_eq!(config.active_markets, 0);
+        assert_eq!(config.treasury, authority);
+
+        // A current layout reads back unchanged
+        data.resize(8 + Config::LEN, 0);
+        current.try_serialize(&mut &mut data[..]).unwrap();
+        let config = read_stored_config(&data).unwrap();
+        assert_eq!((config.active_markets, config.treasury), (99, current.treasury));
+    }
+
+    #[test]
+    fn migrate_upgrades_legacy_market_layout() {
+        let mut legacy = sample_market();
+        legacy.creator = Pubkey::default();
+        legacy.creator_deposit = 0;
+        legacy.version = 0;
+
+        let mut data = Vec::new();
+        legacy.try_serialize(&mut data).unwrap();
+
+        // Accounts from after the metadata split but before v1 end at payout_per_share
+        data.truncate(
+            data.len() - (32 + 8 + 1 + 8 + 32 + 1 + 8 + 32 + 4 + 1 + 8 + 1 + 8 + 1 + 2 + 8 + 8 + 1 + 8 + 1 + 1 + 1 + 2 + 1 + 8 + 1 + 1 + 1),
+        );
+        data.resize(8 + Market::LEN, 0);
+
+        assert!(upgrade_market_data(&mut data).unwrap());
+
+        let migrated = Market::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(migrated.version, MARKET_VERSION);
+        assert_eq!(migrated.creator, legacy.authority);
+        assert_eq!(migrated.creator_deposit, legacy.initial_liquidity * 2);
+        assert_eq!(migrated.market_id, legacy.market_id);
+        assert_eq!(migrated.yes_liquidity, legacy.yes_liquidity);
+        assert_eq!(migrated.no_liquidity, legacy.no_liquidity);
+        assert_eq!(migrated.k_constant, legacy.k_constant);
+        assert_eq!(migrated.amm_precision, PRECISION as u64);
+        assert_eq!(migrated.total_yes_shares, legacy.total_yes_shares);
+        assert_eq!(migrated.vault_bump, legacy.vault_bump);
+
+        // Running it again is a no-op
+        assert!(!upgrade_market_data(&mut data).unwrap());
+    }
+
+    fn sample_legacy_market() -> LegacyMarket {
+        LegacyMarket {
+            market_id: 9,
+            authority: Pubkey::new_unique(),
+            question: "Will it rain tomorrow?".to_string(),
+            description: "Resolves YES on any measurable rain".to_string(),
+            category: "Weather".to_string(),
+            resolution_time: 1_700_000_000,
+            created_at: 1_699_990_000,
+            initial_liquidity: 100_000_000,
+            yes_liquidity: 120_000_000,
+            no_liquidity: 83_333_334,
+            k_constant: 100_000_000u128 * 100_000_000 * PRECISION * PRECISION,
+            total_volume: 25_000_000,
+            resolved: false,
+            outcome: None,
+            total_yes_shares: 16_666_666,
+            total_no_shares: 0,
+            bump: 254,
+            vault_bump: 253,
+        }
+    }
+
+    #[test]
+    fn migrate_splits_the_original_market_layout() {
+        let legacy = sample_legacy_market();
+        let mut data = Market::DISCRIMINATOR.to_vec();
+        legacy.serialize(&mut data).unwrap();
+        data.resize(8 + LegacyMarket::LEN, 0);
+
+        let (market, metadata) = LegacyMarket::read(&data).unwrap().split(252).unwrap();
+        assert_eq!(metadata.market_id, 9);
+        assert_eq!(metadata.question, legacy.question);
+        assert_eq!(metadata.description, legacy.description);
+        assert_eq!(metadata.category, legacy.category);
+        assert_eq!(metadata.bump, 252);
+
+        let mut data = vec![0u8; 8 + Market::LEN];
+        market.try_serialize(&mut &mut data[..]).unwrap();
+        assert!(upgrade_market_data(&mut data).unwrap());
+
+        let migrated = Market::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(migrated.version, MARKET_VERSION);
+        assert_eq!(migrated.market_id, 9);
+        assert_eq!(migrated.authority, legacy.authority);
+        assert_eq!(migrated.creator, legacy.authority);
+        assert_eq!(migrated.creator_deposit, legacy.initial_liquidity * 2);
+        assert_eq!(migrated.resolution_time, legacy.resolution_time);
+        assert_eq!(migrated.created_at, legacy.created_at);
+        assert_eq!((migrated.yes_liquidity, migrated.no_liquidity), (120_000_000, 83_333_334));
+        assert_eq!(migrated.k_constant, legacy.k_constant);
+        assert_eq!(migrated.total_volume, legacy.total_volume);
+        assert_eq!(migrated.total_yes_shares, legacy.total_yes_shares);
+        assert_eq!((migrated.bump, migrated.vault_bump), (254, 253));
+        assert_eq!(migrated.amm_precision, PRECISION as u64);
+        assert_eq!(migrated.payout_per_share, 0);
+
+        // Current accounts are never mistaken for the original layout
+        let mut current = Vec::new();
+        sample_market().try_serialize(&mut current).unwrap();
+        current.resize(8 + LegacyMarket::LEN, 0);
+        assert!(LegacyMarket::read(&current).is_none());
+    }
+
+    #[test]
+    fn migrated_market_is_filed_under_its_category_and_trades() {
+        let (market, metadata) = sample_legacy_market().split(252).unwrap();
+        let mut data = vec![0u8; 8 + Market::LEN];
+        market.try_serialize(&mut &mut data[..]).unwrap();
+        upgrade_market_data(&mut data).unwrap();
+        let mut market = Market::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(market.category_hash, [0; 32]);
+
+        // The category already holds a full page, so the market lands on the next one
+        let mut stats = CategoryStats {
+            category_hash: hash(b"Weather").to_bytes(),
+            market_count: CATEGORY_PAGE_SIZE,
+            total_volume: 0,
+            bump: 250,
+        };
+        let mut index = CategoryIndex { category_hash: [0; 32], page: 0, market_ids: Vec::new(), bump: 0 };
+        file_under_category(&sample_config(), &mut market, &metadata.category, &mut stats, 250, &mut index, 249).unwrap();
+
+        assert_eq!(market.category_hash, hash(b"Weather").to_bytes());
+        assert_eq!(stats.market_count, CATEGORY_PAGE_SIZE + 1);
+        assert_eq!((index.page, index.market_ids.clone(), index.bump), (1, vec![9], 249));
+
+        // The category_stats seeds the trading contexts check now resolve, and the
+        // carried-over pool prices a buy
+        let (by_market, _) = Pubkey::find_program_address(&[CATEGORY_STATS_SEED, &market.category_hash], &crate::ID);
+        let (by_name, _) = Pubkey::find_program_address(&[CATEGORY_STATS_SEED, hash(b"Weather").as_ref()], &crate::ID);
+        assert_eq!(by_market, by_name);
+        let (shares, new_in, _) = amm::swap(
+            market.no_liquidity,
+            market.yes_liquidity,
+            market.k_constant,
+            10_000_000,
+            market.precision(),
+        )
+        .unwrap();
+        assert!(shares > 0);
+        assert_eq!(new_in, market.no_liquidity + 10_000_000);
+    }
+
+    #[test]
+    fn lmsr_prices_match_expected_values() {
+        let b = 100_000_000;
+        assert_eq!(amm::lmsr_yes_price_bps(b, 0, 0), 5000);
+
+        // q_yes - q_no = b ln 3 puts YES at 3 / (3 + 1) = 75%, give or take truncation
+        let q_yes = 109_861_229;
+        assert!((7499..=7500).contains(&amm::lmsr_yes_price_bps(b, q_yes, 0)));
+        assert!((2499..=2500).contains(&amm::lmsr_yes_price_bps(b, 0, q_yes)));
+
+        // An empty book costs b ln 2, the bound the vault is seeded with
+        let mut market = sample_market();
+        market.market_type = MarketType::Lmsr;
+        market.initial_liquidity = b;
+        let empty_cost = amm::lmsr_cost(b, 0, 0).unwrap();
+        assert!((empty_cost as i128 - market.seed_deposit().unwrap() as i128).abs() <= 1);
+    }
+
+    #[test]
+    fn dust_position_pays_zero_instead_of_failing() {
+        let mut market = sample_market();
+        market.resolved = true;
+        market.outcome = Some(true);
+
+        // 10 of 16_666_667 winning shares against 1_000_000 lamports truncates to 0
+        assert_eq!(market.payout_for_shares(10, 1_000_000).unwrap(), 0);
+
+        // The final holder still sweeps everything that is left
+        let all = market.total_yes_shares as u64;
+        assert_eq!(market.payout_for_shares(all, 1_000_000).unwrap(), 1_000_000);
+    }
+
+    struct RentStubs;
+
+    impl solana_sysvar::program_stubs::SyscallStubs for RentStubs {
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe { *(var_addr as *mut Rent) = Rent::default() };
+            0
+        }
+    }
+
+    #[test]
+    fn dust_position_settles_for_nothing() {
+        solana_sysvar::program_stubs::set_syscall_stubs(Box::new(RentStubs));
+        let rent_floor = Rent::default().minimum_balance(0);
+
+        // Share decimals make positions this small common: 10 of 16_666_667 winning
+        // shares are owed less than a lamport of the vault's distributable balance
+        let mut market = sample_market();
+        market.resolved = true;
+        market.outcome = Some(true);
+        market.share_decimals = 3;
+        let mut position = UserPosition {
+            user: Pubkey::new_unique(),
+            market_id: market.market_id,
+            yes_shares: 10,
+            no_shares: 4,
+            claimed: false,
+            bump: 255,
+            last_trade_time: 0,
+            lamports_contributed: 1,
+            cosigner: None,
+            cosign_threshold: 0,
+        };
+        market.total_no_shares = 4;
+
+        let program_id = crate::ID;
+        let system_id = system_program::ID;
+        let (vault_key, user_key) = (Pubkey::new_unique(), position.user);
+        let (mut vault_lamports, mut user_lamports, mut system_lamports) = (rent_floor + 1_000_000, 5, 1);
+        let (mut vault_data, mut user_data, mut system_data) = (vec![], vec![], vec![]);
+        let vault = AccountInfo::new(&vault_key, false, true, &mut vault_lamports, &mut vault_data, &program_id, false, 0);
+        let user = AccountInfo::new(&user_key, true, true, &mut user_lamports, &mut user_data, &system_id, false, 0);
+        let system = AccountInfo::new(&system_id, false, false, &mut system_lamports, &mut system_data, &system_id, true, 0);
+
+        let payout = settle_position(&mut market, &mut position, &vault, &user, None, &system).unwrap();
+
+        assert_eq!(payout, 0);
+        assert_eq!((position.yes_shares, position.no_shares), (0, 0));
+        assert!(position.claimed);
+        assert_eq!(market.total_yes_shares, 16_666_667 - 10);
+        // Losing shares never counted toward the winners' pool
+        assert_eq!(market.total_no_shares, 4);
+        assert_eq!(market.total_claimed, 0);
+        assert_eq!(vault.lamports(), rent_floor + 1_000_000);
+        assert_eq!(user.lamports(), 5);
+    }
+
+    #[test]
+    fn scalar_thresholds_settle_over_under_with_a_configured_tie() {
+        let mut market = sample_market();
+        market.market_type = MarketType::Scalar { lower_bound: 0, upper_bound: 100 };
+        market.resolved = true;
+        market.scalar_threshold = Some(ScalarThreshold { value: 40, at_threshold: ThresholdTie::Split });
+
+        market.settlement_value = Some(41);
+        assert_eq!(market.payout_weights().unwrap(), (10_000, 0));
+        market.settlement_value = Some(39);
+        assert_eq!(market.payout_weights().unwrap(), (0, 10_000));
+
+        // Exactly on the threshold a split pays both sides half
+        market.settlement_value = Some(40);
+        assert!(!market.refunds_at(40));
+        assert_eq!(market.payout_weights().unwrap(), (5_000, 5_000));
+        assert_eq!(market.weighted_shares(1_001, 3_000).unwrap(), 2_000);
+
+        // A refund tie cancels instead, and only exactly on the threshold
+        market.scalar_threshold = Some(ScalarThreshold { value: 40, at_threshold: ThresholdTie::Refund });
+        assert!(market.refunds_at(40));
+        assert!(!market.refunds_at(41));
+        assert_eq!(market.payout_weights().unwrap_err(), ErrorCode::MarketCancelled.into());
+        market.settlement_value = Some(41);
+        assert_eq!(market.payout_weights().unwrap(), (10_000, 0));
+    }
+
+    #[test]
+    fn scalar_weights_follow_the_settlement() {
+        let mut market = sample_market();
+        market.market_type = MarketType::Scalar { lower_bound: -100, upper_bound: 300 };
+        market.resolved = true;
+
+        market.settlement_value = Some(100);
+        assert_eq!(market.payout_weights().unwrap(), (5_000, 5_000));
+        assert_eq!(market.weighted_shares(1_001, 3_000).unwrap(), 2_000);
+
+        market.settlement_value = Some(300);
+        assert_eq!(market.payout_weights().unwrap(), (10_000, 0));
+        assert_eq!(market.weighted_shares(1_001, 3_000).unwrap(), 1_001);
+
+        // Binary markets keep paying the winning side one for one
+        market.market_type = MarketType::ConstantProduct;
+        market.settlement_value = None;
+        market.outcome = Some(false);
+        assert_eq!(market.payout_weights().unwrap(), (0, 10_000));
+    }
+
+    #[test]
+    fn rebalancing_top_up_moves_a_skewed_pool_toward_even_odds() {
+        let (yes, no) = (120_000_000, 83_333_334);
+
+        // Proportional keeps the ratio; rebalancing lifts the thin side first
+        let (p_yes, p_no) = proportional_reserves(yes, no, 10_000_000).unwrap();
+        assert_eq!((p_yes, p_no), (125_901_639, 87_431_694));
+        let (r_yes, r_no) = rebalanced_reserves(yes, no, 10_000_000).unwrap();
+        assert_eq!((r_yes, r_no), (120_000_000, 93_333_334));
+        assert!(r_yes - r_no < p_yes - p_no);
+
+        // Past the gap the rest splits evenly, and the pool never tips the other way
+        assert_eq!(rebalanced_reserves(yes, no, 36_666_667).unwrap(), (120_000_000, 120_000_001));
+        assert_eq!(rebalanced_reserves(no, yes, 36_666_667).unwrap(), (120_000_001, 120_000_000));
+
+        // Both deposits are solvent against a vault holding the reserves plus the
+        // lamports traders paid for the outstanding shares
+        let outstanding = 16_666_667u64;
+        for (yes_liquidity, no_liquidity, deposit) in [(p_yes, p_no, 10_000_000), (r_yes, r_no, 9_950_000)] {
+            let mut market = sample_market();
+            market.yes_liquidity = yes_liquidity;
+            market.no_liquidity = no_liquidity;
+            market.k_constant = yes_liquidity as u128 * no_liquidity as u128 * PRECISION * PRECISION;
+            market.total_yes_shares = outstanding as u128;
+            market.total_no_shares = 0;
+            let vault = yes + no + outstanding + deposit;
+            market.require_sell_back_covered(vault).unwrap();
+            assert_eq!(
+                market.require_sell_back_covered(1_000).unwrap_err(),
+                ErrorCode::RebalanceUndercollateralized.into()
+            );
+        }
+    }
+
+    #[test]
+    fn oracle_prices_must_be_fresh_and_tight() {
+        let now = 1_700_000_000;
+
+        // Published 10s ago with a 0.5% band, inside a 60s / 1% policy
+        assert_eq!(
+            validate_oracle_price(2_000_000, now - 10, 10_000, 60, 100, now).unwrap(),
+            2_000_000
+        );
+        // Exactly at the limits is still accepted
+        assert_eq!(
+            validate_oracle_price(2_000_000, now - 60, 20_000, 60, 100, now).unwrap(),
+            2_000_000
+        );
+
+        assert_eq!(
+            validate_oracle_price(2_000_000, now - 61, 10_000, 60, 100, now).unwrap_err(),
+            ErrorCode::StaleOracle.into()
+        );
+        // A publish time past `now` would otherwise read as perfectly fresh
+        assert_eq!(
+            validate_oracle_price(2_000_000, now + 1, 10_000, 60, 100, now).unwrap_err(),
+            ErrorCode::OracleFromFuture.into()
+        );
+        assert_eq!(
+            validate_oracle_price(2_000_000, now - 10, 20_001, 60, 100, now).unwrap_err(),
+            ErrorCode::UnreliableOracle.into()
+        );
+        assert_eq!(
+            validate_oracle_price(0, now, 0, 60, 100, now).unwrap_err(),
+            ErrorCode::UnreliableOracle.into()
+        );
+    }
+
+    #[test]
+    fn resolution_countdown_goes_negative_once_past() {
+        let mut market = sample_market();
+        market.resolution_time = 1_700_000_000;
+        assert_eq!(market.seconds_to_resolution(1_699_999_000).unwrap(), 1_000);
+        assert_eq!(market.seconds_to_resolution(1_700_000_000).unwrap(), 0);
+        assert_eq!(market.seconds_to_resolution(1_700_000_042).unwrap(), -42);
+        assert_eq!(
+            market.seconds_to_resolution(i64::MIN).unwrap_err(),
+            ErrorCode::TimeOverflow.into()
+        );
+    }
+
+    #[test]
+    fn refund_deadline_rejects_overflowing_grace_periods() {
+        let resolution_time = i64::MAX - 3_600;
+        assert_eq!(refund_deadline(resolution_time, 3_600).unwrap(), i64::MAX);
+        assert_eq!(
+            refund_deadline(resolution_time, 3_601).unwrap_err(),
+            ErrorCode::TimeOverflow.into()
+        );
+        assert_eq!(
+            refund_deadline(i64::MAX, 86_400).unwrap_err(),
+            ErrorCode::TimeOverflow.into()
+        );
+        assert_eq!(refund_deadline(1_700_000_000, 86_400).unwrap(), 1_700_086_400);
+    }
+
+    fn serialized_len<T: AccountSerialize>(account: &T) -> usize {
+        let mut data = Vec::new();
+        account.try_serialize(&mut data).unwrap();
+        data.len()
+    }
+
+    // Every variable-size field is filled to its maximum, so the serialized length is
+    // exactly what `8 + LEN` must reserve
+    #[test]
+    fn account_lens_match_the_largest_serialized_layout() {
+        let mut market = sample_market();
+        market.outcome = Some(true); // Option<bool>: 1 tag byte + 1 value byte
+        market.market_type = MarketType::Scalar { lower_bound: i64::MIN, upper_bound: i64::MAX };
+        market.evidence_uri = "x".repeat(EVIDENCE_URI_LEN);
+        market.settlement_value = Some(0);
+        market.fee_bps = Some(0);
+        market.payout_mode = PayoutMode::FixedRedemption { lamports_per_share: 0 };
+        market.access_gate = Some(Pubkey::default());
+        market.resolution_callback = Some(Pubkey::default());
+        market.scalar_threshold = Some(ScalarThreshold { value: 0, at_threshold: ThresholdTie::Split });
+        assert_eq!(serialized_len(&market), 8 + Market::LEN);
+
+        let metadata = MarketMetadata {
+            market_id: 0,
+            question: "q".repeat(200),
+            description: "d".repeat(1000),
+            category: "c".repeat(50),
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&metadata), 8 + MarketMetadata::LEN);
+
+        let config = Config {
+            resolvers: vec![Pubkey::default(); MAX_RESOLVERS],
+            ..sample_config()
+        };
+        assert_eq!(serialized_len(&config), 8 + Config::LEN);
+
+        let entry = AuditEntry {
+            action: AuditAction::FeeSet,
+            actor: Pubkey::default(),
+            market_id: 0,
+            amount: 0,
+            timestamp: 0,
+        };
+        let audit_log = AuditLog {
+            entries: vec![entry; AUDIT_LOG_CAPACITY],
+            next: 0,
+            total_recorded: 0,
+            overwritten: 0,
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&audit_log), 8 + AuditLog::LEN);
+
+        let position = UserPosition {
+            user: Pubkey::default(),
+            market_id: 0,
+            yes_shares: 0,
+            no_shares: 0,
+            claimed: false,
+            bump: 0,
+            last_trade_time: 0,
+            lamports_contributed: 0,
+            cosigner: Some(Pubkey::default()),
+            cosign_threshold: 0,
+        };
+        assert_eq!(serialized_len(&position), 8 + UserPosition::LEN);
+
+        let stats = CategoryStats {
+            category_hash: [0; 32],
+            market_count: 0,
+            total_volume: 0,
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&stats), 8 + CategoryStats::LEN);
+
+        let creator = CreatorStats {
+            creator: Pubkey::default(),
+            markets_created: 0,
+            last_created_at: 0,
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&creator), 8 + CreatorStats::LEN);
+
+        let entry = WhitelistEntry {
+            gate: Pubkey::default(),
+            user: Pubkey::default(),
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&entry), 8 + WhitelistEntry::LEN);
+
+        let index = CategoryIndex {
+            category_hash: [0; 32],
+            page: 0,
+            market_ids: vec![0; CATEGORY_PAGE_SIZE as usize],
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&index), 8 + CategoryIndex::LEN);
+
+        let order = TriggerOrder {
+            owner: Pubkey::default(),
+            market_id: 0,
+            order_id: 0,
+            is_yes: false,
+            shares: 0,
+            trigger_price_bps: 0,
+            direction: TriggerDirection::Above,
+            keeper_reward: 0,
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&order), 8 + TriggerOrder::LEN);
+
+        let escrow = TradingEscrow {
+            owner: Pubkey::default(),
+            operator: Pubkey::default(),
+            bump: 0,
+        };
+        assert_eq!(serialized_len(&escrow), 8 + TradingEscrow::LEN);
+    }
+
+    #[test]
+    fn solvency_compares_the_vault_with_outstanding_obligations() {
+        let mut market = sample_market();
+        market.total_no_shares = 4_000_000;
+
+        // Unresolved: either side may win, so the larger side is owed
+        let report = market.solvency(20_000_000).unwrap();
+        assert_eq!(report.obligations, 16_666_667);
+        assert!(report.solvent);
+
+        // A share count the vault cannot back, as a logic bug would leave behind
+        market.total_yes_shares = 25_000_000;
+        let report = market.solvency(20_000_000).unwrap();
+        assert_eq!(report.obligations, 25_000_000);
+        assert!(!report.solvent);
+
+        // Once resolved only the winning side counts
+        market.resolved = true;
+        market.outcome = Some(false);
+        assert!(market.solvency(20_000_000).unwrap().solvent);
+
+        // Cancelled markets owe refunds of what was contributed
+        market.cancelled = true;
+        market.total_contributed = 30_000_000;
+        assert!(!market.solvency(20_000_000).unwrap().solvent);
+    }
+
+    #[test]
+    fn fine_shares_pay_out_like_lamport_shares() {
+        // Two winners holding a third and two thirds of the YES side
+        let holdings = [3_333_334u64, 6_666_667];
+        let settle = |share_decimals: u8| {
+            let mut market = sample_market();
+            market.share_decimals = share_decimals;
+            market.resolved = true;
+            market.outcome = Some(true);
+            let scale = market.share_scale();
+            market.total_yes_shares = holdings.iter().map(|h| (h * scale) as u128).sum();
+
+            let mut vault = 20_000_001u64;
+            holdings.map(|held| {
+                let shares = held * scale;
+                market.snapshot_payout_per_share(vault).unwrap();
+                let payout = market.payout_for_shares(shares, vault).unwrap();
+                vault -= payout;
+                market.total_yes_shares -= shares as u128;
+                payout
+            })
+        };
+
+        let coarse = settle(0);
+        assert_eq!(coarse.iter().sum::<u64>(), 20_000_001);
+        assert_eq!(settle(MAX_SHARE_DECIMALS), coarse);
+
+        // A position that only exists below a lamport still earns its pro rata share
+        let mut market = sample_market();
+        market.share_decimals = MAX_SHARE_DECIMALS;
+        market.resolved = true;
+        market.outcome = Some(true);
+        market.total_yes_shares = 10_000_000_000;
+        assert_eq!(market.payout_for_shares(500, 20_000_000).unwrap(), 1);
+    }
+
+    #[test]
+    fn fixed_redemption_pays_its_rate_until_the_vault_runs_short() {
+        let holdings = [4_000_000u64, 6_000_000];
+        let settle = |payout_mode: PayoutMode| {
+            let mut market = sample_market();
+            market.payout_mode = payout_mode;
+            market.resolved = true;
+            market.outcome = Some(true);
+            market.total_yes_shares = holdings.iter().map(|h| *h as u128).sum();
+
+            let mut vault = 25_000_000u64;
+            let payouts = holdings.map(|shares| {
+                market.snapshot_payout_per_share(vault).unwrap();
+                let payout = market.payout_for_shares(shares, vault).unwrap();
+                vault -= payout;
+                market.total_yes_shares -= shares as u128;
+                payout
+            });
+            (payouts, vault)
+        };
+
+        assert_eq!(settle(PayoutMode::Parimutuel), ([10_000_000, 15_000_000], 0));
+        // Two lamports a share leaves the rest of the vault behind
+        assert_eq!(
+            settle(PayoutMode::FixedRedemption { lamports_per_share: 2 }),
+            ([8_000_000, 12_000_000], 5_000_000)
+        );
+        // Three a share is more than the vault holds, so winners split it instead
+        assert_eq!(
+            settle(PayoutMode::FixedRedemption { lamports_per_share: 3 }),
+            ([10_000_000, 15_000_000], 0)
+        );
+    }
+
+    #[test]
+    fn insurance_covers_a_vault_that_comes_up_short() {
+        let mut market = sample_market();
+        market.resolved = true;
+        market.outcome = Some(true);
+        market.total_yes_shares = 10_000_000;
+
+        // The first claim fixes the rate at 2.5 lamports a share
+        market.snapshot_payout_per_share(25_000_000).unwrap();
+        assert_eq!(market.payout_for_shares(4_000_000, 25_000_000).unwrap(), 10_000_000);
+        market.total_yes_shares -= 4_000_000;
+
+        // The vault then loses 5_000_000, so the last claimant is owed more than it holds
+        let owed = market.owed_for_shares(6_000_000, 10_000_000).unwrap();
+        let paid = market.payout_for_shares(6_000_000, 10_000_000).unwrap();
+        assert_eq!((owed, paid), (15_000_000, 10_000_000));
+        assert_eq!(insurance_draw(owed, paid, 3_000_000), 3_000_000);
+        assert_eq!(insurance_draw(owed, paid, 9_000_000), 5_000_000);
+
+        // With the vault intact the last claimant sweeps it and insurance is untouched
+        let owed = market.owed_for_shares(6_000_000, 15_000_001).unwrap();
+        let paid = market.payout_for_shares(6_000_000, 15_000_001).unwrap();
+        assert_eq!((owed, paid), (15_000_001, 15_000_001));
+        assert_eq!(insurance_draw(owed, paid, 9_000_000), 0);
+    }
+
+    #[test]
+    fn only_unclaimed_winnings_are_sweepable_after_the_deadline() {
+        let resolved_at = 1_700_000_000;
+        assert_eq!(claim_deadline(resolved_at, 0).unwrap(), 0);
+        let deadline = claim_deadline(resolved_at, MIN_CLAIM_WINDOW).unwrap();
+
+        let mut market = sample_market();
+        market.resolved = true;
+        market.outcome = Some(true);
+        market.total_yes_shares = 10_000_000;
+        market.total_no_shares = 7_000_000;
+        market.claim_deadline = deadline;
+
+        // Claims run right up to the deadline, and nothing is sweepable before it
+        market.require_claimable(deadline - 1).unwrap();
+        assert_eq!(
+            market.sweepable_unclaimed(deadline - 1, 25_000_000).unwrap_err(),
+            ErrorCode::ClaimDeadlineNotReached.into()
+        );
+
+        // One winner claims 4_000_000 shares at 2.5 lamports a share
+        market.snapshot_payout_per_share(25_000_000).unwrap();
+        market.total_yes_shares -= 4_000_000;
+
+        assert_eq!(
+            market.require_claimable(deadline).unwrap_err(),
+            ErrorCode::ClaimDeadlinePassed.into()
+        );
+        assert_eq!(market.sweepable_unclaimed(deadline, 15_000_000).unwrap(), (6_000_000, 15_000_000));
+
+        // A fixed redemption sweeps the winners' due and leaves the creator's surplus
+        market.payout_per_share = 0;
+        market.payout_mode = PayoutMode::FixedRedemption { lamports_per_share: 1 };
+        assert_eq!(market.sweepable_unclaimed(deadline, 15_000_000).unwrap(), (6_000_000, 6_000_000));
+
+        // Markets without a deadline never sweep
+        market.claim_deadline = 0;
+        market.require_claimable(i64::MAX).unwrap();
+        assert_eq!(
+            market.sweepable_unclaimed(i64::MAX, 15_000_000).unwrap_err(),
+            ErrorCode::NoClaimDeadline.into()
+        );
+    }
+
+    #[test]
+    fn amm_invariant_tolerates_floor_rounding_only() {
+        let mut market = sample_market();
+        assert!(market.amm_invariant_holds());
+
+        market.no_liquidity += 1_000_000;
+        assert!(!market.amm_invariant_holds());
+    }
+
+    #[test]
+    fn trading_and_resolution_split_cleanly_at_resolution_time() {
+        let mut config = sample_config();
+        let market = sample_market();
+        let t = market.resolution_time;
+
+        assert!(config.trading_open(t, t - 1).unwrap());
+        assert!(!market.resolution_reached(t - 1));
+        for now in [t, t + 1] {
+            assert!(!config.trading_open(t, now).unwrap());
+            assert!(market.resolution_reached(now));
+        }
+
+        // A buffer moves the trading edge earlier but never the resolution edge
+        config.trading_buffer = 60;
+        assert!(config.trading_open(t, t - 61).unwrap());
+        assert!(!config.trading_open(t, t - 60).unwrap());
+        assert!(!market.resolution_reached(t - 1));
+    }
+
+    #[test]
+    fn only_house_wallets_are_fee_exempt_and_only_when_enabled() {
+        let mut config = sample_config();
+        config.authority = Pubkey::new_unique();
+        config.treasury = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+
+        assert!(!config.fee_exempt(&config.authority));
+        assert!(!config.fee_exempt(&config.treasury));
+
+        config.house_fee_exempt = true;
+        assert!(config.fee_exempt(&config.authority));
+        assert!(config.fee_exempt(&config.treasury));
+        assert!(!config.fee_exempt(&user));
+    }
+
+    #[test]
+    fn audit_log_overwrites_the_oldest_entries_once_full() {
+        let mut log = AuditLog { entries: Vec::new(), next: 0, total_recorded: 0, overwritten: 0, bump: 0 };
+        let entry = |timestamp| AuditEntry {
+            action: AuditAction::FeeSet,
+            actor: Pubkey::default(),
+            market_id: 0,
+            amount: 0,
+            timestamp,
+        };
+
+        for t in 0..3 {
+            log.record(254, entry(t));
+        }
+        let times: Vec<i64> = log.in_order().iter().map(|e| e.timestamp).collect();
+        assert_eq!(times, vec![0, 1, 2]);
+        assert_eq!(log.overwritten, 0);
+
+        let extra = 5;
+        for t in 3..(AUDIT_LOG_CAPACITY as i64 + extra) {
+            log.record(254, entry(t));
+        }
+        let times: Vec<i64> = log.in_order().iter().map(|e| e.timestamp).collect();
+        assert_eq!(times, (extra..AUDIT_LOG_CAPACITY as i64 + extra).collect::<Vec<_>>());
+        assert_eq!(log.overwritten, extra as u64);
+        assert_eq!(log.total_recorded, AUDIT_LOG_CAPACITY as u64 + extra as u64);
+        assert_eq!(log.bump, 254);
+    }
+
+    #[test]
+    fn config_view_mirrors_the_stored_config() {
+        let mut config = sample_config();
+        config.authority = Pubkey::new_unique();
+        config.treasury = Pubkey::new_unique();
+        config.market_count = 12;
+        config.active_markets = 7;
+        config.fee_percentage = 200;
+        config.max_fee_bps = 500;
+        config.trading_buffer = 60;
+        config.resolvers = vec![Pubkey::new_unique()];
+        config.claim_window = MIN_CLAIM_WINDOW;
+
+        let view = config.view();
+        assert_eq!(view.authority, config.authority);
+        assert_eq!(view.treasury, config.treasury);
+        assert_eq!(view.market_count, 12);
+        assert_eq!(view.active_markets, 7);
+        assert_eq!(view.fee_bps, 200);
+        assert_eq!(view.max_fee_bps, 500);
+        assert_eq!(view.trading_buffer, 60);
+        assert_eq!(view.resolvers, config.resolvers);
+        assert_eq!(view.claim_window, MIN_CLAIM_WINDOW);
+
+        // The view reports the fee trades are charged, not a setting above the cap
+        config.max_fee_bps = 100;
+        assert_eq!(config.view().fee_bps, 100);
+    }
+
+    #[test]
+    fn max_buy_keeps_the_reserve_floor() {
+        let market = sample_market();
+        let fee_bps = 200;
+        let after_fee = |amount: u64| amount - amount * fee_bps / BPS_DENOMINATOR;
+
+        for is_yes in [true, false] {
+            let (reserve_in, reserve_out) = if is_yes {
+                (market.yes_liquidity, market.no_liquidity)
+            } else {
+                (market.no_liquidity, market.yes_liquidity)
+            };
+            let max = market.max_buy_lamports(is_yes, fee_bps).unwrap();
+            let fill = |amount| amm::swap(reserve_in, reserve_out, market.k_constant, after_fee(amount), PRECISION);
+
+            let (_, _, new_out) = fill(max).unwrap();
+            assert!(new_out >= MIN_RESERVE);
+            let (_, _, new_out) = fill(max + 2).unwrap();
+            assert!(new_out < MIN_RESERVE);
+        }
+
+        let mut lmsr = sample_market();
+        lmsr.market_type = MarketType::Lmsr;
+        assert_eq!(lmsr.max_buy_lamports(true, fee_bps), None);
+    }
+
+    #[test]
+    fn outcome_prices_sum_to_the_whole() {
+        let mut market = sample_market();
+        for (yes, no) in [(120_000_000, 83_333_333), (1, 1_000_000_000), (77_777_777, 77_777_777)] {
+            market.yes_liquidity = yes;
+            market.no_liquidity = no;
+            let prices = market.outcome_prices_bps();
+            assert_eq!(prices.len(), 2);
+            assert_eq!(prices.iter().map(|&p| p as u64).sum::<u64>(), BPS_DENOMINATOR);
+            assert_eq!(prices[0], market.implied_yes_bps());
+        }
+
+        market.market_type = MarketType::Lmsr;
+        market.total_yes_shares = 40_000_000;
+        market.total_no_shares = 10_000_000;
+        let prices = market.outcome_prices_bps();
+        assert_eq!(prices[0] as u64 + prices[1] as u64, BPS_DENOMINATOR);
+        assert!(prices[0] > prices[1]);
+    }
+
+    #[test]
+    fn round_trip_spread_covers_both_fees() {
+        let market = sample_market();
+        // With no fee the round trip only loses the pool's rounding
+        assert!(market.round_trip_spread_bps(true, 1_000_000, 0).unwrap() <= 1);
+
+        // A 1% fee each way loses just under two percent of the probe
+        for is_yes in [true, false] {
+            let spread = market.round_trip_spread_bps(is_yes, 1_000_000, 100).unwrap();
+            assert!((199..=201).contains(&spread), "spread {}", spread);
+        }
+
+        let mut lmsr = sample_market();
+        lmsr.market_type = MarketType::Lmsr;
+        lmsr.total_yes_shares = 40_000_000;
+        lmsr.total_no_shares = 10_000_000;
+        assert!(lmsr.round_trip_spread_bps(true, 1_000_000, 0).unwrap() <= 1);
+        assert!((199..=201).contains(&lmsr.round_trip_spread_bps(false, 1_000_000, 100).unwrap()));
+    }
+
+    #[test]
+    fn price_impact_grows_with_trade_size() {
+        let market = sample_market();
+        let small = market.price_impact_bps(true, 100_000).unwrap();
+        let large = market.price_impact_bps(true, 50_000_000).unwrap();
+        assert!(large > small, "large {} vs small {}", large, small);
+        assert!(market.price_impact_bps(false, 50_000_000).unwrap() > small);
+    }
+
+    // Applies a pool-side swap the way process_buy and execute_sell do
+    fn apply_swap(market: &mut Market, yes_in: bool, amount_in: u64) {
+        let (reserve_in, reserve_out) = if yes_in {
+            (market.yes_liquidity, market.no_liquidity)
+        } else {
+            (market.no_liquidity, market.yes_liquidity)
+        };
+        let (_, new_in, new_out) =
+            amm::swap(reserve_in, reserve_out, market.k_constant, amount_in, market.precision()).unwrap();
+        if yes_in {
+            (market.yes_liquidity, market.no_liquidity) = (new_in, new_out);
+        } else {
+            (market.no_liquidity, market.yes_liquidity) = (new_in, new_out);
+        }
+    }
+
+    #[test]
+    fn min_k_holds_across_normal_trades() {
+        let mut market = sample_market();
+        market.require_min_k().unwrap();
+        for (yes_in, amount) in [(true, 1_000_000), (false, 3_333_333), (true, 7), (false, 50_000_000)] {
+            apply_swap(&mut market, yes_in, amount);
+            market.require_min_k().unwrap();
+        }
+    }
+
+    #[test]
+    fn min_k_trips_on_a_drained_reserve() {
+        let mut market = sample_market();
+        apply_swap(&mut market, true, 1_000_000);
+        // Corrupt the pool as a buggy swap would: shares paid out without reserves backing them
+        market.no_liquidity -= market.no_liquidity / 100;
+        assert_eq!(market.require_min_k().unwrap_err(), ErrorCode::InvariantViolation.into());
+
+        // A pool richer than k is fine, and LMSR markets are not checked against k
+        let mut market = sample_market();
+        market.no_liquidity += 1_000_000;
+        market.require_min_k().unwrap();
+        market.no_liquidity -= 2_000_000;
+        market.market_type = MarketType::Lmsr;
+        market.require_min_k().unwrap();
+    }
+}
@@ -0,0 +1,38 @@
+use anchor_lang::prelude::*;
+
+declare_id!("AZQLCfaFUdf618UqKRwcB1bpTdMX2pWxQr3RGRjwL2NC");
+
+// Stands in for a program that depends on market outcomes, such as a lending protocol.
+// It only logs and emits what it receives so tests can check the callback fired
+#[program]
+pub mod resolution_callback_mock {
+    use super::*;
+
+    pub fn on_market_resolved(ctx: Context<OnMarketResolved>, market_id: u64, outcome_yes: bool) -> Result<()> {
+        emit!(ResolutionReceivedEvent {
+            market: ctx.accounts.market.key(),
+            market_id,
+            outcome_yes,
+        });
+
+        msg!(
+            "Callback received market #{} resolved {}",
+            market_id,
+            if outcome_yes { "YES" } else { "NO" }
+        );
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct OnMarketResolved<'info> {
+    /// CHECK: the resolving market, only logged here
+    pub market: UncheckedAccount<'info>,
+}
+
+#[event]
+pub struct ResolutionReceivedEvent {
+    pub market: Pubkey,
+    pub market_id: u64,
+    pub outcome_yes: bool,
+}
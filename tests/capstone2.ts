@@ -1,7 +1,11 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { PredictionMarket } from "../target/types/prediction_market";
+import {
+  ResolutionCallbackMock,
+} from "../target/types/resolution_callback_mock";
 import { expect } from "chai";
+import { createHash } from "crypto";
 
 describe("Prediction Market - Complete Test Suite", () => {
   const provider = anchor.AnchorProvider.env();
@@ -28,6 +32,7 @@ describe("Prediction Market - Complete Test Suite", () => {
     outcome: boolean;
     vaultPda?: anchor.web3.PublicKey;
     marketPda?: anchor.web3.PublicKey;
+    metadataPda?: anchor.web3.PublicKey;
   }[] = [
     {
       marketId: 1,
@@ -61,6 +66,237 @@ describe("Prediction Market - Complete Test Suite", () => {
   const delay = (ms: number) =>
     new Promise((resolve) => setTimeout(resolve, ms));
 
+  // PDA helpers shared by the feature suites below
+  const idBytes = (id: number) =>
+    new anchor.BN(id).toArrayLike(Buffer, "le", 8);
+  const findPda = (seeds: Buffer[]) =>
+    anchor.web3.PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+  const marketPdaFor = (id: number) =>
+    findPda([Buffer.from("market"), idBytes(id)]);
+  const metadataPdaFor = (id: number) =>
+    findPda([Buffer.from("metadata"), idBytes(id)]);
+  const vaultPdaFor = (id: number) =>
+    findPda([Buffer.from("vault"), idBytes(id)]);
+  const insuranceVaultPdaFor = (id: number) =>
+    findPda([Buffer.from("insurance_vault"), idBytes(id)]);
+  const positionPdaFor = (user: anchor.web3.PublicKey, id: number) =>
+    findPda([Buffer.from("position"), user.toBuffer(), idBytes(id)]);
+  const feeBucketPdaFor = (bucket: number) => {
+    const seed = Buffer.alloc(2);
+    seed.writeUInt16LE(bucket);
+    return findPda([Buffer.from("fee_vault"), seed]);
+  };
+  const categoryStatsPdaFor = (category: string) =>
+    findPda([
+      Buffer.from("category_stats"),
+      createHash("sha256").update(category).digest(),
+    ]);
+  const categoryIndexPdaFor = (category: string, page: number) =>
+    findPda([
+      Buffer.from("category_index"),
+      createHash("sha256").update(category).digest(),
+      idBytes(page),
+    ]);
+  const CATEGORY_PAGE_SIZE = 10;
+  const creatorStatsPdaFor = (creator: anchor.web3.PublicKey) =>
+    findPda([Buffer.from("creator_stats"), creator.toBuffer()]);
+  // The index page create_market appends the next market in `category` to
+  const nextCategoryIndexPda = async (category: string) => {
+    const stats = await program.account.categoryStats.fetchNullable(
+      categoryStatsPdaFor(category)
+    );
+    const count = stats ? stats.marketCount.toNumber() : 0;
+    return categoryIndexPdaFor(
+      category,
+      Math.floor(count / CATEGORY_PAGE_SIZE)
+    );
+  };
+
+  type TestMarket = {
+    marketId: number;
+    marketPda: anchor.web3.PublicKey;
+    metadataPda: anchor.web3.PublicKey;
+    vaultPda: anchor.web3.PublicKey;
+    category: string;
+    createSig?: string;
+  };
+
+  // Creates a fresh market that stays open for `durationSecs`
+  const createTestMarket = async (
+    marketId: number,
+    durationSecs = 3600,
+    liquidity = 0.1 * anchor.web3.LAMPORTS_PER_SOL,
+    category = "Test",
+    houseShares = 0,
+    marketType: object = { constantProduct: {} },
+    ammPrecision = 0
+  ): Promise<TestMarket> => {
+    const m: TestMarket = {
+      marketId,
+      marketPda: marketPdaFor(marketId),
+      metadataPda: metadataPdaFor(marketId),
+      vaultPda: vaultPdaFor(marketId),
+      category,
+    };
+    const resolutionTime = new anchor.BN(
+      Math.floor(Date.now() / 1000) + durationSecs
+    );
+    m.createSig = await program.methods
+      .createMarket(
+        new anchor.BN(marketId),
+        `Test market #${marketId}`,
+        "Created by the feature test suites",
+        category,
+        resolutionTime,
+        new anchor.BN(liquidity),
+        new anchor.BN(houseShares),
+        marketType,
+        new anchor.BN(ammPrecision)
+      )
+      .accounts({
+        config: configPda,
+        market: m.marketPda,
+        metadata: m.metadataPda,
+        categoryStats: categoryStatsPdaFor(category),
+        categoryIndex: await nextCategoryIndexPda(category),
+        creatorStats: creatorStatsPdaFor(authority),
+        housePosition:
+          houseShares > 0 ? positionPdaFor(authority, marketId) : null,
+        insuranceVault: insuranceVaultPdaFor(marketId),
+        vault: m.vaultPda,
+        authority: authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc({ commitment: "confirmed" });
+    return m;
+  };
+
+  // Decodes the Anchor events logged by a confirmed transaction
+  const eventParser = new anchor.EventParser(
+    program.programId,
+    new anchor.BorshCoder(program.idl)
+  );
+  const eventsFromTx = async (sig: string) => {
+    const tx = await provider.connection.getTransaction(sig, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    return Array.from(eventParser.parseLogs(tx.meta.logMessages));
+  };
+  const findEvent = (events: anchor.Event[], name: string) =>
+    events.find((e) => e.name.toLowerCase() === name.toLowerCase());
+
+  const fundedKeypair = async (sol = 5) => {
+    const kp = anchor.web3.Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(
+      kp.publicKey,
+      sol * anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+    return kp;
+  };
+
+  // Opens the trader's position in the market unless it already exists
+  const openPosition = async (trader: anchor.web3.Keypair, m: TestMarket) => {
+    const positionPda = positionPdaFor(trader.publicKey, m.marketId);
+    if (await program.account.userPosition.fetchNullable(positionPda)) return;
+    await program.methods
+      .openPosition()
+      .accounts({
+        market: m.marketPda,
+        userPosition: positionPda,
+        user: trader.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([trader])
+      .rpc();
+  };
+
+  const buy = async (
+    trader: anchor.web3.Keypair,
+    m: TestMarket,
+    isYes: boolean,
+    lamports: number,
+    minSharesOut = 0,
+    feeBucket: number | null = null,
+    allowPartial: boolean | null = null
+  ) => {
+    await openPosition(trader, m);
+    return program.methods
+      .buyShares(
+        isYes,
+        new anchor.BN(lamports),
+        new anchor.BN(minSharesOut),
+        feeBucket,
+        allowPartial
+      )
+      .accounts({
+        config: configPda,
+        market: m.marketPda,
+        vault: m.vaultPda,
+        feeVault: feeBucket ? feeBucketPdaFor(feeBucket) : feeVaultPda,
+        categoryStats: categoryStatsPdaFor(m.category),
+        userPosition: positionPdaFor(trader.publicKey, m.marketId),
+        user: trader.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+        cosigner: null,
+        whitelistEntry: null,
+        insuranceVault: null,
+      })
+      .signers([trader])
+      .rpc();
+  };
+
+  // Waits out a short market and resolves it
+  const resolveTestMarket = async (m: TestMarket, outcomeYes: boolean) => {
+    const acc = await program.account.market.fetch(m.marketPda);
+    const waitMs = (acc.resolutionTime.toNumber() + 2) * 1000 - Date.now();
+    if (waitMs > 0) await delay(waitMs);
+    await program.methods
+      .resolveMarket(outcomeYes)
+      .accounts({
+        config: configPda,
+        market: m.marketPda,
+        authority: authority,
+      })
+      .rpc();
+  };
+
+  const claimAccounts = (user: anchor.web3.PublicKey, m: TestMarket) => ({
+    market: m.marketPda,
+    vault: m.vaultPda,
+    userPosition: positionPdaFor(user, m.marketId),
+    user: user,
+    systemProgram: anchor.web3.SystemProgram.programId,
+    insuranceVault: null,
+  });
+
+  // Mirrors the on-chain buy math so tests can predict fills exactly
+  const PRECISION = new anchor.BN(1_000_000_000);
+  const quoteBuy = async (m: TestMarket, isYes: boolean, lamports: number) => {
+    const acc = await program.account.market.fetch(m.marketPda);
+    const config = await program.account.config.fetch(configPda);
+    const amount = new anchor.BN(lamports);
+    const fee = amount.muln(config.feePercentage).divn(10000);
+    const afterFee = amount.sub(fee);
+    const reserveIn = isYes ? acc.yesLiquidity : acc.noLiquidity;
+    const reserveOut = isYes ? acc.noLiquidity : acc.yesLiquidity;
+    // The program rounds the opposite reserve up, in the pool's favor
+    const divCeil = (a: anchor.BN, b: anchor.BN) => a.add(b).subn(1).div(b);
+    const precision = acc.ammPrecision;
+    const newIn = reserveIn.add(afterFee).mul(precision);
+    const newOut = divCeil(divCeil(acc.kConstant, newIn), precision);
+    const shares = reserveOut.sub(newOut);
+    return { fee, afterFee, shares };
+  };
+
+  const impliedYesBps = async (m: TestMarket) => {
+    const acc = await program.account.market.fetch(m.marketPda);
+    const yes = acc.yesLiquidity.toNumber();
+    const no = acc.noLiquidity.toNumber();
+    return Math.floor((yes * 10000) / (yes + no));
+  };
+
   before(async () => {
     console.log("\n Setting up test environment...\n");
 
@@ -169,6 +405,16 @@ describe("Prediction Market - Complete Test Suite", () => {
         );
         market.vaultPda = vaultPda;
 
+        // Derive metadata PDA
+        const [metadataPda] = anchor.web3.PublicKey.findProgramAddressSync(
+          [
+            Buffer.from("metadata"),
+            new anchor.BN(market.marketId).toArrayLike(Buffer, "le", 8),
+          ],
+          program.programId
+        );
+        market.metadataPda = metadataPda;
+
         try {
           await program.methods
             .createMarket(
@@ -177,11 +423,20 @@ describe("Prediction Market - Complete Test Suite", () => {
               market.description,
               market.category,
               resolutionTime,
-              initialLiquidity
+              initialLiquidity,
+              new anchor.BN(0),
+              { constantProduct: {} },
+              new anchor.BN(0)
             )
             .accounts({
               config: configPda,
               market: marketPda,
+              metadata: metadataPda,
+              categoryStats: categoryStatsPdaFor(market.category),
+              categoryIndex: await nextCategoryIndexPda(market.category),
+              creatorStats: creatorStatsPdaFor(authority),
+              housePosition: null,
+              insuranceVault: insuranceVaultPdaFor(market.marketId),
               vault: vaultPda,
               authority: authority,
               systemProgram: anchor.web3.SystemProgram.programId,
@@ -206,14 +461,56 @@ describe("Prediction Market - Complete Test Suite", () => {
         const marketAccount = await program.account.market.fetch(
           market.marketPda
         );
+        const metadata = await program.account.marketMetadata.fetch(
+          market.metadataPda
+        );
 
-        expect(marketAccount.question).to.equal(market.question);
+        expect(metadata.marketId.toNumber()).to.equal(market.marketId);
+        expect(metadata.question).to.equal(market.question);
+        expect(metadata.description).to.equal(market.description);
+        expect(metadata.category).to.equal(market.category);
         expect(marketAccount.resolved).to.be.false;
 
-        console.log(`   Market ${i + 1}: "${marketAccount.question}"`);
+        console.log(`   Market ${i + 1}: "${metadata.question}"`);
       }
       console.log();
     });
+
+    it("Returns a market summary matching the market account", async () => {
+      const market = markets[0];
+      const marketAccount = await program.account.market.fetch(
+        market.marketPda
+      );
+      const metadata = await program.account.marketMetadata.fetch(
+        market.metadataPda
+      );
+
+      const summary = await program.methods
+        .getMarketSummary()
+        .accounts({ market: market.marketPda, metadata: market.metadataPda })
+        .view();
+
+      const yes = marketAccount.yesLiquidity.toNumber();
+      const no = marketAccount.noLiquidity.toNumber();
+      const expectedYesBps = Math.floor((yes * 10000) / (yes + no));
+
+      expect(summary.marketId.toNumber()).to.equal(market.marketId);
+      expect(summary.questionTruncated).to.equal(
+        metadata.question.substring(0, 64)
+      );
+      expect(summary.category).to.equal(metadata.category);
+      expect(summary.impliedYesBps).to.equal(expectedYesBps);
+      expect(summary.totalVolume.toString()).to.equal(
+        marketAccount.totalVolume.toString()
+      );
+      expect(summary.resolved).to.equal(marketAccount.resolved);
+      expect(summary.outcome).to.equal(marketAccount.outcome);
+      expect(summary.resolutionTime.toString()).to.equal(
+        marketAccount.resolutionTime.toString()
+      );
+
+      console.log(" Market summary matches the underlying market\n");
+    });
   });
 
   describe("Share Purchases", () => {
@@ -264,16 +561,21 @@ describe("Prediction Market - Complete Test Suite", () => {
             const { blockhash } =
               await provider.connection.getLatestBlockhash();
 
+            await openPosition(trader, market);
             await program.methods
-              .buyShares(buyYes, amount, minSharesOut)
+              .buyShares(buyYes, amount, minSharesOut, null, null)
               .accounts({
                 config: configPda,
                 market: market.marketPda,
                 vault: market.vaultPda,
                 feeVault: feeVaultPda,
+                categoryStats: categoryStatsPdaFor(market.category),
                 userPosition: userPositionPda,
                 user: trader.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
+                cosigner: null,
+                whitelistEntry: null,
+                insuranceVault: null,
               })
               .signers([trader])
               .rpc({
@@ -320,6 +622,20 @@ describe("Prediction Market - Complete Test Suite", () => {
       );
     });
 
+    it("Buys without touching the metadata account", async () => {
+      const buyIx = program.idl.instructions.find(
+        (ix) => ix.name === "buy_shares"
+      );
+      const accountNames = buyIx.accounts.map((acc) => acc.name);
+      expect(accountNames).to.not.include("metadata");
+
+      // Metadata is still intact and readable after trading
+      const metadata = await program.account.marketMetadata.fetch(
+        markets[0].metadataPda
+      );
+      expect(metadata.question).to.equal(markets[0].question);
+    });
+
     it("Verifies market share totals after purchases", async () => {
       for (let i = 0; i < markets.length; i++) {
         const market = markets[i];
@@ -490,6 +806,7 @@ describe("Prediction Market - Complete Test Suite", () => {
                 userPosition: userPositionPda,
                 user: trader.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
+                insuranceVault: null,
               })
               .signers([trader])
               .rpc();
@@ -596,6 +913,7 @@ describe("Prediction Market - Complete Test Suite", () => {
             userPosition: userPositionPda,
             user: trader.publicKey,
             systemProgram: anchor.web3.SystemProgram.programId,
+            insuranceVault: null,
           })
           .signers([trader])
           .rpc();
@@ -615,7 +933,7 @@ describe("Prediction Market - Complete Test Suite", () => {
       if (feeVaultBalance > 0) {
         try {
           await program.methods
-            .withdrawFees(new anchor.BN(feeVaultBalance))
+            .withdrawFees(new anchor.BN(feeVaultBalance), 0)
             .accounts({
               config: configPda,
               feeVault: feeVaultPda,
@@ -725,16 +1043,21 @@ describe("Prediction Market - Complete Test Suite", () => {
       );
 
       try {
+        await openPosition(newTrader, market);
         await program.methods
-          .buyShares(true, amount, minSharesOut)
+          .buyShares(true, amount, minSharesOut, null, null)
           .accounts({
             config: configPda,
             market: market.marketPda,
             vault: market.vaultPda,
             feeVault: feeVaultPda,
+            categoryStats: categoryStatsPdaFor(market.category),
             userPosition: userPositionPda,
             user: newTrader.publicKey,
             systemProgram: anchor.web3.SystemProgram.programId,
+            cosigner: null,
+            whitelistEntry: null,
+            insuranceVault: null,
           })
           .signers([newTrader])
           .rpc();
@@ -768,6 +1091,7 @@ describe("Prediction Market - Complete Test Suite", () => {
             userPosition: userPositionPda,
             user: trader.publicKey,
             systemProgram: anchor.web3.SystemProgram.programId,
+            insuranceVault: null,
           })
           .signers([trader])
           .rpc();
@@ -779,4 +1103,5250 @@ describe("Prediction Market - Complete Test Suite", () => {
       }
     });
   });
+
+  describe("Fee Vault Initialization", () => {
+    it("Rejects buys into an uninitialized fee vault", async () => {
+      const market = await createTestMarket(103);
+      const trader = await fundedKeypair(1);
+
+      // Drain the fee vault so it looks like initialize() never funded it
+      const balance = await provider.connection.getBalance(feeVaultPda);
+      if (balance > 0) {
+        await program.methods
+          .withdrawFees(new anchor.BN(balance), 0)
+          .accounts({
+            config: configPda,
+            feeVault: feeVaultPda,
+            authority: authority,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .rpc();
+      }
+
+      try {
+        await buy(trader, market, true, 0.01 * anchor.web3.LAMPORTS_PER_SOL);
+        expect.fail("Buy should fail against an empty fee vault");
+      } catch (error) {
+        expect(error.message).to.include("FeeVaultNotInitialized");
+      }
+
+      // Re-seed the fee vault with its rent-exempt minimum for later suites
+      const minRent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({
+            fromPubkey: authority,
+            toPubkey: feeVaultPda,
+            lamports: minRent,
+          })
+        )
+      );
+
+      await buy(trader, market, true, 0.01 * anchor.web3.LAMPORTS_PER_SOL);
+    });
+  });
+
+  describe("Selling & Trigger Orders", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+    let whale: anchor.web3.Keypair;
+    let keeper: anchor.web3.Keypair;
+
+    const triggerPdaFor = (
+      owner: anchor.web3.PublicKey,
+      marketId: number,
+      orderId: number
+    ) =>
+      findPda([
+        Buffer.from("trigger"),
+        owner.toBuffer(),
+        idBytes(marketId),
+        idBytes(orderId),
+      ]);
+
+    const placeTrigger = (
+      orderId: number,
+      isYes: boolean,
+      shares: anchor.BN,
+      triggerBps: number,
+      direction: object
+    ) =>
+      program.methods
+        .placeTriggerOrder(
+          new anchor.BN(orderId),
+          isYes,
+          shares,
+          triggerBps,
+          direction as any,
+          new anchor.BN(0.001 * SOL)
+        )
+        .accounts({
+          market: market.marketPda,
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          triggerOrder: triggerPdaFor(
+            trader.publicKey,
+            market.marketId,
+            orderId
+          ),
+          owner: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([trader])
+        .rpc();
+
+    const executeTrigger = (orderId: number) =>
+      program.methods
+        .executeTrigger()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          triggerOrder: triggerPdaFor(
+            trader.publicKey,
+            market.marketId,
+            orderId
+          ),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          owner: trader.publicKey,
+          keeper: keeper.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: null,
+        })
+        .signers([keeper])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(101);
+      trader = await fundedKeypair();
+      whale = await fundedKeypair(10);
+      keeper = await fundedKeypair(1);
+    });
+
+    it("Sells shares back to the pool", async () => {
+      await buy(trader, market, true, 0.05 * SOL);
+      const positionPda = positionPdaFor(trader.publicKey, market.marketId);
+      const before = await program.account.userPosition.fetch(positionPda);
+      const sellAmount = before.yesShares.divn(4);
+      const balanceBefore = await provider.connection.getBalance(
+        trader.publicKey
+      );
+
+      await program.methods
+        .sellShares(true, sellAmount, new anchor.BN(1))
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPda,
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+
+      const after = await program.account.userPosition.fetch(positionPda);
+      const balanceAfter = await provider.connection.getBalance(
+        trader.publicKey
+      );
+      expect(after.yesShares.toString()).to.equal(
+        before.yesShares.sub(sellAmount).toString()
+      );
+      expect(balanceAfter).to.be.greaterThan(balanceBefore);
+    });
+
+    it("Fires a take-profit when the price rises", async () => {
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      const shares = position.yesShares.divn(2);
+      const triggerBps = (await impliedYesBps(market)) + 200;
+
+      await placeTrigger(1, true, shares, triggerBps, { above: {} });
+
+      try {
+        await executeTrigger(1);
+        expect.fail("Trigger should not fire before the price crosses");
+      } catch (error) {
+        expect(error.message).to.include("TriggerNotReached");
+      }
+
+      // Whale pushes the YES price above the trigger
+      await buy(whale, market, true, 1 * SOL);
+      expect(await impliedYesBps(market)).to.be.at.least(triggerBps);
+
+      const keeperBefore = await provider.connection.getBalance(
+        keeper.publicKey
+      );
+      await executeTrigger(1);
+      const keeperAfter = await provider.connection.getBalance(
+        keeper.publicKey
+      );
+
+      const after = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(after.yesShares.toString()).to.equal(
+        position.yesShares.sub(shares).toString()
+      );
+      expect(keeperAfter).to.be.greaterThan(keeperBefore);
+      const orderInfo = await provider.connection.getAccountInfo(
+        triggerPdaFor(trader.publicKey, market.marketId, 1)
+      );
+      expect(orderInfo).to.be.null;
+    });
+
+    it("Fires a stop-loss when the price falls", async () => {
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      const triggerBps = (await impliedYesBps(market)) - 200;
+
+      await placeTrigger(2, true, position.yesShares, triggerBps, {
+        below: {},
+      });
+
+      // Whale dumps into NO, dragging the YES price below the trigger
+      await buy(whale, market, false, 2 * SOL);
+      expect(await impliedYesBps(market)).to.be.at.most(triggerBps);
+
+      await executeTrigger(2);
+
+      const after = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(after.yesShares.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("Partial Claims", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let winner: anchor.web3.Keypair;
+    let other: anchor.web3.Keypair;
+
+    before(async () => {
+      market = await createTestMarket(102, 15);
+      winner = await fundedKeypair();
+      other = await fundedKeypair();
+      await buy(winner, market, true, 0.05 * SOL);
+      await buy(other, market, true, 0.05 * SOL);
+      await buy(other, market, false, 0.03 * SOL);
+      await resolveTestMarket(market, true);
+    });
+
+    it("Two partial claims sum to the full payout", async () => {
+      const positionPda = positionPdaFor(winner.publicKey, market.marketId);
+      const position = await program.account.userPosition.fetch(positionPda);
+      const half = position.yesShares.divn(2);
+      const rest = position.yesShares.sub(half);
+      const vaultStart = await provider.connection.getBalance(market.vaultPda);
+
+      await program.methods
+        .claimPartial(half)
+        .accounts(claimAccounts(winner.publicKey, market))
+        .signers([winner])
+        .rpc();
+
+      const mid = await program.account.userPosition.fetch(positionPda);
+      expect(mid.yesShares.toString()).to.equal(rest.toString());
+      expect(mid.claimed).to.be.false;
+
+      try {
+        await program.methods
+          .claimPartial(rest.addn(1))
+          .accounts(claimAccounts(winner.publicKey, market))
+          .signers([winner])
+          .rpc();
+        expect.fail("Over-claim should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InsufficientShares");
+      }
+
+      await program.methods
+        .claimPartial(rest)
+        .accounts(claimAccounts(winner.publicKey, market))
+        .signers([winner])
+        .rpc();
+
+      const vaultEnd = await provider.connection.getBalance(market.vaultPda);
+      const marketAccount = await program.account.market.fetch(
+        market.marketPda
+      );
+      const fullPayout = position.yesShares
+        .mul(marketAccount.payoutPerShare)
+        .div(new anchor.BN(1_000_000_000));
+      const paid = vaultStart - vaultEnd;
+
+      // Each partial rounds down independently, so allow one lamport of drift
+      expect(Math.abs(paid - fullPayout.toNumber())).to.be.at.most(1);
+
+      const final = await program.account.userPosition.fetch(positionPda);
+      expect(final.yesShares.toNumber()).to.equal(0);
+      expect(final.claimed).to.be.true;
+    });
+  });
+
+  describe("Creator Liquidity Withdrawal", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const withdrawCreator = (m: TestMarket) =>
+      program.methods
+        .withdrawCreatorLiquidity()
+        .accounts({
+          market: m.marketPda,
+          vault: m.vaultPda,
+          creator: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    it("Recovers the full seed when nobody holds winning shares", async () => {
+      const market = await createTestMarket(104, 15);
+      const loser = await fundedKeypair(1);
+      await buy(loser, market, false, 0.02 * SOL);
+      await resolveTestMarket(market, true);
+
+      const before = await program.account.market.fetch(market.marketPda);
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+      const minRent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+
+      await withdrawCreator(market);
+
+      const vaultAfter = await provider.connection.getBalance(market.vaultPda);
+      const expected = Math.min(
+        before.creatorDeposit.toNumber(),
+        vaultBefore - minRent
+      );
+      expect(vaultBefore - vaultAfter).to.equal(expected);
+
+      const after = await program.account.market.fetch(market.marketPda);
+      expect(after.creatorDeposit.toNumber()).to.equal(0);
+    });
+
+    it("Only pays out true surplus after winners are settled", async () => {
+      const market = await createTestMarket(105, 15);
+      const winner = await fundedKeypair(1);
+      await buy(winner, market, true, 0.05 * SOL);
+      await resolveTestMarket(market, true);
+
+      try {
+        await withdrawCreator(market);
+        expect.fail("Withdrawal should wait for winners to claim");
+      } catch (error) {
+        expect(error.message).to.include("UnclaimedWinningsOutstanding");
+      }
+
+      await program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(winner.publicKey, market))
+        .signers([winner])
+        .rpc();
+
+      const before = await program.account.market.fetch(market.marketPda);
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+      const minRent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      // The last winner takes everything above the rent floor
+      expect(vaultBefore).to.equal(minRent);
+      expect(before.creatorDeposit.toNumber()).to.be.greaterThan(0);
+
+      try {
+        await withdrawCreator(market);
+        expect.fail("Nothing is left for the creator to recover");
+      } catch (error) {
+        expect(error.message).to.include("NoRemainingFunds");
+      }
+    });
+  });
+
+  describe("Average Price Cap", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const amount = 0.05 * SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const buyCapped = (capBps: number) =>
+      program.methods
+        .buySharesWithPriceCap(true, new anchor.BN(amount), capBps)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: null,
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+
+    // ceil(amount_after_fee * 10000 / shares_out), as the program computes it
+    const expectedAvgBps = async () => {
+      const quote = await quoteBuy(market, true, amount);
+      const scaled = quote.afterFee.muln(10000);
+      const avg = scaled.add(quote.shares).subn(1).div(quote.shares);
+      return avg.toNumber();
+    };
+
+    before(async () => {
+      market = await createTestMarket(106);
+      trader = await fundedKeypair();
+      await openPosition(trader, market);
+    });
+
+    it("Rejects a buy whose average price is above the cap", async () => {
+      const avg = await expectedAvgBps();
+      try {
+        await buyCapped(avg - 1);
+        expect.fail("Buy above the price cap should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("PriceCapExceeded");
+      }
+    });
+
+    it("Accepts a buy priced exactly at the cap", async () => {
+      const avg = await expectedAvgBps();
+      await buyCapped(avg);
+    });
+
+    it("Accepts a buy priced below the cap", async () => {
+      const avg = await expectedAvgBps();
+      await buyCapped(avg + 100);
+    });
+  });
+
+  describe("Market Created Event", () => {
+    it("Emits MarketCreatedEvent matching the new market", async () => {
+      const market = await createTestMarket(107);
+      const events = await eventsFromTx(market.createSig);
+      const event = findEvent(events, "MarketCreatedEvent");
+      expect(event, "MarketCreatedEvent should be emitted").to.exist;
+
+      const marketAccount = await program.account.market.fetch(
+        market.marketPda
+      );
+      const metadata = await program.account.marketMetadata.fetch(
+        market.metadataPda
+      );
+
+      expect(event.data.marketPubkey.toBase58()).to.equal(
+        market.marketPda.toBase58()
+      );
+      expect(event.data.marketId.toNumber()).to.equal(market.marketId);
+      expect(event.data.authority.toBase58()).to.equal(authority.toBase58());
+      expect(event.data.category).to.equal(metadata.category);
+      expect(event.data.resolutionTime.toString()).to.equal(
+        marketAccount.resolutionTime.toString()
+      );
+      expect(event.data.initialLiquidity.toString()).to.equal(
+        marketAccount.initialLiquidity.toString()
+      );
+      expect(event.data.createdAt.toString()).to.equal(
+        marketAccount.createdAt.toString()
+      );
+    });
+  });
+
+  describe("Claimable Amount Preview", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let winner: anchor.web3.Keypair;
+    let loser: anchor.web3.Keypair;
+
+    const preview = (user: anchor.web3.PublicKey) =>
+      program.methods
+        .getClaimableAmount()
+        .accounts({
+          market: market.marketPda,
+          vault: market.vaultPda,
+          userPosition: positionPdaFor(user, market.marketId),
+        })
+        .view();
+
+    before(async () => {
+      market = await createTestMarket(108, 15);
+      winner = await fundedKeypair(1);
+      loser = await fundedKeypair(1);
+      await buy(winner, market, true, 0.04 * SOL);
+      await buy(loser, market, false, 0.04 * SOL);
+      await resolveTestMarket(market, true);
+    });
+
+    it("Matches the actual payout for a winner", async () => {
+      const previewed = await preview(winner.publicKey);
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+
+      await program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(winner.publicKey, market))
+        .signers([winner])
+        .rpc();
+
+      const vaultAfter = await provider.connection.getBalance(market.vaultPda);
+      expect(previewed.toNumber()).to.be.greaterThan(0);
+      expect(previewed.toNumber()).to.equal(vaultBefore - vaultAfter);
+    });
+
+    it("Returns zero for a losing position", async () => {
+      const previewed = await preview(loser.publicKey);
+      expect(previewed.toNumber()).to.equal(0);
+    });
+
+    it("Returns zero for an already-claimed position", async () => {
+      const previewed = await preview(winner.publicKey);
+      expect(previewed.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("Trading Buffer", () => {
+    const BUFFER = 20;
+    const setTradingBuffer = (seconds: number) =>
+      program.methods
+        .setTradingBuffer(new anchor.BN(seconds))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    after(async () => {
+      await setTradingBuffer(0);
+    });
+
+    it("Allows buys before the buffer and blocks them inside it", async () => {
+      await setTradingBuffer(BUFFER);
+      const market = await createTestMarket(109, BUFFER + 10);
+      const trader = await fundedKeypair(1);
+
+      // ~10s before the cutoff: still tradeable
+      await buy(trader, market, true, 0.01 * anchor.web3.LAMPORTS_PER_SOL);
+
+      // Step just past resolution_time - buffer, still before resolution_time
+      const acc = await program.account.market.fetch(market.marketPda);
+      const cutoffMs = (acc.resolutionTime.toNumber() - BUFFER + 2) * 1000;
+      await delay(Math.max(0, cutoffMs - Date.now()));
+
+      try {
+        await buy(trader, market, true, 0.01 * anchor.web3.LAMPORTS_PER_SOL);
+        expect.fail("Buy inside the trading buffer should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("MarketExpired");
+      }
+    });
+
+    it("Only the authority can change the buffer", async () => {
+      const outsider = await fundedKeypair(1);
+      try {
+        await program.methods
+          .setTradingBuffer(new anchor.BN(60))
+          .accounts({ config: configPda, authority: outsider.publicKey })
+          .signers([outsider])
+          .rpc();
+        expect.fail("Non-authority should not set the buffer");
+      } catch (error) {
+        expect(error.message).to.include("Unauthorized");
+      }
+    });
+  });
+
+  describe("Market Layout Migration", () => {
+    const migrate = (m: TestMarket, signer?: anchor.web3.Keypair) => {
+      const builder = program.methods.migrateMarket().accounts({
+        config: configPda,
+        market: m.marketPda,
+        authority: signer ? signer.publicKey : authority,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      });
+      return signer ? builder.signers([signer]).rpc() : builder.rpc();
+    };
+
+    it("Is a no-op on a market already at the current layout", async () => {
+      const market = await createTestMarket(110);
+      const before = await program.account.market.fetch(market.marketPda);
+
+      await migrate(market);
+      await migrate(market);
+
+      const after = await program.account.market.fetch(market.marketPda);
+      expect(after.version).to.equal(before.version);
+      expect(after.creator.toBase58()).to.equal(before.creator.toBase58());
+      expect(after.yesLiquidity.toString()).to.equal(
+        before.yesLiquidity.toString()
+      );
+    });
+
+    it("Rejects migration by a non-authority", async () => {
+      const market = await createTestMarket(111);
+      const outsider = await fundedKeypair(1);
+      try {
+        await migrate(market, outsider);
+        expect.fail("Non-authority should not migrate markets");
+      } catch (error) {
+        expect(error.message).to.include("Unauthorized");
+      }
+    });
+
+    it("Leaves a current config untouched on migrate_config", async () => {
+      const before = await program.account.config.fetch(configPda);
+      await program.methods
+        .migrateConfig()
+        .accounts({ config: configPda, authority })
+        .rpc();
+
+      const after = await program.account.config.fetch(configPda);
+      expect(after.marketCount.toString()).to.equal(
+        before.marketCount.toString()
+      );
+      expect(after.treasury.toBase58()).to.equal(before.treasury.toBase58());
+      expect(after.maxFeeBps).to.equal(before.maxFeeBps);
+    });
+  });
+
+  describe("Trade Cooldown", () => {
+    const COOLDOWN = 5;
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    before(async () => {
+      market = await createTestMarket(112);
+      trader = await fundedKeypair(1);
+      await program.methods
+        .setTradeCooldown(new anchor.BN(COOLDOWN))
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+    });
+
+    it("Rejects a second trade inside the cooldown window", async () => {
+      await buy(trader, market, true, 0.01 * SOL);
+      try {
+        await buy(trader, market, true, 0.01 * SOL);
+        expect.fail("Second trade inside the cooldown should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("TradeCooldown");
+      }
+    });
+
+    it("Allows a trade once the cooldown has passed", async () => {
+      await delay((COOLDOWN + 2) * 1000);
+      await buy(trader, market, true, 0.01 * SOL);
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(position.lastTradeTime.toNumber()).to.be.greaterThan(0);
+    });
+  });
+
+  describe("Category Stats", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const CATEGORY = "Sports";
+    const statsPda = categoryStatsPdaFor(CATEGORY);
+
+    const fetchStats = async () => {
+      const info = await provider.connection.getAccountInfo(statsPda);
+      if (!info) return { marketCount: 0, totalVolume: 0 };
+      const stats = await program.account.categoryStats.fetch(statsPda);
+      return {
+        marketCount: stats.marketCount.toNumber(),
+        totalVolume: stats.totalVolume.toNumber(),
+      };
+    };
+
+    it("Counts markets and aggregates volume per category", async () => {
+      const before = await fetchStats();
+
+      const first = await createTestMarket(113, 3600, 0.1 * SOL, CATEGORY);
+      const second = await createTestMarket(114, 3600, 0.1 * SOL, CATEGORY);
+
+      const afterCreate = await fetchStats();
+      expect(afterCreate.marketCount).to.equal(before.marketCount + 2);
+
+      const trader = await fundedKeypair(1);
+      await buy(trader, first, true, 0.02 * SOL);
+      await buy(trader, second, false, 0.03 * SOL);
+
+      const afterBuys = await fetchStats();
+      expect(afterBuys.totalVolume).to.equal(
+        afterCreate.totalVolume + 0.05 * SOL
+      );
+    });
+
+    it("Enforces max_markets_per_category when set", async () => {
+      const current = await fetchStats();
+      await program.methods
+        .setMaxMarketsPerCategory(new anchor.BN(current.marketCount))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+      try {
+        await createTestMarket(115, 3600, 0.1 * SOL, CATEGORY);
+        expect.fail("Category at its limit should reject new markets");
+      } catch (error) {
+        expect(error.message).to.include("CategoryFull");
+      } finally {
+        await program.methods
+          .setMaxMarketsPerCategory(new anchor.BN(0))
+          .accounts({ config: configPda, authority: authority })
+          .rpc();
+      }
+    });
+  });
+
+  describe("Resolution Requires Trades", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const setRequireTrades = (enabled: boolean) =>
+      program.methods
+        .setRequireTradesToResolve(enabled)
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    after(async () => {
+      await setRequireTrades(false);
+    });
+
+    it("Resolves an untraded market while the flag is off", async () => {
+      await setRequireTrades(false);
+      const market = await createTestMarket(116, 15);
+      await resolveTestMarket(market, true);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.resolved).to.be.true;
+    });
+
+    it("Rejects an untraded market while the flag is on", async () => {
+      await setRequireTrades(true);
+      const market = await createTestMarket(117, 15);
+      try {
+        await resolveTestMarket(market, true);
+        expect.fail("Untraded market should not resolve");
+      } catch (error) {
+        expect(error.message).to.include("NoTradesToResolve");
+      }
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.resolved).to.be.false;
+    });
+
+    it("Resolves a traded market while the flag is on", async () => {
+      await setRequireTrades(true);
+      const market = await createTestMarket(118, 15);
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.01 * SOL);
+      await resolveTestMarket(market, true);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.resolved).to.be.true;
+    });
+  });
+
+  describe("Portfolio Value", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let trader: anchor.web3.Keypair;
+    let other: anchor.web3.Keypair;
+    let resolved: TestMarket;
+    let live: TestMarket[];
+
+    const portfolioAccounts = (
+      user: anchor.web3.PublicKey,
+      markets: TestMarket[]
+    ) =>
+      markets.reduce<anchor.web3.AccountMeta[]>(
+        (metas, m) =>
+          metas.concat([
+            { pubkey: m.marketPda, isSigner: false, isWritable: false },
+            { pubkey: m.vaultPda, isSigner: false, isWritable: false },
+            {
+              pubkey: positionPdaFor(user, m.marketId),
+              isSigner: false,
+              isWritable: false,
+            },
+          ]),
+        []
+      );
+
+    const portfolioValue = (
+      signer: anchor.web3.Keypair,
+      accounts: anchor.web3.AccountMeta[]
+    ) =>
+      program.methods
+        .getPortfolioValue()
+        .accounts({ user: signer.publicKey })
+        .remainingAccounts(accounts)
+        .signers([signer])
+        .view();
+
+    // Same valuation as Market::mark_to_market
+    const markToMarket = async (m: TestMarket) => {
+      const bps = await impliedYesBps(m);
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, m.marketId)
+      );
+      return position.yesShares
+        .muln(bps)
+        .add(position.noShares.muln(10000 - bps))
+        .divn(10000);
+    };
+
+    before(async () => {
+      trader = await fundedKeypair(2);
+      other = await fundedKeypair(1);
+      resolved = await createTestMarket(119, 15);
+      live = [await createTestMarket(120), await createTestMarket(121)];
+
+      await buy(trader, resolved, true, 0.02 * SOL);
+      await buy(trader, live[0], true, 0.03 * SOL);
+      await buy(trader, live[1], false, 0.01 * SOL);
+      await buy(trader, live[1], true, 0.02 * SOL);
+      await buy(other, live[0], false, 0.05 * SOL);
+
+      await resolveTestMarket(resolved, true);
+    });
+
+    it("Sums claimable and mark-to-market values", async () => {
+      const claimable: anchor.BN = await program.methods
+        .getClaimableAmount()
+        .accounts({
+          market: resolved.marketPda,
+          vault: resolved.vaultPda,
+          userPosition: positionPdaFor(trader.publicKey, resolved.marketId),
+        })
+        .view();
+      expect(claimable.toNumber()).to.be.greaterThan(0);
+
+      let expected = claimable;
+      for (const m of live) {
+        expected = expected.add(await markToMarket(m));
+      }
+
+      const total: anchor.BN = await portfolioValue(
+        trader,
+        portfolioAccounts(trader.publicKey, [resolved, ...live])
+      );
+      expect(total.toString()).to.equal(expected.toString());
+    });
+
+    it("Skips positions owned by someone else", async () => {
+      const own = portfolioAccounts(trader.publicKey, [resolved, ...live]);
+      const foreign = portfolioAccounts(other.publicKey, [live[0]]);
+
+      const withForeign: anchor.BN = await portfolioValue(trader, [
+        ...own,
+        ...foreign,
+      ]);
+      const ownOnly: anchor.BN = await portfolioValue(trader, own);
+      expect(withForeign.toString()).to.equal(ownOnly.toString());
+    });
+  });
+
+  describe("Final Claim Dust", () => {
+    // Odd sizes so per-share payouts truncate
+    const stakes = [12_345_677, 23_456_789, 7_654_321];
+
+    it("Leaves the vault at exactly the rent floor", async () => {
+      const market = await createTestMarket(122, 15);
+      const winners: anchor.web3.Keypair[] = [];
+      for (const lamports of stakes) {
+        const winner = await fundedKeypair(1);
+        await buy(winner, market, true, lamports);
+        winners.push(winner);
+      }
+      const loser = await fundedKeypair(1);
+      await buy(loser, market, false, 9_999_999);
+      await resolveTestMarket(market, true);
+
+      for (const winner of winners) {
+        await program.methods
+          .claimWinnings()
+          .accounts(claimAccounts(winner.publicKey, market))
+          .signers([winner])
+          .rpc();
+      }
+
+      const minRent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      const vault = await provider.connection.getBalance(market.vaultPda);
+      expect(vault).to.equal(minRent);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalYesShares.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("Share Transfers", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let sender: anchor.web3.Keypair;
+    let recipient: anchor.web3.Keypair;
+
+    const transfer = (isYes: boolean, shares: anchor.BN) =>
+      program.methods
+        .transferShares(isYes, shares)
+        .accounts({
+          market: market.marketPda,
+          senderPosition: positionPdaFor(sender.publicKey, market.marketId),
+          recipientPosition: positionPdaFor(
+            recipient.publicKey,
+            market.marketId
+          ),
+          recipient: recipient.publicKey,
+          sender: sender.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([sender])
+        .rpc();
+
+    const fetchPositions = async () => ({
+      sender: await program.account.userPosition.fetch(
+        positionPdaFor(sender.publicKey, market.marketId)
+      ),
+      recipient: await program.account.userPosition.fetch(
+        positionPdaFor(recipient.publicKey, market.marketId)
+      ),
+    });
+
+    before(async () => {
+      market = await createTestMarket(123, 15);
+      sender = await fundedKeypair(1);
+      recipient = anchor.web3.Keypair.generate();
+      await buy(sender, market, true, 0.03 * SOL);
+    });
+
+    it("Moves shares into a new recipient position", async () => {
+      const positionPda = positionPdaFor(sender.publicKey, market.marketId);
+      const before = await program.account.userPosition.fetch(positionPda);
+      const half = before.yesShares.divn(2);
+      const totalsBefore = await program.account.market.fetch(
+        market.marketPda
+      );
+
+      await transfer(true, half);
+
+      const after = await fetchPositions();
+      expect(after.sender.yesShares.toString()).to.equal(
+        before.yesShares.sub(half).toString()
+      );
+      expect(after.recipient.yesShares.toString()).to.equal(half.toString());
+      expect(after.recipient.user.toString()).to.equal(
+        recipient.publicKey.toString()
+      );
+
+      const totalsAfter = await program.account.market.fetch(
+        market.marketPda
+      );
+      expect(totalsAfter.totalYesShares.toString()).to.equal(
+        totalsBefore.totalYesShares.toString()
+      );
+    });
+
+    it("Rejects transfers above the sender's balance", async () => {
+      const { sender: position } = await fetchPositions();
+      try {
+        await transfer(true, position.yesShares.addn(1));
+        expect.fail("Over-balance transfer should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InsufficientShares");
+      }
+    });
+
+    it("Rejects transfers once the market is resolved", async () => {
+      await resolveTestMarket(market, true);
+      const { sender: position } = await fetchPositions();
+      try {
+        await transfer(true, position.yesShares);
+        expect.fail("Resolved market transfers should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("MarketResolved");
+      }
+    });
+  });
+
+  describe("Minimum Market Duration", () => {
+    const MIN_DURATION = 120;
+
+    const setMinDuration = (seconds: number) =>
+      program.methods
+        .setMinMarketDuration(new anchor.BN(seconds))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    before(async () => {
+      await setMinDuration(MIN_DURATION);
+    });
+
+    after(async () => {
+      await setMinDuration(0);
+    });
+
+    it("Rejects a market just under the minimum", async () => {
+      try {
+        await createTestMarket(124, MIN_DURATION - 10);
+        expect.fail("Short market should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("MarketDurationTooShort");
+      }
+    });
+
+    it("Accepts a market just over the minimum", async () => {
+      const market = await createTestMarket(125, MIN_DURATION + 10);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(
+        acc.resolutionTime.toNumber() - acc.createdAt.toNumber()
+      ).to.be.at.least(MIN_DURATION);
+    });
+
+    it("Rejects a negative minimum", async () => {
+      try {
+        await setMinDuration(-1);
+        expect.fail("Negative minimum should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InvalidMarketDuration");
+      }
+    });
+  });
+
+  describe("House Shares", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const HOUSE_SHARES = 0.05 * SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    before(async () => {
+      market = await createTestMarket(126, 15, 0.1 * SOL, "Test", HOUSE_SHARES);
+      trader = await fundedKeypair(1);
+    });
+
+    it("Counts house shares in the market totals", async () => {
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalYesShares.toNumber()).to.equal(HOUSE_SHARES);
+      expect(acc.totalNoShares.toNumber()).to.equal(HOUSE_SHARES);
+
+      const house = await program.account.userPosition.fetch(
+        positionPdaFor(authority, market.marketId)
+      );
+      expect(house.user.toString()).to.equal(authority.toString());
+      expect(house.yesShares.toNumber()).to.equal(HOUSE_SHARES);
+      expect(house.noShares.toNumber()).to.equal(HOUSE_SHARES);
+
+      // Both sides are held equally, so the book opens at even odds
+      expect(await impliedYesBps(market)).to.equal(5000);
+    });
+
+    it("Lets the house claim its winning side", async () => {
+      await buy(trader, market, true, 0.02 * SOL);
+      await resolveTestMarket(market, true);
+
+      const houseBalanceBefore = await provider.connection.getBalance(
+        authority
+      );
+      await program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(authority, market))
+        .rpc();
+
+      const house = await program.account.userPosition.fetch(
+        positionPdaFor(authority, market.marketId)
+      );
+      expect(house.claimed).to.be.true;
+      expect(
+        await provider.connection.getBalance(authority)
+      ).to.be.greaterThan(houseBalanceBefore);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      const traderPosition = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(acc.totalYesShares.toString()).to.equal(
+        traderPosition.yesShares.toString()
+      );
+    });
+
+    it("Rejects more house shares than the initial liquidity", async () => {
+      try {
+        await createTestMarket(127, 15, 0.1 * SOL, "Test", 0.2 * SOL);
+        expect.fail("Unbacked house shares should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InvalidHouseShares");
+      }
+    });
+  });
+
+  describe("LMSR Markets", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const B = 0.1 * SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    // Reference LMSR in floating point: shares bought for `cost` lamports
+    const expectedShares = (
+      qBought: number,
+      qOther: number,
+      cost: number
+    ) => {
+      const a = qBought / B;
+      const n = qOther / B;
+      const x = cost / B;
+      return B * (x + Math.log(1 + Math.exp(n - a) * (1 - Math.exp(-x))));
+    };
+
+    before(async () => {
+      market = await createTestMarket(128, 3600, B, "Test", 0, { lmsr: {} });
+      trader = await fundedKeypair(2);
+    });
+
+    it("Seeds the vault with the b ln 2 loss bound", async () => {
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.marketType).to.deep.equal({ lmsr: {} });
+      expect(acc.creatorDeposit.toNumber()).to.equal(
+        Math.ceil(B * Math.LN2)
+      );
+    });
+
+    it("Prices buys with the LMSR cost function", async () => {
+      const amount = 0.02 * SOL;
+      const config = await program.account.config.fetch(configPda);
+      const afterFee = amount - (amount * config.feePercentage) / 10000;
+      const before = await program.account.market.fetch(market.marketPda);
+
+      await buy(trader, market, true, amount);
+
+      const after = await program.account.market.fetch(market.marketPda);
+      const bought = after.totalYesShares
+        .sub(before.totalYesShares)
+        .toNumber();
+      const expected = expectedShares(
+        before.totalYesShares.toNumber(),
+        before.totalNoShares.toNumber(),
+        afterFee
+      );
+      expect(Math.abs(bought - expected)).to.be.at.most(5);
+
+      // LMSR leaves the constant-product reserves untouched
+      expect(after.yesLiquidity.toString()).to.equal(
+        before.yesLiquidity.toString()
+      );
+    });
+
+    it("Moves the implied price monotonically with buys", async () => {
+      const prices: number[] = [];
+      for (let i = 0; i < 3; i++) {
+        await buy(trader, market, true, 0.01 * SOL);
+        const summary = await program.methods
+          .getMarketSummary()
+          .accounts({
+            market: market.marketPda,
+            metadata: market.metadataPda,
+          })
+          .view();
+        prices.push(summary.impliedYesBps);
+      }
+      expect(prices[0]).to.be.greaterThan(5000);
+      expect(prices[1]).to.be.greaterThan(prices[0]);
+      expect(prices[2]).to.be.greaterThan(prices[1]);
+    });
+  });
+
+  describe("Trade Sequence Numbers", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const sequenceOf = async (sig: string, eventName: string) => {
+      await provider.connection.confirmTransaction(sig, "confirmed");
+      const event = findEvent(await eventsFromTx(sig), eventName);
+      expect(event, `${eventName} should be emitted`).to.not.be.undefined;
+      return (event.data.sequence as anchor.BN).toNumber();
+    };
+
+    it("Numbers trades in a market strictly increasing", async () => {
+      const market = await createTestMarket(129);
+      const trader = await fundedKeypair(1);
+
+      const first = await sequenceOf(
+        await buy(trader, market, true, 0.01 * SOL),
+        "BuySharesEvent"
+      );
+      const second = await sequenceOf(
+        await buy(trader, market, false, 0.01 * SOL),
+        "BuySharesEvent"
+      );
+      expect(second).to.be.greaterThan(first);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.tradeSequence.toNumber()).to.equal(second);
+    });
+  });
+
+  describe("Fee Buckets", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const BUCKET = 1;
+    const bucketPda = feeBucketPdaFor(BUCKET);
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const balance = (pubkey: anchor.web3.PublicKey) =>
+      provider.connection.getBalance(pubkey);
+
+    before(async () => {
+      await program.methods
+        .initFeeBucket(BUCKET)
+        .accounts({
+          config: configPda,
+          feeVault: bucketPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+      market = await createTestMarket(130);
+      trader = await fundedKeypair(1);
+    });
+
+    it("Routes fees to the requested bucket only", async () => {
+      const amount = 0.05 * SOL;
+      const config = await program.account.config.fetch(configPda);
+      const fee = Math.floor((amount * config.feePercentage) / 10000);
+      const defaultBefore = await balance(feeVaultPda);
+      const bucketBefore = await balance(bucketPda);
+
+      await buy(trader, market, true, amount, 0, BUCKET);
+
+      expect((await balance(bucketPda)) - bucketBefore).to.equal(fee);
+      expect(await balance(feeVaultPda)).to.equal(defaultBefore);
+
+      await buy(trader, market, true, amount);
+
+      expect((await balance(feeVaultPda)) - defaultBefore).to.equal(fee);
+    });
+
+    it("Rejects a fee vault from another bucket", async () => {
+      try {
+        await program.methods
+          .buyShares(true, new anchor.BN(0.01 * SOL), new anchor.BN(0), 2, null)
+          .accounts({
+            config: configPda,
+            market: market.marketPda,
+            vault: market.vaultPda,
+            feeVault: bucketPda,
+            categoryStats: categoryStatsPdaFor(market.category),
+            userPosition: positionPdaFor(trader.publicKey, market.marketId),
+            user: trader.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            cosigner: null,
+            whitelistEntry: null,
+            insuranceVault: null,
+          })
+          .signers([trader])
+          .rpc();
+        expect.fail("Mismatched fee vault should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InvalidFeeVault");
+      }
+    });
+
+    it("Withdraws from one bucket without touching another", async () => {
+      const defaultBefore = await balance(feeVaultPda);
+      const bucketBefore = await balance(bucketPda);
+      const minRent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      const amount = bucketBefore - minRent;
+      expect(amount).to.be.greaterThan(0);
+
+      await program.methods
+        .withdrawFees(new anchor.BN(amount), BUCKET)
+        .accounts({
+          config: configPda,
+          feeVault: bucketPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      expect(await balance(bucketPda)).to.equal(minRent);
+      expect(await balance(feeVaultPda)).to.equal(defaultBefore);
+    });
+  });
+
+  describe("AMM State", () => {
+    it("Keeps reserves within rounding of k after several buys", async () => {
+      const market = await createTestMarket(131);
+      const trader = await fundedKeypair(2);
+      const buys: [boolean, number][] = [
+        [true, 13_000_000],
+        [false, 21_000_000],
+        [true, 7_100_000],
+        [false, 33_300_000],
+      ];
+      for (const [isYes, lamports] of buys) {
+        await buy(trader, market, isYes, lamports);
+      }
+
+      const state = await program.methods
+        .getAmmState()
+        .accounts({ market: market.marketPda })
+        .view();
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(state.yesLiquidity.toString()).to.equal(
+        acc.yesLiquidity.toString()
+      );
+      expect(state.noLiquidity.toString()).to.equal(
+        acc.noLiquidity.toString()
+      );
+      expect(state.kConstant.toString()).to.equal(acc.kConstant.toString());
+      expect(state.invariantOk).to.be.true;
+
+      const product = state.yesLiquidity.mul(state.noLiquidity);
+      const precision = acc.ammPrecision;
+      const k = state.kConstant.div(precision).div(precision);
+      const tolerance = state.yesLiquidity.add(state.noLiquidity).addn(1);
+      expect(product.sub(k).abs().lte(tolerance)).to.be.true;
+      // Pool-favoring rounding never lets the invariant fall below k
+      expect(product.gte(k)).to.be.true;
+    });
+  });
+
+  describe("Maximum Market Duration", () => {
+    const MAX_DURATION = 7 * 24 * 3600;
+
+    const setMaxDuration = (seconds: number) =>
+      program.methods
+        .setMaxMarketDuration(new anchor.BN(seconds))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    before(async () => {
+      await setMaxDuration(MAX_DURATION);
+    });
+
+    after(async () => {
+      await setMaxDuration(0);
+    });
+
+    it("Accepts a resolution time just within the maximum", async () => {
+      const market = await createTestMarket(132, MAX_DURATION - 60);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(
+        acc.resolutionTime.toNumber() - acc.createdAt.toNumber()
+      ).to.be.at.most(MAX_DURATION);
+    });
+
+    it("Rejects a resolution time just beyond the maximum", async () => {
+      try {
+        await createTestMarket(133, MAX_DURATION + 60);
+        expect.fail("Far-off market should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("ResolutionTimeTooFar");
+      }
+    });
+  });
+
+  describe("Delegated Trading Escrow", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let owner: anchor.web3.Keypair;
+    let operator: anchor.web3.Keypair;
+    let escrowPda: anchor.web3.PublicKey;
+
+    const buyDelegated = (signer: anchor.web3.Keypair, lamports: number) =>
+      program.methods
+        .buySharesDelegated(true, new anchor.BN(lamports), new anchor.BN(0))
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          escrow: escrowPda,
+          userPosition: positionPdaFor(owner.publicKey, market.marketId),
+          owner: owner.publicKey,
+          operator: signer.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: null,
+          insuranceVault: null,
+        })
+        .signers([signer])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(134);
+      owner = await fundedKeypair(2);
+      operator = await fundedKeypair(1);
+      escrowPda = findPda([Buffer.from("escrow"), owner.publicKey.toBuffer()]);
+
+      await program.methods
+        .openTradingEscrow(operator.publicKey, new anchor.BN(0.5 * SOL))
+        .accounts({
+          escrow: escrowPda,
+          owner: owner.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+      await openPosition(owner, market);
+    });
+
+    it("Lets the operator buy for the owner from the escrow", async () => {
+      const amount = 0.1 * SOL;
+      const escrowBefore = await provider.connection.getBalance(escrowPda);
+      const ownerBefore = await provider.connection.getBalance(
+        owner.publicKey
+      );
+
+      await buyDelegated(operator, amount);
+
+      const escrowAfter = await provider.connection.getBalance(escrowPda);
+      expect(escrowBefore - escrowAfter).to.equal(amount);
+      expect(await provider.connection.getBalance(owner.publicKey)).to.equal(
+        ownerBefore
+      );
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(owner.publicKey, market.marketId)
+      );
+      expect(position.user.toString()).to.equal(owner.publicKey.toString());
+      expect(position.yesShares.toNumber()).to.be.greaterThan(0);
+    });
+
+    it("Rejects an operator the escrow did not register", async () => {
+      const intruder = await fundedKeypair(1);
+      try {
+        await buyDelegated(intruder, 0.01 * SOL);
+        expect.fail("Unregistered operator should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("Unauthorized");
+      }
+    });
+
+    it("Lets the owner withdraw the remaining escrow", async () => {
+      const minRent =
+        await provider.connection.getMinimumBalanceForRentExemption(
+          8 + 32 + 32 + 1
+        );
+      const escrowBefore = await provider.connection.getBalance(escrowPda);
+      const available = escrowBefore - minRent;
+
+      await program.methods
+        .withdrawEscrow(new anchor.BN(available))
+        .accounts({ escrow: escrowPda, owner: owner.publicKey })
+        .signers([owner])
+        .rpc();
+
+      expect(await provider.connection.getBalance(escrowPda)).to.equal(
+        minRent
+      );
+
+      try {
+        await buyDelegated(operator, 0.01 * SOL);
+        expect.fail("Empty escrow should not fund trades");
+      } catch (error) {
+        expect(error.message).to.include("InsufficientEscrow");
+      }
+    });
+  });
+
+  describe("Resolution Evidence", () => {
+    const evidenceUri = "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3o";
+    const evidenceHash = Array.from(
+      createHash("sha256").update("resolution source").digest()
+    );
+
+    const resolveWithEvidence = (m: TestMarket, uri: string) =>
+      program.methods
+        .resolveWithEvidence(true, evidenceHash, uri)
+        .accounts({
+          config: configPda,
+          market: m.marketPda,
+          authority: authority,
+        })
+        .rpc({ commitment: "confirmed" });
+
+    const waitForResolution = async (m: TestMarket) => {
+      const acc = await program.account.market.fetch(m.marketPda);
+      const waitMs = (acc.resolutionTime.toNumber() + 2) * 1000 - Date.now();
+      if (waitMs > 0) await delay(waitMs);
+    };
+
+    it("Stores the evidence and includes it in the event", async () => {
+      const market = await createTestMarket(135, 15);
+      await waitForResolution(market);
+
+      const sig = await resolveWithEvidence(market, evidenceUri);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.resolved).to.be.true;
+      expect(Array.from(acc.evidenceHash)).to.deep.equal(evidenceHash);
+      expect(acc.evidenceUri).to.equal(evidenceUri);
+
+      const event = findEvent(await eventsFromTx(sig), "MarketResolvedEvent");
+      expect(event, "MarketResolvedEvent should be emitted").to.not.be
+        .undefined;
+      expect(event.data.outcome).to.be.true;
+      expect(Array.from(event.data.evidenceHash as number[])).to.deep.equal(
+        evidenceHash
+      );
+      expect(event.data.evidenceUri).to.equal(evidenceUri);
+    });
+
+    it("Rejects an evidence URI over the length limit", async () => {
+      const market = await createTestMarket(136, 15);
+      await waitForResolution(market);
+      try {
+        await resolveWithEvidence(market, "x".repeat(201));
+        expect.fail("Overlong URI should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("EvidenceUriTooLong");
+      }
+    });
+  });
+
+  describe("Market Close", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+
+    const closeMarket = (m: TestMarket) =>
+      program.methods
+        .closeMarket()
+        .accounts({
+          config: configPda,
+          market: m.marketPda,
+          metadata: m.metadataPda,
+          vault: m.vaultPda,
+          feeVault: feeVaultPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: insuranceVaultPdaFor(m.marketId),
+        })
+        .rpc({ commitment: "confirmed" });
+
+    before(async () => {
+      market = await createTestMarket(137, 15);
+      const loser = await fundedKeypair(1);
+      await buy(loser, market, true, 0.02 * SOL);
+      await resolveTestMarket(market, false);
+    });
+
+    it("Waits for the creator to withdraw their liquidity", async () => {
+      try {
+        await closeMarket(market);
+        expect.fail("Close should wait for the creator withdrawal");
+      } catch (error) {
+        expect(error.message).to.include("CreatorLiquidityOutstanding");
+      }
+    });
+
+    it("Sends residual to the fee vault, rent to the authority", async () => {
+      await program.methods
+        .withdrawCreatorLiquidity()
+        .accounts({
+          market: market.marketPda,
+          vault: market.vaultPda,
+          creator: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      const connection = provider.connection;
+      const vaultFloor = await connection.getMinimumBalanceForRentExemption(0);
+      const vaultBefore = await connection.getBalance(market.vaultPda);
+      const residual = vaultBefore - vaultFloor;
+      expect(residual).to.be.greaterThan(0);
+
+      const accountRent =
+        (await connection.getBalance(market.marketPda)) +
+        (await connection.getBalance(market.metadataPda));
+      // The insurance vault closes into the fee vault, rent included
+      const insurance = await connection.getBalance(
+        insuranceVaultPdaFor(market.marketId)
+      );
+      const feeVaultBefore = await connection.getBalance(feeVaultPda);
+      const authorityBefore = await connection.getBalance(authority);
+
+      const sig = await closeMarket(market);
+      const tx = await connection.getTransaction(sig, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+
+      expect((await connection.getBalance(feeVaultPda)) - feeVaultBefore).to
+        .equal(residual + insurance);
+      expect(
+        (await connection.getBalance(authority)) -
+          authorityBefore +
+          tx.meta.fee
+      ).to.equal(accountRent + vaultFloor);
+
+      expect(await connection.getAccountInfo(market.marketPda)).to.be.null;
+      expect(await connection.getBalance(market.vaultPda)).to.equal(0);
+      expect(
+        await connection.getAccountInfo(insuranceVaultPdaFor(market.marketId))
+      ).to.be.null;
+
+      const [entry] = (await program.methods.getAuditLog().view()).slice(-1);
+      expect(Object.keys(entry.action)[0]).to.equal("marketClosed");
+      expect(entry.marketId.toNumber()).to.equal(market.marketId);
+      expect(entry.amount.toNumber()).to.equal(residual);
+    });
+
+    const cancel = (m: TestMarket) =>
+      program.methods
+        .cancelMarket()
+        .accounts({ config: configPda, market: m.marketPda, authority })
+        .rpc();
+
+    it("Closes a cancelled market once every refund is taken", async () => {
+      const cancelled = await createTestMarket(225);
+      await cancel(cancelled);
+      await program.methods
+        .withdrawCreatorLiquidity()
+        .accounts({
+          market: cancelled.marketPda,
+          vault: cancelled.vaultPda,
+          creator: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      const activeBefore = (await program.account.config.fetch(configPda))
+        .activeMarkets;
+      await closeMarket(cancelled);
+
+      expect(await provider.connection.getAccountInfo(cancelled.marketPda)).to
+        .be.null;
+      // Cancelling already took it off the active count
+      const activeAfter = (await program.account.config.fetch(configPda))
+        .activeMarkets;
+      expect(activeAfter.toString()).to.equal(activeBefore.toString());
+    });
+
+    it("Keeps a cancelled market open while refunds remain", async () => {
+      const cancelled = await createTestMarket(226);
+      const trader = await fundedKeypair(1);
+      await buy(trader, cancelled, true, 0.02 * SOL);
+      await cancel(cancelled);
+      try {
+        await closeMarket(cancelled);
+        expect.fail("Close should wait for outstanding refunds");
+      } catch (error) {
+        expect(error.message).to.include("RefundsOutstanding");
+      }
+    });
+
+    it("Refuses to close without the insurance vault", async () => {
+      const cancelled = await createTestMarket(231);
+      await cancel(cancelled);
+      await program.methods
+        .withdrawCreatorLiquidity()
+        .accounts({
+          market: cancelled.marketPda,
+          vault: cancelled.vaultPda,
+          creator: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      const insurance = insuranceVaultPdaFor(cancelled.marketId);
+      const ix = await program.methods
+        .closeMarket()
+        .accounts({
+          config: configPda,
+          market: cancelled.marketPda,
+          metadata: cancelled.metadataPda,
+          vault: cancelled.vaultPda,
+          feeVault: feeVaultPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: insurance,
+        })
+        .instruction();
+      ix.keys = ix.keys.filter((meta) => !meta.pubkey.equals(insurance));
+      try {
+        await provider.sendAndConfirm(new anchor.web3.Transaction().add(ix));
+        expect.fail("Close should need the insurance vault");
+      } catch (error) {
+        expect(error).to.exist;
+      }
+
+      // Both stay open, so a close with the vault still sweeps it
+      expect(await provider.connection.getAccountInfo(cancelled.marketPda)).to
+        .not.be.null;
+      expect(await provider.connection.getAccountInfo(insurance)).to.not.be
+        .null;
+      await closeMarket(cancelled);
+      expect(await provider.connection.getAccountInfo(insurance)).to.be.null;
+    });
+  });
+
+  describe("Price-Protected Buys", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const TOLERANCE_BPS = 50;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const buyProtected = (expectedYesBps: number) =>
+      program.methods
+        .buySharesProtected(
+          true,
+          new anchor.BN(0.01 * SOL),
+          expectedYesBps,
+          TOLERANCE_BPS
+        )
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: null,
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(138);
+      trader = await fundedKeypair(1);
+      await openPosition(trader, market);
+    });
+
+    it("Fills when the price is where the user saw it", async () => {
+      const observed = await impliedYesBps(market);
+      await buyProtected(observed);
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(position.yesShares.toNumber()).to.be.greaterThan(0);
+    });
+
+    it("Reverts when a front-run moved the price too far", async () => {
+      const observed = await impliedYesBps(market);
+
+      // Someone else buys YES first and pushes the price up
+      const frontRunner = await fundedKeypair(1);
+      await buy(frontRunner, market, true, 0.05 * SOL);
+      const moved = await impliedYesBps(market);
+      expect(moved - observed).to.be.greaterThan(TOLERANCE_BPS);
+
+      try {
+        await buyProtected(observed);
+        expect.fail("Moved price should revert the buy");
+      } catch (error) {
+        expect(error.message).to.include("PriceMoved");
+      }
+    });
+  });
+
+  describe("Repeat Buys On Existing Positions", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+
+    const buyExisting = (trader: anchor.web3.Keypair, lamports: number) =>
+      program.methods
+        .buySharesExisting(
+          true,
+          new anchor.BN(lamports),
+          new anchor.BN(0),
+          null
+        )
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: null,
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(139);
+    });
+
+    it("Credits shares on a repeat buy through the fast path", async () => {
+      const trader = await fundedKeypair(1);
+      const positionPda = positionPdaFor(trader.publicKey, market.marketId);
+      await buy(trader, market, true, 0.01 * SOL);
+      const before = await program.account.userPosition.fetch(positionPda);
+
+      const { shares } = await quoteBuy(market, true, 0.01 * SOL);
+      await buyExisting(trader, 0.01 * SOL);
+
+      const after = await program.account.userPosition.fetch(positionPda);
+      expect(
+        after.yesShares.sub(before.yesShares).toString()
+      ).to.equal(shares.toString());
+    });
+
+    it("Fails cleanly when the position does not exist yet", async () => {
+      const newcomer = await fundedKeypair(1);
+      try {
+        await buyExisting(newcomer, 0.01 * SOL);
+        expect.fail("Fast path should require an existing position");
+      } catch (error) {
+        expect(error.message).to.include("AccountNotInitialized");
+      }
+    });
+  });
+
+  describe("Minimum Reserve Floor", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const LIQUIDITY = 0.01 * SOL;
+    let market: TestMarket;
+
+    before(async () => {
+      market = await createTestMarket(140, 3600, LIQUIDITY);
+    });
+
+    it("Rejects a buy that would consume ~99% of a reserve", async () => {
+      // Buying YES with 99x the pool leaves ~1% of the NO reserve behind
+      const whale = await fundedKeypair(2);
+      try {
+        await buy(whale, market, true, 99 * LIQUIDITY);
+        expect.fail("Oversized trade should hit the reserve floor");
+      } catch (error) {
+        expect(error.message).to.include("TradeExceedsLiquidity");
+      }
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.noLiquidity.toNumber()).to.equal(LIQUIDITY);
+    });
+
+    it("Still fills trades that stay above the floor", async () => {
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.01 * SOL);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.noLiquidity.toNumber()).to.be.within(1_000_000, LIQUIDITY);
+    });
+  });
+
+  describe("Contributions And Cancellation Refunds", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let alice: anchor.web3.Keypair;
+    let bob: anchor.web3.Keypair;
+
+    const contributedBy = async (trader: anchor.web3.Keypair) =>
+      (
+        await program.account.userPosition.fetch(
+          positionPdaFor(trader.publicKey, market.marketId)
+        )
+      ).lamportsContributed;
+
+    const claimRefund = (trader: anchor.web3.Keypair) =>
+      program.methods
+        .claimRefund()
+        .accounts({
+          market: market.marketPda,
+          vault: market.vaultPda,
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(141);
+      alice = await fundedKeypair(1);
+      bob = await fundedKeypair(1);
+    });
+
+    it("Tracks contributed lamports that match the vault inflow", async () => {
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+      await buy(alice, market, true, 0.02 * SOL);
+      await buy(bob, market, false, 0.03 * SOL);
+      await buy(alice, market, false, 0.01 * SOL);
+      const vaultAfter = await provider.connection.getBalance(market.vaultPda);
+
+      const total = (await contributedBy(alice)).add(await contributedBy(bob));
+      expect(total.toNumber()).to.equal(vaultAfter - vaultBefore);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalContributed.toString()).to.equal(total.toString());
+    });
+
+    it("Releases contributions pro rata on a sell", async () => {
+      const positionPda = positionPdaFor(alice.publicKey, market.marketId);
+      const before = await program.account.userPosition.fetch(positionPda);
+      const sold = before.yesShares.divn(2);
+
+      await program.methods
+        .sellShares(true, sold, new anchor.BN(1))
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPda,
+          user: alice.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: null,
+        })
+        .signers([alice])
+        .rpc();
+
+      const held = before.yesShares.add(before.noShares);
+      const released = before.lamportsContributed.mul(sold).div(held);
+      expect((await contributedBy(alice)).toString()).to.equal(
+        before.lamportsContributed.sub(released).toString()
+      );
+    });
+
+    it("Refunds each position exactly what it contributed", async () => {
+      await program.methods
+        .cancelMarket()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+
+      try {
+        await buy(bob, market, true, 0.01 * SOL);
+        expect.fail("Cancelled market should not trade");
+      } catch (error) {
+        expect(error.message).to.include("MarketCancelled");
+      }
+
+      for (const trader of [alice, bob]) {
+        const owed = await contributedBy(trader);
+        const vaultBefore = await provider.connection.getBalance(
+          market.vaultPda
+        );
+        await claimRefund(trader);
+        const vaultAfter = await provider.connection.getBalance(
+          market.vaultPda
+        );
+        expect(vaultBefore - vaultAfter).to.equal(owed.toNumber());
+      }
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalContributed.toNumber()).to.equal(0);
+
+      try {
+        await claimRefund(alice);
+        expect.fail("Refund should only be paid once");
+      } catch (error) {
+        expect(error.message).to.include("AlreadyClaimed");
+      }
+    });
+  });
+
+  describe("Fee Balance", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const feeBalance = () =>
+      program.methods
+        .getFeeBalance(0)
+        .accounts({ feeVault: feeVaultPda })
+        .view();
+
+    it("Reports collected fees net of the rent floor", async () => {
+      const market = await createTestMarket(142);
+      const trader = await fundedKeypair(1);
+      const config = await program.account.config.fetch(configPda);
+      const before = await feeBalance();
+
+      const amounts = [0.01 * SOL, 0.02 * SOL, 0.005 * SOL];
+      let collected = new anchor.BN(0);
+      for (const lamports of amounts) {
+        await buy(trader, market, true, lamports);
+        collected = collected.add(
+          new anchor.BN(lamports).muln(config.feePercentage).divn(10000)
+        );
+      }
+
+      const after = await feeBalance();
+      expect(after.sub(before).toString()).to.equal(collected.toString());
+
+      const rent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      const balance = await provider.connection.getBalance(feeVaultPda);
+      expect(after.toNumber()).to.equal(balance - rent);
+    });
+  });
+
+  describe("Scalar Markets", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const scalarType = {
+      scalar: { lowerBound: new anchor.BN(0), upperBound: new anchor.BN(100) },
+    };
+
+    // One YES holder and one NO holder, so each settlement splits a known pool
+    const setUp = async (marketId: number, threshold?: object) => {
+      const m = await createTestMarket(
+        marketId,
+        15,
+        0.1 * SOL,
+        "Test",
+        0,
+        scalarType
+      );
+      if (threshold) {
+        await program.methods
+          .setScalarThreshold(threshold)
+          .accounts({ config: configPda, market: m.marketPda, authority })
+          .rpc();
+      }
+      const long = await fundedKeypair(1);
+      const short = await fundedKeypair(1);
+      await buy(long, m, true, 0.02 * SOL);
+      await buy(short, m, false, 0.03 * SOL);
+      return { m, long, short };
+    };
+
+    const settle = async (m: TestMarket, value: number) => {
+      const acc = await program.account.market.fetch(m.marketPda);
+      const waitMs = (acc.resolutionTime.toNumber() + 2) * 1000 - Date.now();
+      if (waitMs > 0) await delay(waitMs);
+      await program.methods
+        .resolveScalar(new anchor.BN(value))
+        .accounts({ config: configPda, market: m.marketPda, authority })
+        .rpc();
+    };
+
+    const claim = async (m: TestMarket, trader: anchor.web3.Keypair) => {
+      const before = await provider.connection.getBalance(m.vaultPda);
+      await program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(trader.publicKey, m))
+        .signers([trader])
+        .rpc();
+      return before - (await provider.connection.getBalance(m.vaultPda));
+    };
+
+    const distributable = async (m: TestMarket) => {
+      const rent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      return (await provider.connection.getBalance(m.vaultPda)) - rent;
+    };
+
+    it("Splits the pool by weight at the midpoint", async () => {
+      const { m, long, short } = await setUp(143);
+      await settle(m, 50);
+
+      const acc = await program.account.market.fetch(m.marketPda);
+      expect(acc.settlementValue.toNumber()).to.equal(50);
+
+      // Both sides are worth half a share each
+      const yes = acc.totalYesShares.muln(5000).divn(10000);
+      const total = acc.totalYesShares
+        .muln(5000)
+        .add(acc.totalNoShares.muln(5000))
+        .divn(10000);
+      const pool = await distributable(m);
+      const pps = new anchor.BN(pool).mul(PRECISION).div(total);
+
+      const longPayout = await claim(m, long);
+      expect(longPayout).to.equal(yes.mul(pps).div(PRECISION).toNumber());
+
+      // The last claimant sweeps what is left
+      const shortPayout = await claim(m, short);
+      expect(longPayout + shortPayout).to.equal(pool);
+    });
+
+    it("Pays YES in full when settling at the upper bound", async () => {
+      const { m, long, short } = await setUp(144);
+      await settle(m, 100);
+
+      try {
+        await claim(m, short);
+        expect.fail("NO shares are worth nothing at the upper bound");
+      } catch (error) {
+        expect(error.message).to.include("NoWinningShares");
+      }
+
+      const pool = await distributable(m);
+      expect(await claim(m, long)).to.equal(pool);
+    });
+
+    it("Clamps a settlement beyond the bounds", async () => {
+      const { m, long, short } = await setUp(145);
+      await settle(m, -40);
+
+      const acc = await program.account.market.fetch(m.marketPda);
+      expect(acc.settlementValue.toNumber()).to.equal(0);
+
+      try {
+        await claim(m, long);
+        expect.fail("YES shares are worth nothing at the lower bound");
+      } catch (error) {
+        expect(error.message).to.include("NoWinningShares");
+      }
+
+      const pool = await distributable(m);
+      expect(await claim(m, short)).to.equal(pool);
+    });
+
+    const threshold = (atThreshold: object) => ({
+      value: new anchor.BN(40),
+      atThreshold,
+    });
+
+    it("Pays the over side in full past a threshold", async () => {
+      const { m, long, short } = await setUp(228, threshold({ split: {} }));
+      await settle(m, 41);
+
+      try {
+        await claim(m, short);
+        expect.fail("NO shares are worth nothing over the threshold");
+      } catch (error) {
+        expect(error.message).to.include("NoWinningShares");
+      }
+      const pool = await distributable(m);
+      expect(await claim(m, long)).to.equal(pool);
+    });
+
+    it("Splits the pool evenly exactly on a split threshold", async () => {
+      const { m, long, short } = await setUp(229, threshold({ split: {} }));
+      await settle(m, 40);
+
+      const acc = await program.account.market.fetch(m.marketPda);
+      const total = acc.totalYesShares.add(acc.totalNoShares).divn(2);
+      const pool = await distributable(m);
+      const pps = new anchor.BN(pool).mul(PRECISION).div(total);
+
+      const longPayout = await claim(m, long);
+      expect(longPayout).to.equal(
+        acc.totalYesShares.divn(2).mul(pps).div(PRECISION).toNumber()
+      );
+      expect(longPayout + (await claim(m, short))).to.equal(pool);
+    });
+
+    it("Cancels for refunds exactly on a refund threshold", async () => {
+      const { m, long } = await setUp(230, threshold({ refund: {} }));
+      await settle(m, 40);
+
+      const acc = await program.account.market.fetch(m.marketPda);
+      expect(acc.cancelled).to.equal(true);
+      expect(acc.resolved).to.equal(false);
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(long.publicKey, m.marketId)
+      );
+      const before = await provider.connection.getBalance(m.vaultPda);
+      await program.methods
+        .claimRefund()
+        .accounts(claimAccounts(long.publicKey, m))
+        .signers([long])
+        .rpc();
+      expect(
+        before - (await provider.connection.getBalance(m.vaultPda))
+      ).to.equal(position.lamportsContributed.toNumber());
+    });
+  });
+
+  describe("Atomic Market Creation", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const MARKET_ID = 146;
+
+    const moveLamports = (
+      from: anchor.web3.PublicKey,
+      to: anchor.web3.PublicKey,
+      lamports: number,
+      signers: anchor.web3.Keypair[] = []
+    ) =>
+      provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          anchor.web3.SystemProgram.transfer({
+            fromPubkey: from,
+            toPubkey: to,
+            lamports,
+          })
+        ),
+        signers
+      );
+
+    it("Leaves no market behind when the creator can't fund it", async () => {
+      // Park all but 1 SOL elsewhere; a 1 SOL market needs a 2 SOL deposit
+      const holder = anchor.web3.Keypair.generate();
+      const balance = await provider.connection.getBalance(authority);
+      await moveLamports(authority, holder.publicKey, balance - 1 * SOL);
+
+      try {
+        await createTestMarket(MARKET_ID, 3600, 1 * SOL);
+        expect.fail("Creation should fail without the seed deposit");
+      } catch (error) {
+        expect(error.message).to.include("InsufficientFunds");
+      } finally {
+        const parked = await provider.connection.getBalance(holder.publicKey);
+        await moveLamports(holder.publicKey, authority, parked - 5000, [
+          holder,
+        ]);
+      }
+
+      for (const pda of [
+        marketPdaFor(MARKET_ID),
+        metadataPdaFor(MARKET_ID),
+        vaultPdaFor(MARKET_ID),
+      ]) {
+        expect(await provider.connection.getAccountInfo(pda)).to.be.null;
+      }
+
+      // Nothing half-initialized blocks the id from being used again
+      const market = await createTestMarket(MARKET_ID, 3600, 1 * SOL);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(await provider.connection.getBalance(market.vaultPda)).to.equal(
+        acc.creatorDeposit.toNumber()
+      );
+    });
+  });
+
+  describe("Per-Market AMM Precision", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const LIQUIDITY = 0.01 * SOL;
+
+    const createAtPrecision = (marketId: number, precision: number) =>
+      createTestMarket(
+        marketId,
+        3600,
+        LIQUIDITY,
+        "Test",
+        0,
+        { constantProduct: {} },
+        precision
+      );
+
+    for (const [marketId, precision] of [
+      [147, 1_000],
+      [148, 1_000_000_000_000],
+    ]) {
+      it(`Prices buys exactly at precision ${precision}`, async () => {
+        const market = await createAtPrecision(marketId, precision);
+        const acc = await program.account.market.fetch(market.marketPda);
+        expect(acc.ammPrecision.toString()).to.equal(precision.toString());
+
+        const trader = await fundedKeypair(1);
+        const positionPda = positionPdaFor(trader.publicKey, marketId);
+        // Dust-sized and sizeable buys must neither truncate nor overflow
+        for (const lamports of [10_000, 0.005 * SOL]) {
+          const before = await program.account.userPosition
+            .fetch(positionPda)
+            .then((p) => p.yesShares)
+            .catch(() => new anchor.BN(0));
+          const { shares } = await quoteBuy(market, true, lamports);
+          expect(shares.gtn(0)).to.be.true;
+
+          await buy(trader, market, true, lamports);
+          const after = await program.account.userPosition.fetch(positionPda);
+          expect(after.yesShares.sub(before).toString()).to.equal(
+            shares.toString()
+          );
+        }
+      });
+    }
+
+    it("Rejects a precision outside the safe range", async () => {
+      try {
+        await createAtPrecision(149, 10);
+        expect.fail("Precision below the minimum should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InvalidAmmPrecision");
+      }
+    });
+  });
+
+  describe("Fee Cap", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const CAP_BPS = 500;
+    let market: TestMarket;
+
+    const setMaxFee = (bps: number) =>
+      program.methods
+        .setMaxFeeBps(bps)
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    const setMarketFee = (bps: number | null) =>
+      program.methods
+        .setMarketFee(bps)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(150);
+      await setMaxFee(CAP_BPS);
+    });
+
+    after(async () => {
+      await setMaxFee(1_000);
+    });
+
+    it("Rejects a per-market fee above the cap", async () => {
+      try {
+        await setMarketFee(CAP_BPS + 1);
+        expect.fail("Fee above the cap should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("FeeTooHigh");
+      }
+
+      try {
+        await program.methods
+          .setFee(CAP_BPS + 1)
+          .accounts({ config: configPda, authority: authority })
+          .rpc();
+        expect.fail("Global fee above the cap should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("FeeTooHigh");
+      }
+    });
+
+    it("Accepts and charges a per-market fee at the cap", async () => {
+      await setMarketFee(CAP_BPS);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.feeBps).to.equal(CAP_BPS);
+
+      const trader = await fundedKeypair(1);
+      const lamports = 0.01 * SOL;
+      const before = await provider.connection.getBalance(feeVaultPda);
+      await buy(trader, market, true, lamports);
+      const after = await provider.connection.getBalance(feeVaultPda);
+      expect(after - before).to.equal((lamports * CAP_BPS) / 10000);
+    });
+  });
+
+  describe("User Position View", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let trader: anchor.web3.Keypair;
+    let live: TestMarket;
+    let resolved: TestMarket;
+
+    const positionView = (user: anchor.web3.PublicKey, m: TestMarket) =>
+      program.methods
+        .getUserPosition()
+        .accounts({
+          market: m.marketPda,
+          vault: m.vaultPda,
+          userPosition: positionPdaFor(user, m.marketId),
+          user,
+        })
+        .view();
+
+    before(async () => {
+      trader = await fundedKeypair(1);
+      live = await createTestMarket(151);
+      resolved = await createTestMarket(152, 15);
+
+      await buy(trader, live, true, 0.02 * SOL);
+      await buy(trader, live, false, 0.01 * SOL);
+      await buy(trader, resolved, true, 0.02 * SOL);
+      await resolveTestMarket(resolved, true);
+    });
+
+    it("Values a live position at the current price", async () => {
+      const view = await positionView(trader.publicKey, live);
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, live.marketId)
+      );
+      const bps = await impliedYesBps(live);
+
+      expect(view.yesShares.toString()).to.equal(
+        position.yesShares.toString()
+      );
+      expect(view.noShares.toString()).to.equal(position.noShares.toString());
+      expect(view.claimed).to.be.false;
+      expect(view.currentValueIfLive.toString()).to.equal(
+        position.yesShares
+          .muln(bps)
+          .add(position.noShares.muln(10000 - bps))
+          .divn(10000)
+          .toString()
+      );
+      expect(view.claimableIfResolved.toNumber()).to.equal(0);
+    });
+
+    it("Reports the claimable payout once resolved", async () => {
+      const view = await positionView(trader.publicKey, resolved);
+      const claimable = await program.methods
+        .getClaimableAmount()
+        .accounts({
+          market: resolved.marketPda,
+          vault: resolved.vaultPda,
+          userPosition: positionPdaFor(trader.publicKey, resolved.marketId),
+        })
+        .view();
+
+      expect(view.currentValueIfLive.toNumber()).to.equal(0);
+      expect(view.claimableIfResolved.toNumber()).to.be.greaterThan(0);
+      expect(view.claimableIfResolved.toString()).to.equal(
+        claimable.toString()
+      );
+    });
+
+    it("Returns zeros for a wallet that never traded", async () => {
+      const stranger = anchor.web3.Keypair.generate();
+      const view = await positionView(stranger.publicKey, live);
+      expect(view.yesShares.toNumber()).to.equal(0);
+      expect(view.noShares.toNumber()).to.equal(0);
+      expect(view.currentValueIfLive.toNumber()).to.equal(0);
+      expect(view.claimableIfResolved.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("Claims On Cancelled Markets", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    it("Rejects claim_winnings and refunds instead", async () => {
+      const market = await createTestMarket(153);
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.02 * SOL);
+
+      await program.methods
+        .cancelMarket()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+
+      try {
+        await program.methods
+          .claimWinnings()
+          .accounts(claimAccounts(trader.publicKey, market))
+          .signers([trader])
+          .rpc();
+        expect.fail("Cancelled markets should not pay winnings");
+      } catch (error) {
+        expect(error.message).to.include("MarketCancelled");
+      }
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+      await program.methods
+        .claimRefund()
+        .accounts(claimAccounts(trader.publicKey, market))
+        .signers([trader])
+        .rpc();
+      const vaultAfter = await provider.connection.getBalance(market.vaultPda);
+      expect(vaultBefore - vaultAfter).to.equal(
+        position.lamportsContributed.toNumber()
+      );
+    });
+  });
+
+  describe("Event Versions", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    // Keep in sync with each event struct's VERSION in lib.rs
+    const EXPECTED_VERSIONS: { [name: string]: number } = {
+      MarketCreatedEvent: 1,
+      BuySharesEvent: 1,
+      SellSharesEvent: 1,
+      MarketResolvedEvent: 1,
+    };
+
+    const versionOf = async (sig: string, eventName: string) => {
+      await provider.connection.confirmTransaction(sig, "confirmed");
+      const event = findEvent(await eventsFromTx(sig), eventName);
+      expect(event, `${eventName} should be emitted`).to.not.be.undefined;
+      return event.data.version as number;
+    };
+
+    it("Stamps every event with its layout version", async () => {
+      const market = await createTestMarket(154, 15);
+      const trader = await fundedKeypair(1);
+      const positionPda = positionPdaFor(trader.publicKey, market.marketId);
+
+      const sigs: { [name: string]: string } = {
+        MarketCreatedEvent: market.createSig,
+        BuySharesEvent: await buy(trader, market, true, 0.02 * SOL),
+      };
+      const position = await program.account.userPosition.fetch(positionPda);
+      sigs.SellSharesEvent = await program.methods
+        .sellShares(true, position.yesShares.divn(2), new anchor.BN(1))
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPda,
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      await delay((acc.resolutionTime.toNumber() + 2) * 1000 - Date.now());
+      sigs.MarketResolvedEvent = await program.methods
+        .resolveMarket(true)
+        .accounts({ config: configPda, market: market.marketPda, authority })
+        .rpc();
+
+      for (const name of Object.keys(EXPECTED_VERSIONS)) {
+        expect(await versionOf(sigs[name], name)).to.equal(
+          EXPECTED_VERSIONS[name]
+        );
+      }
+    });
+  });
+
+  describe("Two-Sided Buys", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+
+    const buyBoth = (
+      trader: anchor.web3.Keypair,
+      yesLamports: number,
+      noLamports: number,
+      minYes = 0,
+      minNo = 0
+    ) =>
+      program.methods
+        .buyBoth(
+          new anchor.BN(yesLamports),
+          new anchor.BN(noLamports),
+          new anchor.BN(minYes),
+          new anchor.BN(minNo)
+        )
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: null,
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(155);
+    });
+
+    it("Settles both legs at sequential AMM prices", async () => {
+      const trader = await fundedKeypair(1);
+      await openPosition(trader, market);
+      const acc = await program.account.market.fetch(market.marketPda);
+      const config = await program.account.config.fetch(configPda);
+      const yesLamports = 0.02 * SOL;
+      const noLamports = 0.01 * SOL;
+
+      // Same rounding as the program, with the NO leg priced after the YES leg
+      const divCeil = (a: anchor.BN, b: anchor.BN) => a.add(b).subn(1).div(b);
+      const afterFee = (lamports: number) => {
+        const amount = new anchor.BN(lamports);
+        return amount.sub(amount.muln(config.feePercentage).divn(10000));
+      };
+      const precision = acc.ammPrecision;
+      const swap = (reserveIn: anchor.BN, reserveOut: anchor.BN, n: number) => {
+        const newIn = reserveIn.add(afterFee(n));
+        const newOut = divCeil(
+          divCeil(acc.kConstant, newIn.mul(precision)),
+          precision
+        );
+        return { out: reserveOut.sub(newOut), newIn, newOut };
+      };
+      const yesLeg = swap(acc.yesLiquidity, acc.noLiquidity, yesLamports);
+      const noLeg = swap(yesLeg.newOut, yesLeg.newIn, noLamports);
+
+      await buyBoth(trader, yesLamports, noLamports);
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(position.yesShares.toString()).to.equal(yesLeg.out.toString());
+      expect(position.noShares.toString()).to.equal(noLeg.out.toString());
+
+      const after = await program.account.market.fetch(market.marketPda);
+      expect(after.yesLiquidity.toString()).to.equal(noLeg.newOut.toString());
+      expect(after.noLiquidity.toString()).to.equal(noLeg.newIn.toString());
+    });
+
+    it("Reverts both legs when one leg slips", async () => {
+      const trader = await fundedKeypair(1);
+      await openPosition(trader, market);
+      const before = await program.account.market.fetch(market.marketPda);
+
+      try {
+        await buyBoth(trader, 0.01 * SOL, 0.01 * SOL, 0, 0.5 * SOL);
+        expect.fail("NO leg slippage should revert the call");
+      } catch (error) {
+        expect(error.message).to.include("SlippageExceeded");
+      }
+
+      const after = await program.account.market.fetch(market.marketPda);
+      expect(after.yesLiquidity.toString()).to.equal(
+        before.yesLiquidity.toString()
+      );
+      expect(after.tradeSequence.toString()).to.equal(
+        before.tradeSequence.toString()
+      );
+      const info = await provider.connection.getAccountInfo(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(info).to.be.null;
+    });
+  });
+
+  describe("Resolve Deadline Refunds", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const GRACE_SECS = 8;
+    let market: TestMarket;
+    let alice: anchor.web3.Keypair;
+    let stranger: anchor.web3.Keypair;
+
+    const setGracePeriod = (secs: number) =>
+      program.methods
+        .setResolveGracePeriod(new anchor.BN(secs))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    const enableRefunds = () =>
+      program.methods
+        .enableRefunds()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          caller: stranger.publicKey,
+        })
+        .signers([stranger])
+        .rpc();
+
+    const waitUntil = async (unixSecs: number) => {
+      const waitMs = unixSecs * 1000 - Date.now();
+      if (waitMs > 0) await delay(waitMs);
+    };
+
+    before(async () => {
+      await setGracePeriod(GRACE_SECS);
+      market = await createTestMarket(156, 10);
+      alice = await fundedKeypair(1);
+      stranger = await fundedKeypair(1);
+      await buy(alice, market, true, 0.02 * SOL);
+    });
+
+    after(async () => {
+      await setGracePeriod(7 * 24 * 60 * 60);
+    });
+
+    it("Blocks refunds until the grace deadline passes", async () => {
+      const acc = await program.account.market.fetch(market.marketPda);
+      await waitUntil(acc.resolutionTime.toNumber() + 2);
+
+      try {
+        await enableRefunds();
+        expect.fail("Refunds should wait for the grace deadline");
+      } catch (error) {
+        expect(error.message).to.include("ResolveDeadlineNotReached");
+      }
+
+      const after = await program.account.market.fetch(market.marketPda);
+      expect(after.cancelled).to.equal(false);
+    });
+
+    it("Lets anyone enable refunds after the deadline", async () => {
+      const acc = await program.account.market.fetch(market.marketPda);
+      await waitUntil(acc.resolutionTime.toNumber() + GRACE_SECS + 2);
+
+      await enableRefunds();
+      const after = await program.account.market.fetch(market.marketPda);
+      expect(after.cancelled).to.equal(true);
+
+      const positionPda = positionPdaFor(alice.publicKey, market.marketId);
+      const owed = (await program.account.userPosition.fetch(positionPda))
+        .lamportsContributed;
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+      await program.methods
+        .claimRefund()
+        .accounts({
+          market: market.marketPda,
+          vault: market.vaultPda,
+          userPosition: positionPda,
+          user: alice.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          insuranceVault: null,
+        })
+        .signers([alice])
+        .rpc();
+      const vaultAfter = await provider.connection.getBalance(market.vaultPda);
+      expect(vaultBefore - vaultAfter).to.equal(owed.toNumber());
+
+      try {
+        await resolveTestMarket(market, true);
+        expect.fail("Refunded market should not resolve");
+      } catch (error) {
+        expect(error.message).to.include("MarketCancelled");
+      }
+    });
+  });
+
+  describe("Category Index", () => {
+    const CATEGORY = "Paging";
+    const firstId = 157;
+    const count = CATEGORY_PAGE_SIZE + 2;
+    const ids: number[] = [];
+    for (let i = 0; i < count; i++) ids.push(firstId + i);
+
+    const getPage = (page: number) =>
+      program.methods
+        .getCategoryPage(new anchor.BN(page))
+        .accounts({
+          categoryStats: categoryStatsPdaFor(CATEGORY),
+          categoryIndex: categoryIndexPdaFor(CATEGORY, page),
+        })
+        .view();
+
+    before(async () => {
+      for (const id of ids) {
+        await createTestMarket(id, 3600, undefined, CATEGORY);
+      }
+    });
+
+    it("Records every new market on the category's index pages", async () => {
+      const first = await program.account.categoryIndex.fetch(
+        categoryIndexPdaFor(CATEGORY, 0)
+      );
+      expect(first.page.toNumber()).to.equal(0);
+      expect(first.marketIds.map((id) => id.toNumber())).to.deep.equal(
+        ids.slice(0, CATEGORY_PAGE_SIZE)
+      );
+
+      const stats = await program.account.categoryStats.fetch(
+        categoryStatsPdaFor(CATEGORY)
+      );
+      expect(stats.marketCount.toNumber()).to.equal(count);
+    });
+
+    it("Pages through the category in creation order", async () => {
+      const seen: number[] = [];
+      let page: number | null = 0;
+      while (page !== null) {
+        const result = await getPage(page);
+        expect(result.page.toNumber()).to.equal(page);
+        result.marketIds.forEach((id) => seen.push(id.toNumber()));
+        page = result.nextPage ? result.nextPage.toNumber() : null;
+      }
+      expect(seen).to.deep.equal(ids);
+
+      const last = await getPage(1);
+      expect(last.marketIds.length).to.equal(count - CATEGORY_PAGE_SIZE);
+      expect(last.nextPage).to.be.null;
+    });
+  });
+
+  describe("Account Verification", () => {
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const verifyAccounts = (vault: anchor.web3.PublicKey) =>
+      program.methods
+        .verifyAccounts(null)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(169);
+      trader = anchor.web3.Keypair.generate();
+    });
+
+    it("Accepts the account set buy_shares expects", async () => {
+      await verifyAccounts(market.vaultPda);
+    });
+
+    it("Names the vault when it is derived for another market", async () => {
+      try {
+        await verifyAccounts(vaultPdaFor(market.marketId + 1));
+        expect.fail("A vault for another market should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("MisderivedVault");
+      }
+    });
+  });
+
+  describe("Resolver Whitelist", () => {
+    let resolver: anchor.web3.Keypair;
+    let first: TestMarket;
+    let second: TestMarket;
+    let third: TestMarket;
+
+    const resolveAs = (m: TestMarket, signer: anchor.web3.Keypair) =>
+      program.methods
+        .resolveMarket(true)
+        .accounts({
+          config: configPda,
+          market: m.marketPda,
+          authority: signer.publicKey,
+        })
+        .signers([signer])
+        .rpc();
+
+    const updateWhitelist = (add: boolean) => {
+      const builder = add
+        ? program.methods.addResolver(resolver.publicKey)
+        : program.methods.removeResolver(resolver.publicKey);
+      return builder
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+    };
+    const isWhitelisted = async () =>
+      (await program.account.config.fetch(configPda)).resolvers.some((r) =>
+        r.equals(resolver.publicKey)
+      );
+
+    before(async () => {
+      resolver = await fundedKeypair(1);
+      first = await createTestMarket(170, 5);
+      second = await createTestMarket(171, 5);
+      third = await createTestMarket(172, 5);
+      await updateWhitelist(true);
+      await delay(7000);
+    });
+
+    after(async () => {
+      if (await isWhitelisted()) await updateWhitelist(false);
+    });
+
+    it("Lets a whitelisted resolver resolve", async () => {
+      expect(await isWhitelisted()).to.equal(true);
+
+      await resolveAs(first, resolver);
+      const acc = await program.account.market.fetch(first.marketPda);
+      expect(acc.resolved).to.equal(true);
+    });
+
+    it("Rejects a resolver after removal", async () => {
+      await updateWhitelist(false);
+      try {
+        await resolveAs(second, resolver);
+        expect.fail("Removed resolver should not resolve");
+      } catch (error) {
+        expect(error.message).to.include("Unauthorized");
+      }
+      const acc = await program.account.market.fetch(second.marketPda);
+      expect(acc.resolved).to.equal(false);
+    });
+
+    it("Always lets the authority resolve", async () => {
+      await updateWhitelist(true);
+      await resolveTestMarket(third, true);
+      await resolveTestMarket(second, false);
+      const acc = await program.account.market.fetch(third.marketPda);
+      expect(acc.resolved).to.equal(true);
+    });
+  });
+
+  describe("Circuit Breaker", () => {
+    const LIMIT_BPS = 500;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    // Move in bps a YES buy of `lamports` causes, with the program's math
+    const divCeil = (a: anchor.BN, b: anchor.BN) => a.add(b).subn(1).div(b);
+    const bpsOf = (yes: anchor.BN, no: anchor.BN) =>
+      yes.muln(10000).div(yes.add(no)).toNumber();
+    type Reserves = {
+      yesLiquidity: anchor.BN;
+      noLiquidity: anchor.BN;
+      kConstant: anchor.BN;
+      ammPrecision: anchor.BN;
+    };
+    const yesMoveFor = (acc: Reserves, feeBps: number, lamports: number) => {
+      const amount = new anchor.BN(lamports);
+      const afterFee = amount.sub(amount.muln(feeBps).divn(10000));
+      const newYes = acc.yesLiquidity.add(afterFee);
+      const newNo = divCeil(
+        divCeil(acc.kConstant, newYes.mul(acc.ammPrecision)),
+        acc.ammPrecision
+      );
+      return (
+        bpsOf(newYes, newNo) - bpsOf(acc.yesLiquidity, acc.noLiquidity)
+      );
+    };
+
+    before(async () => {
+      market = await createTestMarket(173);
+      trader = await fundedKeypair(1);
+      await program.methods
+        .setMaxPriceMove(LIMIT_BPS)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+    });
+
+    it("Trips just over the limit and allows just under it", async () => {
+      const acc = await program.account.market.fetch(market.marketPda);
+      const config = await program.account.config.fetch(configPda);
+      const fee = config.feePercentage;
+
+      // Largest YES buy whose move stays within the limit
+      let lo = 1;
+      let hi = acc.yesLiquidity.toNumber() * 4;
+      while (lo < hi) {
+        const mid = Math.ceil((lo + hi) / 2);
+        if (yesMoveFor(acc, fee, mid) <= LIMIT_BPS) lo = mid;
+        else hi = mid - 1;
+      }
+      expect(yesMoveFor(acc, fee, lo + 1)).to.be.greaterThan(LIMIT_BPS);
+
+      try {
+        await buy(trader, market, true, lo + 1);
+        expect.fail("A buy over the limit should trip the breaker");
+      } catch (error) {
+        expect(error.message).to.include("CircuitBreakerTripped");
+      }
+
+      const before = await impliedYesBps(market);
+      await buy(trader, market, true, lo);
+      const after = await impliedYesBps(market);
+      expect(after - before).to.equal(yesMoveFor(acc, fee, lo));
+      expect(after - before).to.be.at.most(LIMIT_BPS);
+    });
+  });
+
+  describe("Claim Totals", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let winners: anchor.web3.Keypair[];
+
+    before(async () => {
+      market = await createTestMarket(174, 5);
+      winners = [await fundedKeypair(1), await fundedKeypair(1)];
+      const loser = await fundedKeypair(1);
+      await buy(winners[0], market, true, 0.02 * SOL);
+      await buy(winners[1], market, true, 0.03 * SOL);
+      await buy(loser, market, false, 0.04 * SOL);
+      await resolveTestMarket(market, true);
+    });
+
+    it("Accumulates every payout into total_claimed", async () => {
+      let paid = 0;
+      for (const winner of winners) {
+        const vaultBefore = await provider.connection.getBalance(
+          market.vaultPda
+        );
+        const sig = await program.methods
+          .claimWinnings()
+          .accounts(claimAccounts(winner.publicKey, market))
+          .signers([winner])
+          .rpc({ commitment: "confirmed" });
+        const vaultAfter = await provider.connection.getBalance(
+          market.vaultPda
+        );
+        const payout = vaultBefore - vaultAfter;
+        paid += payout;
+
+        const event = findEvent(await eventsFromTx(sig), "ClaimWinningsEvent");
+        expect(event, "ClaimWinningsEvent should be emitted").to.not.be
+          .undefined;
+        expect(event.data.payout.toNumber()).to.equal(payout);
+        expect(event.data.totalClaimed.toNumber()).to.equal(paid);
+      }
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalClaimed.toNumber()).to.equal(paid);
+
+      const summary = await program.methods
+        .getMarketSummary()
+        .accounts({ market: market.marketPda, metadata: market.metadataPda })
+        .view();
+      expect(summary.totalClaimed.toNumber()).to.equal(paid);
+    });
+  });
+
+  describe("Resolution Reverts", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const WINDOW_SECS = 6;
+    let timely: TestMarket;
+    let late: TestMarket;
+    let claimed: TestMarket;
+    let yesTrader: anchor.web3.Keypair;
+
+    const setRevertWindow = (secs: number) =>
+      program.methods
+        .setRevertWindow(new anchor.BN(secs))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    const revert = (m: TestMarket) =>
+      program.methods
+        .revertResolution()
+        .accounts({
+          config: configPda,
+          market: m.marketPda,
+          authority: authority,
+        })
+        .rpc();
+
+    before(async () => {
+      await setRevertWindow(WINDOW_SECS);
+      yesTrader = await fundedKeypair(1);
+      const noTrader = await fundedKeypair(1);
+      timely = await createTestMarket(175, 5);
+      late = await createTestMarket(176, 5);
+      claimed = await createTestMarket(177, 5);
+      for (const m of [timely, late, claimed]) {
+        await buy(yesTrader, m, true, 0.01 * SOL);
+        await buy(noTrader, m, false, 0.01 * SOL);
+      }
+    });
+
+    after(async () => {
+      await setRevertWindow(0);
+    });
+
+    it("Reverts a resolution inside the window", async () => {
+      await resolveTestMarket(timely, true);
+      await revert(timely);
+
+      const acc = await program.account.market.fetch(timely.marketPda);
+      expect(acc.resolved).to.equal(false);
+      expect(acc.outcome).to.be.null;
+      expect(acc.resolvedAt.toNumber()).to.equal(0);
+
+      // The corrected outcome can then be recorded
+      await resolveTestMarket(timely, false);
+      const fixed = await program.account.market.fetch(timely.marketPda);
+      expect(fixed.outcome).to.equal(false);
+    });
+
+    it("Refuses to revert once the window has passed", async () => {
+      await resolveTestMarket(late, true);
+      await delay((WINDOW_SECS + 2) * 1000);
+      try {
+        await revert(late);
+        expect.fail("Revert after the window should fail");
+      } catch (error) {
+        expect(error.message).to.include("RevertWindowClosed");
+      }
+    });
+
+    it("Refuses to revert after a claim", async () => {
+      await resolveTestMarket(claimed, true);
+      await program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(yesTrader.publicKey, claimed))
+        .signers([yesTrader])
+        .rpc();
+      try {
+        await revert(claimed);
+        expect.fail("Revert after a claim should fail");
+      } catch (error) {
+        expect(error.message).to.include("SettlementStarted");
+      }
+      const acc = await program.account.market.fetch(claimed.marketPda);
+      expect(acc.outcome).to.equal(true);
+    });
+  });
+
+  describe("Fee Vault Closing", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const BUCKET = 9;
+    const bucketPda = feeBucketPdaFor(BUCKET);
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const feeVaultAccounts = (feeVault: anchor.web3.PublicKey) => ({
+      config: configPda,
+      feeVault,
+      authority: authority,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    });
+    const initBucket = () =>
+      program.methods
+        .initFeeBucket(BUCKET)
+        .accounts(feeVaultAccounts(bucketPda))
+        .rpc();
+
+    before(async () => {
+      await initBucket();
+      market = await createTestMarket(178);
+      trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.02 * SOL, 0, BUCKET);
+    });
+
+    it("Keeps the default vault while markets are open", async () => {
+      const config = await program.account.config.fetch(configPda);
+      expect(config.activeMarkets.toNumber()).to.be.greaterThan(0);
+      try {
+        await program.methods
+          .closeFeeVault(0)
+          .accounts(feeVaultAccounts(feeVaultPda))
+          .rpc();
+        expect.fail("Bucket 0 should stay open while markets route to it");
+      } catch (error) {
+        expect(error.message).to.include("FeeVaultInUse");
+      }
+    });
+
+    it("Closes an idle bucket and re-opens it", async () => {
+      const held = await provider.connection.getBalance(bucketPda);
+      expect(held).to.be.greaterThan(0);
+      const authorityBefore = await provider.connection.getBalance(authority);
+
+      await program.methods
+        .closeFeeVault(BUCKET)
+        .accounts(feeVaultAccounts(bucketPda))
+        .rpc({ commitment: "confirmed" });
+
+      expect(await provider.connection.getAccountInfo(bucketPda)).to.be.null;
+      const authorityAfter = await provider.connection.getBalance(authority);
+      // Less the transaction fee paid by the authority
+      expect(authorityAfter - authorityBefore).to.be.greaterThan(held - 10000);
+
+      const [entry] = (await program.methods.getAuditLog().view()).slice(-1);
+      expect(Object.keys(entry.action)[0]).to.equal("feeVaultClosed");
+      expect(entry.amount.toNumber()).to.equal(held);
+
+      try {
+        await buy(trader, market, true, 0.01 * SOL, 0, BUCKET);
+        expect.fail("A closed bucket should not take fees");
+      } catch (error) {
+        expect(error.message).to.include("FeeVaultNotInitialized");
+      }
+
+      await initBucket();
+      await buy(trader, market, true, 0.01 * SOL, 0, BUCKET);
+    });
+  });
+
+  describe("Solvency Check", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    it("Reports a traded market as solvent", async () => {
+      const market = await createTestMarket(179);
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.03 * SOL);
+      await buy(trader, market, false, 0.01 * SOL);
+
+      const report = await program.methods
+        .assertSolvency()
+        .accounts({ market: market.marketPda, vault: market.vaultPda })
+        .view();
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      const owed = anchor.BN.max(acc.totalYesShares, acc.totalNoShares);
+      const rent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      const vault = await provider.connection.getBalance(market.vaultPda);
+
+      expect(report.marketId.toNumber()).to.equal(market.marketId);
+      expect(report.obligations.toString()).to.equal(owed.toString());
+      expect(report.vaultBalance.toNumber()).to.equal(vault - rent);
+      expect(report.solvent).to.equal(true);
+    });
+  });
+
+  describe("Share Decimals", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const SHARE_DECIMALS = 3;
+    const SCALE = new anchor.BN(10 ** SHARE_DECIMALS);
+    let market: TestMarket;
+    let alice: anchor.web3.Keypair;
+    let bob: anchor.web3.Keypair;
+
+    const sharesOf = async (trader: anchor.web3.Keypair) =>
+      (
+        await program.account.userPosition.fetch(
+          positionPdaFor(trader.publicKey, market.marketId)
+        )
+      ).yesShares;
+
+    before(async () => {
+      market = await createTestMarket(180, 15);
+      await program.methods
+        .setShareDecimals(SHARE_DECIMALS)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+      alice = await fundedKeypair(1);
+      bob = await fundedKeypair(1);
+    });
+
+    it("Issues shares at the finer scale and totals them", async () => {
+      for (const [trader, lamports] of [
+        [alice, 0.02 * SOL],
+        [bob, 0.01 * SOL],
+      ] as [anchor.web3.Keypair, number][]) {
+        const { shares } = await quoteBuy(market, true, lamports);
+        await buy(trader, market, true, lamports);
+        expect((await sharesOf(trader)).toString()).to.equal(
+          shares.mul(SCALE).toString()
+        );
+      }
+      await buy(await fundedKeypair(1), market, false, 0.01 * SOL);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      const held = (await sharesOf(alice)).add(await sharesOf(bob));
+      expect(acc.totalYesShares.toString()).to.equal(held.toString());
+    });
+
+    it("Pays whole lamports pro rata without dust", async () => {
+      await resolveTestMarket(market, true);
+      const rent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      const distributable =
+        (await provider.connection.getBalance(market.vaultPda)) - rent;
+      const acc = await program.account.market.fetch(market.marketPda);
+      const payoutScale = new anchor.BN(1_000_000_000).mul(SCALE);
+      const rate = new anchor.BN(distributable)
+        .mul(payoutScale)
+        .div(acc.totalYesShares);
+      const aliceShares = await sharesOf(alice);
+
+      let paid = 0;
+      for (const trader of [alice, bob]) {
+        const before = await provider.connection.getBalance(market.vaultPda);
+        await program.methods
+          .claimWinnings()
+          .accounts(claimAccounts(trader.publicKey, market))
+          .signers([trader])
+          .rpc();
+        const payout =
+          before - (await provider.connection.getBalance(market.vaultPda));
+        if (trader === alice) {
+          expect(payout).to.equal(
+            aliceShares.mul(rate).div(payoutScale).toNumber()
+          );
+        }
+        paid += payout;
+      }
+      expect(paid).to.equal(distributable);
+    });
+  });
+
+  describe("Explicit Position Opening", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+
+    before(async () => {
+      market = await createTestMarket(181);
+    });
+
+    it("Opens an empty position and buys into it", async () => {
+      const trader = await fundedKeypair(1);
+      const positionPda = positionPdaFor(trader.publicKey, market.marketId);
+      await openPosition(trader, market);
+
+      const opened = await program.account.userPosition.fetch(positionPda);
+      expect(opened.user.toBase58()).to.equal(trader.publicKey.toBase58());
+      expect(opened.yesShares.toNumber()).to.equal(0);
+      expect(opened.noShares.toNumber()).to.equal(0);
+
+      const { shares } = await quoteBuy(market, true, 0.01 * SOL);
+      await buy(trader, market, true, 0.01 * SOL);
+
+      const after = await program.account.userPosition.fetch(positionPda);
+      expect(after.yesShares.toString()).to.equal(shares.toString());
+    });
+
+    it("Rejects a buy without an opened position", async () => {
+      const trader = await fundedKeypair(1);
+      try {
+        await program.methods
+          .buyShares(
+            true,
+            new anchor.BN(0.01 * SOL),
+            new anchor.BN(0),
+            null,
+            null
+          )
+          .accounts({
+            config: configPda,
+            market: market.marketPda,
+            vault: market.vaultPda,
+            feeVault: feeVaultPda,
+            categoryStats: categoryStatsPdaFor(market.category),
+            userPosition: positionPdaFor(trader.publicKey, market.marketId),
+            user: trader.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            cosigner: null,
+            whitelistEntry: null,
+            insuranceVault: null,
+          })
+          .signers([trader])
+          .rpc();
+        expect.fail("Buying should require an opened position");
+      } catch (error) {
+        expect(error.message).to.include("AccountNotInitialized");
+      }
+    });
+  });
+
+  describe("Market Volume Caps", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const CAP = 0.05 * SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const expectCapReached = async (lamports: number) => {
+      try {
+        await buy(trader, market, true, lamports);
+        expect.fail("Buy past the cap should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("MarketVolumeCapReached");
+      }
+    };
+
+    before(async () => {
+      market = await createTestMarket(182);
+      trader = await fundedKeypair(1);
+      await program.methods
+        .setMaxTotalVolume(new anchor.BN(CAP))
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+    });
+
+    it("Accepts buys just under the cap", async () => {
+      await buy(trader, market, true, CAP - 0.01 * SOL - 1);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalVolume.toNumber()).to.equal(CAP - 0.01 * SOL - 1);
+    });
+
+    it("Rejects a buy that would go over the cap", async () => {
+      await expectCapReached(0.01 * SOL + 2);
+    });
+
+    it("Accepts a buy that lands exactly on the cap", async () => {
+      await buy(trader, market, true, 0.01 * SOL + 1);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalVolume.toNumber()).to.equal(CAP);
+
+      await expectCapReached(1);
+    });
+  });
+
+  describe("Market Creation Cooldown", () => {
+    const COOLDOWN_SECS = 4;
+
+    const setCreateCooldown = (secs: number) =>
+      program.methods
+        .setCreateCooldown(new anchor.BN(secs))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    before(async () => {
+      await createTestMarket(183);
+      await setCreateCooldown(COOLDOWN_SECS);
+    });
+
+    after(async () => {
+      await setCreateCooldown(0);
+    });
+
+    it("Rejects a second market inside the cooldown", async () => {
+      try {
+        await createTestMarket(184);
+        expect.fail("Creation inside the cooldown should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("CreateCooldownActive");
+      }
+    });
+
+    it("Accepts a second market once the cooldown passes", async () => {
+      const statsPda = creatorStatsPdaFor(authority);
+      const stats = await program.account.creatorStats.fetch(statsPda);
+      const readyAt = stats.lastCreatedAt.toNumber() + COOLDOWN_SECS;
+      await delay((readyAt + 1) * 1000 - Date.now());
+
+      await createTestMarket(184);
+
+      const after = await program.account.creatorStats.fetch(statsPda);
+      expect(after.marketsCreated.toNumber()).to.equal(
+        stats.marketsCreated.toNumber() + 1
+      );
+      expect(after.lastCreatedAt.toNumber()).to.be.at.least(readyAt);
+    });
+  });
+
+  describe("Fee Breakdown", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const AMOUNT = 0.0337 * SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const breakdown = (lamports: number) =>
+      program.methods
+        .getFeeBreakdown(new anchor.BN(lamports))
+        .accounts({ config: configPda, market: market.marketPda })
+        .view();
+
+    // Buys and returns what actually reached the fee vault and the pool vault
+    const deducted = async (lamports: number) => {
+      const feeBefore = await provider.connection.getBalance(feeVaultPda);
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+      await buy(trader, market, true, lamports);
+      const feeAfter = await provider.connection.getBalance(feeVaultPda);
+      const vaultAfter = await provider.connection.getBalance(market.vaultPda);
+      return { fee: feeAfter - feeBefore, net: vaultAfter - vaultBefore };
+    };
+
+    before(async () => {
+      market = await createTestMarket(185);
+      trader = await fundedKeypair(1);
+    });
+
+    it("Matches what buy_shares deducts at the global fee", async () => {
+      const quoted = await breakdown(AMOUNT);
+      expect(quoted.fee.add(quoted.amountAfterFee).toNumber()).to.equal(
+        AMOUNT
+      );
+
+      const actual = await deducted(AMOUNT);
+      expect(actual.fee).to.equal(quoted.fee.toNumber());
+      expect(actual.net).to.equal(quoted.amountAfterFee.toNumber());
+    });
+
+    it("Follows a per-market fee override", async () => {
+      const config = await program.account.config.fetch(configPda);
+      const overrideBps = Math.min(123, config.maxFeeBps);
+      await program.methods
+        .setMarketFee(overrideBps)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+
+      const quoted = await breakdown(AMOUNT);
+      expect(quoted.fee.toNumber()).to.equal(
+        Math.floor((AMOUNT * overrideBps) / 10000)
+      );
+
+      const actual = await deducted(AMOUNT);
+      expect(actual.fee).to.equal(quoted.fee.toNumber());
+      expect(actual.net).to.equal(quoted.amountAfterFee.toNumber());
+    });
+  });
+
+  describe("Co-Signed Large Buys", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const THRESHOLD = 0.02 * SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+    let cosigner: anchor.web3.Keypair;
+
+    const buyCosigned = (lamports: number) =>
+      program.methods
+        .buyShares(true, new anchor.BN(lamports), new anchor.BN(0), null, null)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: cosigner.publicKey,
+          whitelistEntry: null,
+          insuranceVault: null,
+        })
+        .signers([trader, cosigner])
+        .rpc();
+
+    const expectCosignerRequired = async (attempt: Promise<string>) => {
+      try {
+        await attempt;
+        expect.fail("Missing cosigner should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("CosignerRequired");
+      }
+    };
+
+    before(async () => {
+      market = await createTestMarket(186);
+      trader = await fundedKeypair(1);
+      cosigner = anchor.web3.Keypair.generate();
+      await openPosition(trader, market);
+      await program.methods
+        .setPositionCosigner(cosigner.publicKey, new anchor.BN(THRESHOLD))
+        .accounts({
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          currentCosigner: null,
+        })
+        .signers([trader])
+        .rpc();
+    });
+
+    it("Lets the primary signer alone buy up to the threshold", async () => {
+      await buy(trader, market, true, THRESHOLD);
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(position.yesShares.toNumber()).to.be.greaterThan(0);
+    });
+
+    it("Rejects a buy above the threshold without the cosigner", async () => {
+      await expectCosignerRequired(buy(trader, market, true, THRESHOLD + 1));
+    });
+
+    it("Accepts a buy above the threshold with the cosigner", async () => {
+      const positionPda = positionPdaFor(trader.publicKey, market.marketId);
+      const before = await program.account.userPosition.fetch(positionPda);
+      await buyCosigned(THRESHOLD + 1);
+      const after = await program.account.userPosition.fetch(positionPda);
+      expect(after.yesShares.gt(before.yesShares)).to.equal(true);
+    });
+
+    it("Keeps the cosigner unless they sign the change", async () => {
+      await expectCosignerRequired(
+        program.methods
+          .setPositionCosigner(null, new anchor.BN(0))
+          .accounts({
+            userPosition: positionPdaFor(trader.publicKey, market.marketId),
+            user: trader.publicKey,
+            currentCosigner: null,
+          })
+          .signers([trader])
+          .rpc()
+      );
+    });
+  });
+
+  describe("Closing Trading", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const closeTrading = () =>
+      program.methods
+        .closeTrading()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          caller: trader.publicKey,
+        })
+        .signers([trader])
+        .rpc({ commitment: "confirmed" });
+
+    before(async () => {
+      market = await createTestMarket(187, 5);
+      trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.01 * SOL);
+    });
+
+    it("Cannot close trading before the cutoff", async () => {
+      try {
+        await closeTrading();
+        expect.fail("Closing early should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("TradingStillOpen");
+      }
+    });
+
+    it("Blocks buys once trading is closed", async () => {
+      const acc = await program.account.market.fetch(market.marketPda);
+      const config = await program.account.config.fetch(configPda);
+      const cutoff = acc.resolutionTime.sub(config.tradingBuffer).toNumber();
+      await delay((cutoff + 2) * 1000 - Date.now());
+
+      const sig = await closeTrading();
+      const closed = await program.account.market.fetch(market.marketPda);
+      expect(closed.tradingClosed).to.equal(true);
+      const event = findEvent(await eventsFromTx(sig), "TradingClosedEvent");
+      expect(event.data.marketId.toNumber()).to.equal(market.marketId);
+
+      try {
+        await buy(trader, market, true, 0.01 * SOL);
+        expect.fail("Buy after close_trading should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("TradingClosed");
+      }
+    });
+  });
+
+  describe("Payout Modes", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const LAMPORTS_PER_SHARE = 1;
+    let parimutuel: TestMarket;
+    let fixed: TestMarket;
+    let winner: anchor.web3.Keypair;
+
+    const claim = async (m: TestMarket) => {
+      const before = await provider.connection.getBalance(m.vaultPda);
+      await program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(winner.publicKey, m))
+        .signers([winner])
+        .rpc();
+      return before - (await provider.connection.getBalance(m.vaultPda));
+    };
+
+    before(async () => {
+      parimutuel = await createTestMarket(188, 5);
+      fixed = await createTestMarket(189, 5);
+      await program.methods
+        .setPayoutMode({
+          fixedRedemption: {
+            lamportsPerShare: new anchor.BN(LAMPORTS_PER_SHARE),
+          },
+        })
+        .accounts({
+          config: configPda,
+          market: fixed.marketPda,
+          authority: authority,
+        })
+        .rpc();
+
+      winner = await fundedKeypair(1);
+      const loser = await fundedKeypair(1);
+      for (const m of [parimutuel, fixed]) {
+        await buy(winner, m, true, 0.02 * SOL);
+        await buy(loser, m, false, 0.01 * SOL);
+      }
+      await resolveTestMarket(parimutuel, true);
+      await resolveTestMarket(fixed, true);
+    });
+
+    it("Pays the same shares differently under each mode", async () => {
+      const shares = async (m: TestMarket) =>
+        (
+          await program.account.userPosition.fetch(
+            positionPdaFor(winner.publicKey, m.marketId)
+          )
+        ).yesShares.toNumber();
+      const held = await shares(parimutuel);
+      expect(await shares(fixed)).to.equal(held);
+
+      // The only winner takes the whole parimutuel vault above its rent floor
+      const rent =
+        await provider.connection.getMinimumBalanceForRentExemption(0);
+      const pool =
+        (await provider.connection.getBalance(parimutuel.vaultPda)) - rent;
+      expect(await claim(parimutuel)).to.equal(pool);
+
+      // A fixed redemption pays its rate and leaves the rest in the vault
+      const fixedPayout = await claim(fixed);
+      expect(fixedPayout).to.equal(held * LAMPORTS_PER_SHARE);
+      expect(fixedPayout).to.be.lessThan(pool);
+    });
+  });
+
+  describe("Authority Liquidity Top-Ups", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let deepened: TestMarket;
+    let control: TestMarket;
+
+    before(async () => {
+      deepened = await createTestMarket(190);
+      control = await createTestMarket(191);
+      // Move both markets off 50/50 the same way
+      const trader = await fundedKeypair(1);
+      await buy(trader, deepened, true, 0.02 * SOL);
+      await buy(trader, control, true, 0.02 * SOL);
+    });
+
+    it("Scales the reserves without moving the price", async () => {
+      const before = await program.account.market.fetch(deepened.marketPda);
+      const bpsBefore = await impliedYesBps(deepened);
+      const vaultBefore = await provider.connection.getBalance(
+        deepened.vaultPda
+      );
+      // Doubling the pool keeps the reserve ratio exact
+      const topUp = before.yesLiquidity.add(before.noLiquidity);
+
+      await program.methods
+        .authorityAddLiquidity(topUp, false)
+        .accounts({
+          config: configPda,
+          market: deepened.marketPda,
+          vault: deepened.vaultPda,
+          feeVault: feeVaultPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      const after = await program.account.market.fetch(deepened.marketPda);
+      expect(after.yesLiquidity.toString()).to.equal(
+        before.yesLiquidity.muln(2).toString()
+      );
+      expect(after.noLiquidity.toString()).to.equal(
+        before.noLiquidity.muln(2).toString()
+      );
+      expect(after.kConstant.toString()).to.equal(
+        after.yesLiquidity
+          .mul(after.noLiquidity)
+          .mul(after.ammPrecision)
+          .mul(after.ammPrecision)
+          .toString()
+      );
+      expect(await impliedYesBps(deepened)).to.equal(bpsBefore);
+      expect(
+        (await provider.connection.getBalance(deepened.vaultPda)) -
+          vaultBefore
+      ).to.equal(topUp.toNumber());
+    });
+
+    it("Gives a large trade less slippage than before", async () => {
+      const size = 0.1 * SOL;
+      expect(await impliedYesBps(deepened)).to.equal(
+        await impliedYesBps(control)
+      );
+      const deep = await quoteBuy(deepened, true, size);
+      const shallow = await quoteBuy(control, true, size);
+      expect(deep.shares.gt(shallow.shares)).to.equal(true);
+
+      const trader = await fundedKeypair(1);
+      await buy(trader, deepened, true, size);
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, deepened.marketId)
+      );
+      expect(position.yesShares.toString()).to.equal(deep.shares.toString());
+    });
+
+    it("Nudges a lopsided pool toward even odds when rebalancing", async () => {
+      const skewed = await createTestMarket(227);
+      const trader = await fundedKeypair(1);
+      await buy(trader, skewed, true, 0.05 * SOL);
+      const bpsBefore = await impliedYesBps(skewed);
+      expect(bpsBefore).to.be.greaterThan(5000);
+
+      const lamports = 0.02 * SOL;
+      const fee = (lamports * 50) / 10_000;
+      const vaultBefore = await provider.connection.getBalance(
+        skewed.vaultPda
+      );
+      const feeVaultBefore = await provider.connection.getBalance(feeVaultPda);
+
+      await program.methods
+        .authorityAddLiquidity(new anchor.BN(lamports), true)
+        .accounts({
+          config: configPda,
+          market: skewed.marketPda,
+          vault: skewed.vaultPda,
+          feeVault: feeVaultPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      const bpsAfter = await impliedYesBps(skewed);
+      expect(bpsAfter).to.be.lessThan(bpsBefore);
+      expect(bpsAfter).to.be.at.least(5000);
+      expect(
+        (await provider.connection.getBalance(skewed.vaultPda)) - vaultBefore
+      ).to.equal(lamports - fee);
+      expect(
+        (await provider.connection.getBalance(feeVaultPda)) - feeVaultBefore
+      ).to.equal(fee);
+    });
+  });
+
+  describe("Resolution Countdown", () => {
+    it("Counts down to resolution_time on the cluster clock", async () => {
+      const market = await createTestMarket(192, 3600);
+      const acc = await program.account.market.fetch(market.marketPda);
+
+      const countdown = await program.methods
+        .getResolutionCountdown()
+        .accounts({ market: market.marketPda })
+        .view();
+      const slot = await provider.connection.getSlot();
+      const clock = await provider.connection.getBlockTime(slot);
+
+      const expected = acc.resolutionTime.toNumber() - clock;
+      expect(Math.abs(countdown.toNumber() - expected)).to.be.at.most(2);
+      expect(countdown.toNumber()).to.be.greaterThan(3500);
+    });
+  });
+
+  describe("Zero-Share Buys", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    it("Reverts a buy that rounds down to zero shares", async () => {
+      const market = await createTestMarket(193);
+      const trader = await fundedKeypair(1);
+      // Skew the pool so YES is expensive, then try a dust-sized YES buy
+      await buy(trader, market, true, 0.2 * SOL);
+      const lamports = 2;
+      const { shares } = await quoteBuy(market, true, lamports);
+      expect(shares.toNumber()).to.equal(0);
+
+      const vaultBefore = await provider.connection.getBalance(market.vaultPda);
+      try {
+        await buy(trader, market, true, lamports);
+        expect.fail("A zero-share buy should revert");
+      } catch (error) {
+        expect(error.message).to.include("ZeroSharesOut");
+      }
+      expect(await provider.connection.getBalance(market.vaultPda)).to.equal(
+        vaultBefore
+      );
+    });
+  });
+
+  describe("Whitelist-Gated Markets", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const gate = anchor.web3.Keypair.generate().publicKey;
+    let market: TestMarket;
+    let listed: anchor.web3.Keypair;
+    let outsider: anchor.web3.Keypair;
+
+    const entryPdaFor = (user: anchor.web3.PublicKey) =>
+      findPda([Buffer.from("whitelist"), gate.toBuffer(), user.toBuffer()]);
+
+    const buyGated = async (trader: anchor.web3.Keypair) => {
+      await openPosition(trader, market);
+      return program.methods
+        .buyShares(
+          true,
+          new anchor.BN(0.01 * SOL),
+          new anchor.BN(0),
+          null,
+          null
+        )
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: entryPdaFor(trader.publicKey),
+          insuranceVault: null,
+        })
+        .signers([trader])
+        .rpc();
+    };
+
+    before(async () => {
+      market = await createTestMarket(194);
+      listed = await fundedKeypair(1);
+      outsider = await fundedKeypair(1);
+      await program.methods
+        .setAccessGate(gate)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+      await program.methods
+        .addToWhitelist(gate, listed.publicKey)
+        .accounts({
+          config: configPda,
+          whitelistEntry: entryPdaFor(listed.publicKey),
+          insuranceVault: null,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+    });
+
+    it("Lets a whitelisted user buy", async () => {
+      await buyGated(listed);
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(listed.publicKey, market.marketId)
+      );
+      expect(position.yesShares.toNumber()).to.be.greaterThan(0);
+    });
+
+    it("Rejects a user who is not on the whitelist", async () => {
+      try {
+        await buy(outsider, market, true, 0.01 * SOL);
+        expect.fail("Unlisted buyer should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("NotWhitelisted");
+      }
+    });
+
+    it("Rejects another user's entry", async () => {
+      try {
+        await openPosition(outsider, market);
+        await program.methods
+          .buyShares(
+            true,
+            new anchor.BN(0.01 * SOL),
+            new anchor.BN(0),
+            null,
+            null
+          )
+          .accounts({
+            config: configPda,
+            market: market.marketPda,
+            vault: market.vaultPda,
+            feeVault: feeVaultPda,
+            categoryStats: categoryStatsPdaFor(market.category),
+            userPosition: positionPdaFor(outsider.publicKey, market.marketId),
+            user: outsider.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            cosigner: null,
+            whitelistEntry: entryPdaFor(listed.publicKey),
+            insuranceVault: null,
+          })
+          .signers([outsider])
+          .rpc();
+        expect.fail("A borrowed entry should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("NotWhitelisted");
+      }
+    });
+  });
+
+  describe("Mock Clock", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const mockClockPda = findPda([Buffer.from("mock_clock")]);
+    const hasMockClock = program.idl.instructions.some(
+      (ix) => ix.name === "setMockTime" || ix.name === "set_mock_time"
+    );
+    let trader: anchor.web3.Keypair;
+    let market: TestMarket;
+
+    before(async () => {
+      trader = await fundedKeypair();
+      market = await createTestMarket(195);
+    });
+
+    it("Leaves set_mock_time out of the default build", function () {
+      if (process.env.TEST_CLOCK) this.skip();
+      expect(hasMockClock).to.equal(false);
+    });
+
+    it("Expires a market once the mock clock passes it", async function () {
+      if (!hasMockClock) this.skip();
+      const acc = await program.account.market.fetch(market.marketPda);
+      const methods = program.methods as any;
+      await methods
+        .setMockTime(acc.resolutionTime.addn(1))
+        .accounts({
+          config: configPda,
+          mockClock: mockClockPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      try {
+        await openPosition(trader, market);
+        await program.methods
+          .buyShares(
+            true,
+            new anchor.BN(0.01 * SOL),
+            new anchor.BN(0),
+            null,
+            null
+          )
+          .accounts({
+            config: configPda,
+            market: market.marketPda,
+            vault: market.vaultPda,
+            feeVault: feeVaultPda,
+            categoryStats: categoryStatsPdaFor(market.category),
+            userPosition: positionPdaFor(trader.publicKey, market.marketId),
+            user: trader.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            cosigner: null,
+            whitelistEntry: null,
+            insuranceVault: null,
+          })
+          .remainingAccounts([
+            { pubkey: mockClockPda, isSigner: false, isWritable: false },
+          ])
+          .signers([trader])
+          .rpc();
+        expect.fail("Buy past the mock resolution time should fail");
+      } catch (error) {
+        expect(error.message).to.include("MarketExpired");
+      } finally {
+        await methods
+          .setMockTime(new anchor.BN(0))
+          .accounts({
+            config: configPda,
+            mockClock: mockClockPda,
+            authority: authority,
+            systemProgram: anchor.web3.SystemProgram.programId,
+          })
+          .rpc();
+      }
+
+      // Without the mock clock the real time still applies
+      await buy(trader, market, true, 0.01 * SOL);
+    });
+  });
+
+  describe("Market Slippage Limits", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const LIMIT_BPS = 300;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    before(async () => {
+      market = await createTestMarket(196);
+      trader = await fundedKeypair(1);
+      await program.methods
+        .setMaxSlippage(LIMIT_BPS)
+        .accounts({ market: market.marketPda, creator: authority })
+        .rpc();
+    });
+
+    it("Only lets the creator set the limit", async () => {
+      try {
+        await program.methods
+          .setMaxSlippage(0)
+          .accounts({ market: market.marketPda, creator: trader.publicKey })
+          .signers([trader])
+          .rpc();
+        expect.fail("Only the creator may change the limit");
+      } catch (error) {
+        expect(error.message).to.include("Unauthorized");
+      }
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.maxSlippageBps).to.equal(LIMIT_BPS);
+    });
+
+    it("Allows a buy that stays within the limit", async () => {
+      const before = await impliedYesBps(market);
+      await buy(trader, market, true, 0.002 * SOL);
+      const after = await impliedYesBps(market);
+      expect(Math.abs(after - before)).to.be.at.most(LIMIT_BPS);
+    });
+
+    it("Rejects a bigger move even with no min_shares_out", async () => {
+      const before = await impliedYesBps(market);
+      try {
+        await buy(trader, market, true, 0.05 * SOL, 0);
+        expect.fail("A buy past the market's limit should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("MarketSlippageExceeded");
+      }
+      expect(await impliedYesBps(market)).to.equal(before);
+    });
+  });
+
+  describe("Pushed Claims", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let winner: anchor.web3.Keypair;
+    let loser: anchor.web3.Keypair;
+    let cranker: anchor.web3.Keypair;
+
+    const pushClaim = (owner: anchor.web3.PublicKey) =>
+      program.methods
+        .pushClaim()
+        .accounts({
+          ...claimAccounts(owner, market),
+          caller: cranker.publicKey,
+        })
+        .signers([cranker])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(197, 5);
+      winner = await fundedKeypair(1);
+      loser = await fundedKeypair(1);
+      cranker = await fundedKeypair(1);
+      await buy(winner, market, true, 0.02 * SOL);
+      await buy(loser, market, false, 0.02 * SOL);
+      await resolveTestMarket(market, true);
+    });
+
+    it("Pays a pushed claim to the position owner", async () => {
+      const winnerBefore = await provider.connection.getBalance(
+        winner.publicKey
+      );
+      const vaultBefore = await provider.connection.getBalance(
+        market.vaultPda
+      );
+
+      await pushClaim(winner.publicKey);
+
+      const winnerAfter = await provider.connection.getBalance(
+        winner.publicKey
+      );
+      const vaultAfter = await provider.connection.getBalance(
+        market.vaultPda
+      );
+      const paid = vaultBefore - vaultAfter;
+      expect(paid).to.be.greaterThan(0);
+      expect(winnerAfter - winnerBefore).to.equal(paid);
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(winner.publicKey, market.marketId)
+      );
+      expect(position.claimed).to.equal(true);
+      expect(position.yesShares.toNumber()).to.equal(0);
+    });
+
+    it("Does nothing for a losing position", async () => {
+      const pda = positionPdaFor(loser.publicKey, market.marketId);
+      const before = await program.account.userPosition.fetch(pda);
+      const vaultBefore = await provider.connection.getBalance(
+        market.vaultPda
+      );
+
+      await pushClaim(loser.publicKey);
+
+      const after = await program.account.userPosition.fetch(pda);
+      expect(after.claimed).to.equal(false);
+      expect(after.noShares.eq(before.noShares)).to.equal(true);
+      expect(await provider.connection.getBalance(market.vaultPda)).to.equal(
+        vaultBefore
+      );
+    });
+
+    it("Rejects a position paired with someone else's wallet", async () => {
+      try {
+        await program.methods
+          .pushClaim()
+          .accounts({
+            ...claimAccounts(winner.publicKey, market),
+            user: cranker.publicKey,
+            caller: cranker.publicKey,
+          })
+          .signers([cranker])
+          .rpc();
+        expect.fail("The payout must go to the position owner");
+      } catch (error) {
+        expect(error.message).to.match(/ConstraintSeeds|ConstraintHasOne/);
+      }
+    });
+  });
+
+  describe("Error Diagnostics", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+
+    const logsOf = (error: any): string =>
+      (error.logs || error.errorLogs || []).join("\n");
+
+    before(async () => {
+      market = await createTestMarket(198);
+      trader = await fundedKeypair(1);
+    });
+
+    it("Logs the shortfall when a buy misses min_shares_out", async () => {
+      const lamports = 0.01 * SOL;
+      const { shares } = await quoteBuy(market, true, lamports);
+      const minShares = shares.addn(500);
+      try {
+        await buy(trader, market, true, lamports, minShares.toNumber());
+        expect.fail("The buy should miss its minimum");
+      } catch (error) {
+        expect(error.message).to.include("SlippageExceeded");
+        expect(logsOf(error)).to.include(
+          `buy returns ${shares.toString()} shares, ` +
+            `minimum was ${minShares.toString()} (short by 500)`
+        );
+      }
+    });
+
+    it("Logs the shortfall when a sell misses its minimum", async () => {
+      await buy(trader, market, true, 0.01 * SOL);
+      const positionPda = positionPdaFor(trader.publicKey, market.marketId);
+      const position = await program.account.userPosition.fetch(positionPda);
+      const minOut = new anchor.BN(SOL);
+      try {
+        await program.methods
+          .sellShares(true, position.yesShares, minOut)
+          .accounts({
+            config: configPda,
+            market: market.marketPda,
+            vault: market.vaultPda,
+            feeVault: feeVaultPda,
+            categoryStats: categoryStatsPdaFor(market.category),
+            userPosition: positionPda,
+            user: trader.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            insuranceVault: null,
+          })
+          .signers([trader])
+          .rpc();
+        expect.fail("The sell should miss its minimum");
+      } catch (error) {
+        expect(error.message).to.include("SlippageExceeded");
+        expect(logsOf(error)).to.match(
+          /sell returns \d+ lamports, minimum was 1000000000 \(short by \d+\)/
+        );
+      }
+    });
+  });
+
+  describe("Insurance Vault", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const INSURANCE_BPS = 5000;
+    let market: TestMarket;
+    let trader: anchor.web3.Keypair;
+    let insurancePda: anchor.web3.PublicKey;
+
+    const setInsuranceFee = (bps: number) =>
+      program.methods
+        .setInsuranceFee(bps)
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    const buyInsured = async (lamports: number) => {
+      await openPosition(trader, market);
+      return program.methods
+        .buyShares(true, new anchor.BN(lamports), new anchor.BN(0), null, null)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(trader.publicKey, market.marketId),
+          user: trader.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: null,
+          insuranceVault: insurancePda,
+        })
+        .signers([trader])
+        .rpc();
+    };
+
+    before(async () => {
+      market = await createTestMarket(199);
+      trader = await fundedKeypair(1);
+      insurancePda = insuranceVaultPdaFor(market.marketId);
+      await setInsuranceFee(INSURANCE_BPS);
+    });
+
+    after(async () => {
+      await setInsuranceFee(0);
+    });
+
+    it("Creates an insurance vault with every market", async () => {
+      const vault = await program.account.insuranceVault.fetch(insurancePda);
+      expect(vault.marketId.toNumber()).to.equal(market.marketId);
+    });
+
+    it("Accrues its slice of each trade fee", async () => {
+      const lamports = 0.05 * SOL;
+      const { fee } = await quoteBuy(market, true, lamports);
+      const insured = fee.muln(INSURANCE_BPS).divn(10000).toNumber();
+      const insuranceBefore = await provider.connection.getBalance(
+        insurancePda
+      );
+      const feeVaultBefore = await provider.connection.getBalance(feeVaultPda);
+
+      await buyInsured(lamports);
+
+      expect(
+        (await provider.connection.getBalance(insurancePda)) - insuranceBefore
+      ).to.equal(insured);
+      expect(
+        (await provider.connection.getBalance(feeVaultPda)) - feeVaultBefore
+      ).to.equal(fee.toNumber() - insured);
+    });
+
+    it("Rejects a fee-paying trade that leaves the vault out", async () => {
+      try {
+        await buy(trader, market, true, 0.01 * SOL);
+        expect.fail("The insurance slice must not be skipped");
+      } catch (error) {
+        expect(error.message).to.include("InsuranceVaultRequired");
+      }
+    });
+
+    it("Accepts trades without it once the slice is zero", async () => {
+      await setInsuranceFee(0);
+      await buy(trader, market, true, 0.01 * SOL);
+      await setInsuranceFee(INSURANCE_BPS);
+    });
+  });
+
+  describe("Recategorizing Markets", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const OLD = "Mislabeled";
+    const NEW = "Recategorized";
+    let market: TestMarket;
+
+    const updateCategory = async (category: string) =>
+      program.methods
+        .updateCategory(category)
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          metadata: market.metadataPda,
+          oldCategoryIndex: categoryIndexPdaFor(market.category, 0),
+          newCategoryStats: categoryStatsPdaFor(category),
+          newCategoryIndex: await nextCategoryIndexPda(category),
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(200, 3600, 0.1 * SOL, OLD);
+    });
+
+    it("Rejects a category over the length limit", async () => {
+      try {
+        await updateCategory("c".repeat(51));
+        expect.fail("Overlong category should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("CategoryTooLong");
+      }
+    });
+
+    it("Moves the market and its index entry", async () => {
+      const newPage = await nextCategoryIndexPda(NEW);
+      await updateCategory(NEW);
+      market.category = NEW;
+
+      const metadata = await program.account.marketMetadata.fetch(
+        market.metadataPda
+      );
+      expect(metadata.category).to.equal(NEW);
+
+      const oldIndex = await program.account.categoryIndex.fetch(
+        categoryIndexPdaFor(OLD, 0)
+      );
+      expect(oldIndex.marketIds.map((id) => id.toNumber())).to.not.include(
+        market.marketId
+      );
+      const newIndex = await program.account.categoryIndex.fetch(newPage);
+      expect(newIndex.marketIds.map((id) => id.toNumber())).to.include(
+        market.marketId
+      );
+
+      // Trades now count toward the new category
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.01 * SOL);
+      const stats = await program.account.categoryStats.fetch(
+        categoryStatsPdaFor(NEW)
+      );
+      expect(stats.totalVolume.toNumber()).to.equal(0.01 * SOL);
+    });
+  });
+
+  describe("Resolution Source", () => {
+    const GRACE_DEFAULT = 7 * 24 * 60 * 60;
+
+    const setGracePeriod = (secs: number) =>
+      program.methods
+        .setResolveGracePeriod(new anchor.BN(secs))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    const sourceOf = async (m: TestMarket) =>
+      Object.keys(
+        (await program.account.market.fetch(m.marketPda)).resolutionSource
+      )[0];
+
+    const waitPast = async (m: TestMarket, extraSecs: number) => {
+      const acc = await program.account.market.fetch(m.marketPda);
+      const target = acc.resolutionTime.toNumber() + extraSecs;
+      const waitMs = target * 1000 - Date.now();
+      if (waitMs > 0) await delay(waitMs);
+    };
+
+    after(async () => {
+      await setGracePeriod(GRACE_DEFAULT);
+    });
+
+    it("Stamps Manual on an authority resolution", async () => {
+      const market = await createTestMarket(201, 5);
+      await resolveTestMarket(market, true);
+      expect(await sourceOf(market)).to.equal("manual");
+    });
+
+    it("Includes the source in MarketResolvedEvent", async () => {
+      const market = await createTestMarket(202, 5);
+      await waitPast(market, 2);
+      const sig = await program.methods
+        .resolveWithEvidence(false, new Array(32).fill(7), "ipfs://src")
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc({ commitment: "confirmed" });
+
+      const event = findEvent(await eventsFromTx(sig), "MarketResolvedEvent");
+      expect(event, "MarketResolvedEvent should be emitted").to.not.be
+        .undefined;
+      expect(event.data.version).to.equal(2);
+      expect(event.data.resolutionSource).to.deep.equal({ manual: {} });
+    });
+
+    it("Stamps Manual when the authority cancels", async () => {
+      const market = await createTestMarket(203);
+      await program.methods
+        .cancelMarket()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          authority: authority,
+        })
+        .rpc();
+      expect(await sourceOf(market)).to.equal("manual");
+    });
+
+    it("Stamps AutoRefund when the grace period lapses", async () => {
+      await setGracePeriod(2);
+      const market = await createTestMarket(204, 5);
+      const caller = await fundedKeypair(1);
+      await waitPast(market, 4);
+
+      await program.methods
+        .enableRefunds()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          caller: caller.publicKey,
+        })
+        .signers([caller])
+        .rpc();
+      expect(await sourceOf(market)).to.equal("autoRefund");
+    });
+  });
+
+  describe("Price Impact", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+
+    const impactOf = (isYes: boolean, lamports: number) =>
+      program.methods
+        .getPriceImpact(isYes, new anchor.BN(lamports))
+        .accounts({ config: configPda, market: market.marketPda })
+        .view();
+
+    before(async () => {
+      market = await createTestMarket(205);
+    });
+
+    it("Reports a larger impact for a larger trade", async () => {
+      const small = await impactOf(true, 0.001 * SOL);
+      const large = await impactOf(true, 0.05 * SOL);
+      expect(large).to.be.greaterThan(small);
+    });
+
+    it("Matches the price move of the executed buy", async () => {
+      const predicted = await impactOf(false, 0.02 * SOL);
+      const before = await impliedYesBps(market);
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, false, 0.02 * SOL);
+      const after = await impliedYesBps(market);
+      expect(Math.abs(after - before)).to.equal(predicted);
+    });
+  });
+
+  describe("Buying For a Beneficiary", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+    let payer: anchor.web3.Keypair;
+    let beneficiary: anchor.web3.Keypair;
+
+    const buyFor = (lamports: number) =>
+      program.methods
+        .buySharesFor(
+          beneficiary.publicKey,
+          true,
+          new anchor.BN(lamports),
+          new anchor.BN(0)
+        )
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          feeVault: feeVaultPda,
+          categoryStats: categoryStatsPdaFor(market.category),
+          userPosition: positionPdaFor(beneficiary.publicKey, market.marketId),
+          payer: payer.publicKey,
+          systemProgram: anchor.web3.SystemProgram.programId,
+          cosigner: null,
+          whitelistEntry: null,
+          insuranceVault: null,
+        })
+        .signers([payer])
+        .rpc();
+
+    before(async () => {
+      market = await createTestMarket(206);
+      payer = await fundedKeypair(1);
+      beneficiary = anchor.web3.Keypair.generate();
+    });
+
+    it("Credits the beneficiary and debits the payer", async () => {
+      const lamports = 0.02 * SOL;
+      const quote = await quoteBuy(market, true, lamports);
+      const payerBefore = await provider.connection.getBalance(
+        payer.publicKey
+      );
+
+      await buyFor(lamports);
+
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(beneficiary.publicKey, market.marketId)
+      );
+      expect(position.user.toBase58()).to.equal(
+        beneficiary.publicKey.toBase58()
+      );
+      expect(position.yesShares.toNumber()).to.equal(
+        quote.shares.toNumber()
+      );
+      expect(position.lamportsContributed.toNumber()).to.equal(
+        quote.afterFee.toNumber()
+      );
+
+      const payerAfter = await provider.connection.getBalance(
+        payer.publicKey
+      );
+      expect(payerBefore - payerAfter).to.be.at.least(lamports);
+      const payerPosition = await provider.connection.getAccountInfo(
+        positionPdaFor(payer.publicKey, market.marketId)
+      );
+      expect(payerPosition).to.be.null;
+    });
+
+    it("Adds to an existing beneficiary position", async () => {
+      const positionPda = positionPdaFor(
+        beneficiary.publicKey,
+        market.marketId
+      );
+      const before = await program.account.userPosition.fetch(positionPda);
+      const quote = await quoteBuy(market, true, 0.01 * SOL);
+
+      await buyFor(0.01 * SOL);
+
+      const after = await program.account.userPosition.fetch(positionPda);
+      expect(after.yesShares.sub(before.yesShares).toNumber()).to.equal(
+        quote.shares.toNumber()
+      );
+    });
+  });
+
+  describe("Protocol Treasury", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let treasury: anchor.web3.Keypair;
+
+    const setTreasury = (key: anchor.web3.PublicKey) =>
+      program.methods
+        .setTreasury(key)
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    const withdraw = (lamports: number, to: anchor.web3.PublicKey) =>
+      program.methods
+        .withdrawFees(new anchor.BN(lamports), 0)
+        .accounts({
+          config: configPda,
+          feeVault: feeVaultPda,
+          authority: authority,
+          treasury: to,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    before(async () => {
+      treasury = await fundedKeypair(1);
+      const market = await createTestMarket(207);
+      await buy(await fundedKeypair(1), market, true, 0.1 * SOL);
+    });
+
+    after(async () => {
+      await setTreasury(authority);
+    });
+
+    it("Defaults the treasury to the authority", async () => {
+      const config = await program.account.config.fetch(configPda);
+      expect(config.treasury.toBase58()).to.equal(authority.toBase58());
+    });
+
+    it("Sends fee withdrawals to the configured treasury", async () => {
+      await setTreasury(treasury.publicKey);
+      const config = await program.account.config.fetch(configPda);
+      expect(config.treasury.toBase58()).to.equal(
+        treasury.publicKey.toBase58()
+      );
+
+      const before = await provider.connection.getBalance(treasury.publicKey);
+      await withdraw(10_000, treasury.publicKey);
+      const after = await provider.connection.getBalance(treasury.publicKey);
+      expect(after - before).to.equal(10_000);
+    });
+
+    it("Rejects a withdrawal to any other account", async () => {
+      try {
+        await withdraw(10_000, authority);
+        expect.fail("Withdrawal to the authority should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InvalidTreasury");
+      }
+    });
+
+    it("Only lets the authority change the treasury", async () => {
+      const stranger = await fundedKeypair(1);
+      try {
+        await program.methods
+          .setTreasury(stranger.publicKey)
+          .accounts({ config: configPda, authority: stranger.publicKey })
+          .signers([stranger])
+          .rpc();
+        expect.fail("Non-authority should not set the treasury");
+      } catch (error) {
+        expect(error.message).to.include("Unauthorized");
+      }
+      const config = await program.account.config.fetch(configPda);
+      expect(config.treasury.toBase58()).to.equal(
+        treasury.publicKey.toBase58()
+      );
+    });
+  });
+
+  describe("Batch Market Creation", () => {
+    const CATEGORY = "Tournament";
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const paramsFor = (marketId: number, resolutionOffset = 3600) => ({
+      marketId: new anchor.BN(marketId),
+      question: `Match #${marketId} winner`,
+      description: "Batch",
+      category: CATEGORY,
+      resolutionTime: new anchor.BN(
+        Math.floor(Date.now() / 1000) + resolutionOffset
+      ),
+      initialLiquidityLamports: new anchor.BN(0.05 * SOL),
+      marketType: { constantProduct: {} },
+      ammPrecision: new anchor.BN(0),
+    });
+
+    // Every entry lands on page 0 of a fresh category. Three entries with
+    // short strings keep the transaction under the packet size limit
+    const accountsFor = (marketId: number) =>
+      [
+        marketPdaFor(marketId),
+        metadataPdaFor(marketId),
+        vaultPdaFor(marketId),
+        insuranceVaultPdaFor(marketId),
+        categoryStatsPdaFor(CATEGORY),
+        categoryIndexPdaFor(CATEGORY, 0),
+      ].map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }));
+
+    const createBatch = (params: ReturnType<typeof paramsFor>[]) =>
+      program.methods
+        .createMarketsBatch(params)
+        .accounts({
+          config: configPda,
+          creatorStats: creatorStatsPdaFor(authority),
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts(
+          params.flatMap((p) => accountsFor(p.marketId.toNumber()))
+        )
+        .preInstructions([
+          anchor.web3.ComputeBudgetProgram.setComputeUnitLimit({
+            units: 600_000,
+          }),
+        ])
+        .rpc();
+
+    it("Creates three markets in one transaction", async () => {
+      const ids = [208, 209, 210];
+      const configBefore = await program.account.config.fetch(configPda);
+
+      await createBatch(ids.map((id) => paramsFor(id)));
+
+      for (const id of ids) {
+        const market = await program.account.market.fetch(marketPdaFor(id));
+        expect(market.marketId.toNumber()).to.equal(id);
+        expect(market.yesLiquidity.toNumber()).to.equal(0.05 * SOL);
+        expect(market.noLiquidity.toNumber()).to.equal(0.05 * SOL);
+        expect(market.resolved).to.be.false;
+
+        const metadata = await program.account.marketMetadata.fetch(
+          metadataPdaFor(id)
+        );
+        expect(metadata.question).to.equal(`Match #${id} winner`);
+        expect(metadata.category).to.equal(CATEGORY);
+
+        const vault = await provider.connection.getBalance(vaultPdaFor(id));
+        expect(vault).to.be.at.least(market.creatorDeposit.toNumber());
+      }
+
+      const stats = await program.account.categoryStats.fetch(
+        categoryStatsPdaFor(CATEGORY)
+      );
+      expect(stats.marketCount.toNumber()).to.equal(3);
+      const page = await program.account.categoryIndex.fetch(
+        categoryIndexPdaFor(CATEGORY, 0)
+      );
+      expect(page.marketIds.map((id) => id.toNumber())).to.deep.equal(ids);
+
+      const configAfter = await program.account.config.fetch(configPda);
+      expect(
+        configAfter.marketCount.sub(configBefore.marketCount).toNumber()
+      ).to.equal(3);
+    });
+
+    it("Reverts the whole batch when one entry is invalid", async () => {
+      try {
+        await createBatch([paramsFor(211), paramsFor(212, -60)]);
+        expect.fail("Batch with a past resolution time should fail");
+      } catch (error) {
+        expect(error.message).to.include("InvalidResolutionTime");
+      }
+
+      const first = await provider.connection.getAccountInfo(
+        marketPdaFor(211)
+      );
+      expect(first).to.be.null;
+      const stats = await program.account.categoryStats.fetch(
+        categoryStatsPdaFor(CATEGORY)
+      );
+      expect(stats.marketCount.toNumber()).to.equal(3);
+    });
+  });
+
+  describe("Claim Deadline", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const CLAIM_WINDOW = 90 * 24 * 60 * 60;
+    const mockClockPda = findPda([Buffer.from("mock_clock")]);
+    const hasMockClock = program.idl.instructions.some(
+      (ix) => ix.name === "setMockTime" || ix.name === "set_mock_time"
+    );
+    let market: TestMarket;
+    let early: anchor.web3.Keypair;
+    let late: anchor.web3.Keypair;
+
+    const setClaimWindow = (secs: number) =>
+      program.methods
+        .setClaimWindow(new anchor.BN(secs))
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    const claim = (
+      user: anchor.web3.Keypair,
+      extra: anchor.web3.AccountMeta[] = []
+    ) =>
+      program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(user.publicKey, market))
+        .remainingAccounts(extra)
+        .signers([user])
+        .rpc();
+
+    const sweep = (extra: anchor.web3.AccountMeta[] = []) =>
+      program.methods
+        .sweepUnclaimed()
+        .accounts({
+          config: configPda,
+          market: market.marketPda,
+          vault: market.vaultPda,
+          treasury: authority,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .remainingAccounts(extra)
+        .rpc();
+
+    const setMockTime = (unixSecs: anchor.BN) =>
+      (program.methods as any)
+        .setMockTime(unixSecs)
+        .accounts({
+          config: configPda,
+          mockClock: mockClockPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    before(async () => {
+      await setClaimWindow(CLAIM_WINDOW);
+      market = await createTestMarket(213, 5);
+      early = await fundedKeypair(1);
+      late = await fundedKeypair(1);
+      await buy(early, market, true, 0.02 * SOL);
+      await buy(late, market, true, 0.02 * SOL);
+      await resolveTestMarket(market, true);
+    });
+
+    after(async () => {
+      await setClaimWindow(0);
+    });
+
+    it("Rejects windows too short to give winners fair warning", async () => {
+      try {
+        await setClaimWindow(24 * 60 * 60);
+        expect.fail("A one-day claim window should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("InvalidClaimWindow");
+      }
+    });
+
+    it("Sets the deadline at resolution and pays claims before", async () => {
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.claimDeadline.toNumber()).to.equal(
+        acc.resolvedAt.toNumber() + CLAIM_WINDOW
+      );
+
+      const before = await provider.connection.getBalance(early.publicKey);
+      await claim(early);
+      const after = await provider.connection.getBalance(early.publicKey);
+      expect(after).to.be.greaterThan(before);
+    });
+
+    it("Refuses to sweep before the deadline", async () => {
+      try {
+        await sweep();
+        expect.fail("Sweep before the deadline should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("ClaimDeadlineNotReached");
+      }
+    });
+
+    it("Sweeps only unclaimed winnings past the deadline", async function () {
+      if (!hasMockClock) this.skip();
+      const acc = await program.account.market.fetch(market.marketPda);
+      const clock = [
+        { pubkey: mockClockPda, isSigner: false, isWritable: false },
+      ];
+      await setMockTime(acc.claimDeadline.addn(1));
+      try {
+        try {
+          await claim(late, clock);
+          expect.fail("Claim past the deadline should be rejected");
+        } catch (error) {
+          expect(error.message).to.include("ClaimDeadlinePassed");
+        }
+
+        const rentFloor =
+          await provider.connection.getMinimumBalanceForRentExemption(0);
+        await sweep(clock);
+        const vault = await provider.connection.getBalance(market.vaultPda);
+        expect(vault).to.equal(rentFloor);
+
+        const swept = await program.account.market.fetch(market.marketPda);
+        expect(swept.totalYesShares.toNumber()).to.equal(0);
+        // The early winner's claim was not clawed back
+        expect(swept.totalClaimed.toNumber()).to.equal(
+          acc.totalClaimed.toNumber()
+        );
+      } finally {
+        await setMockTime(new anchor.BN(0));
+      }
+    });
+  });
+
+  describe("Outcome Prices", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    let market: TestMarket;
+
+    const accounts = () => ({ config: configPda, market: market.marketPda });
+
+    before(async () => {
+      market = await createTestMarket(214);
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.03 * SOL);
+    });
+
+    it("Returns two outcome prices that sum to 10000 bps", async () => {
+      const prices: number[] = await program.methods
+        .getOutcomePrices()
+        .accounts(accounts())
+        .view();
+      expect(prices).to.have.length(2);
+      expect(prices[0] + prices[1]).to.equal(10000);
+      expect(prices[0]).to.equal(await impliedYesBps(market));
+    });
+
+    it("Reports a spread on each outcome", async () => {
+      const spreads: number[] = await program.methods
+        .getOutcomeSpreads(new anchor.BN(0.01 * SOL))
+        .accounts(accounts())
+        .view();
+      expect(spreads).to.have.length(2);
+      spreads.forEach((spread) => expect(spread).to.be.greaterThan(0));
+    });
+  });
+
+  describe("Resolution Callback", () => {
+    const mock = anchor.workspace
+      .ResolutionCallbackMock as Program<ResolutionCallbackMock>;
+    const mockParser = new anchor.EventParser(
+      mock.programId,
+      new anchor.BorshCoder(mock.idl)
+    );
+    const mockMeta = {
+      pubkey: mock.programId,
+      isSigner: false,
+      isWritable: false,
+    };
+
+    const setCallback = (m: TestMarket, required: boolean) =>
+      program.methods
+        .setResolutionCallback(mock.programId, required)
+        .accounts({
+          config: configPda,
+          market: m.marketPda,
+          authority: authority,
+        })
+        .rpc();
+
+    const resolveWith = async (
+      m: TestMarket,
+      extra: anchor.web3.AccountMeta[]
+    ) => {
+      const acc = await program.account.market.fetch(m.marketPda);
+      const waitMs = (acc.resolutionTime.toNumber() + 2) * 1000 - Date.now();
+      if (waitMs > 0) await delay(waitMs);
+      return program.methods
+        .resolveMarket(true)
+        .accounts({
+          config: configPda,
+          market: m.marketPda,
+          authority: authority,
+        })
+        .remainingAccounts(extra)
+        .rpc();
+    };
+
+    it("CPIs the callback program with the outcome", async () => {
+      const market = await createTestMarket(215, 15);
+      await setCallback(market, true);
+      const sig = await resolveWith(market, [mockMeta]);
+
+      const tx = await provider.connection.getTransaction(sig, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const received = Array.from(
+        mockParser.parseLogs(tx.meta.logMessages)
+      ).find((e) => e.name === "resolutionReceivedEvent");
+      expect(received).to.exist;
+      expect(received.data.marketId.toNumber()).to.equal(215);
+      expect(received.data.outcomeYes).to.equal(true);
+      expect(received.data.market.toBase58()).to.equal(
+        market.marketPda.toBase58()
+      );
+    });
+
+    it("Will not resolve without a required callback", async () => {
+      const market = await createTestMarket(216, 15);
+      await setCallback(market, true);
+      try {
+        await resolveWith(market, []);
+        expect.fail("Resolution should need the callback program");
+      } catch (error) {
+        expect(error.message).to.include("ResolutionCallbackMissing");
+      }
+    });
+
+    it("Resolves anyway when an optional callback is absent", async () => {
+      const market = await createTestMarket(217, 15);
+      await setCallback(market, false);
+      await resolveWith(market, []);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.resolved).to.equal(true);
+    });
+  });
+
+  describe("Config View", () => {
+    it("Matches the stored config", async () => {
+      const view = await program.methods
+        .getConfig()
+        .accounts({ config: configPda })
+        .view();
+      const stored = await program.account.config.fetch(configPda);
+
+      expect(view.authority.toBase58()).to.equal(stored.authority.toBase58());
+      expect(view.treasury.toBase58()).to.equal(stored.treasury.toBase58());
+      expect(view.marketCount.toNumber()).to.equal(
+        stored.marketCount.toNumber()
+      );
+      expect(view.feeBps).to.equal(
+        Math.min(stored.feePercentage, stored.maxFeeBps)
+      );
+      expect(view.maxFeeBps).to.equal(stored.maxFeeBps);
+      expect(view.tradingBuffer.toNumber()).to.equal(
+        stored.tradingBuffer.toNumber()
+      );
+      expect(view.claimWindow.toNumber()).to.equal(
+        stored.claimWindow.toNumber()
+      );
+      expect(view.resolvers.length).to.equal(stored.resolvers.length);
+    });
+  });
+
+  describe("Partial Fills", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const MIN_RESERVE = 1_000_000;
+    let market: TestMarket;
+
+    // buy_shares returns the lamports it filled as program return data
+    const filledBy = async (sig: string) => {
+      const tx = await provider.connection.getTransaction(sig, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const prefix = `Program return: ${program.programId.toBase58()} `;
+      const line = tx.meta.logMessages.find((l) => l.startsWith(prefix));
+      const data = Buffer.from(line.slice(prefix.length), "base64");
+      return new anchor.BN(data, "le").toNumber();
+    };
+
+    before(async () => {
+      market = await createTestMarket(218);
+    });
+
+    it("Rejects an oversized buy by default", async () => {
+      const trader = await fundedKeypair(2);
+      try {
+        await buy(trader, market, true, 1 * SOL);
+        expect.fail("A buy past the reserve floor should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("TradeExceedsLiquidity");
+      }
+    });
+
+    it("Fills an oversized buy up to the reserve floor", async () => {
+      const trader = await fundedKeypair(2);
+      const before = await provider.connection.getBalance(trader.publicKey);
+      const sig = await buy(trader, market, true, 1 * SOL, 0, null, true);
+      const filled = await filledBy(sig);
+      expect(filled).to.be.greaterThan(0);
+      expect(filled).to.be.lessThan(1 * SOL);
+
+      // Beyond the fill the wallet only paid position rent and tx fees
+      const after = await provider.connection.getBalance(trader.publicKey);
+      expect(before - after).to.be.lessThan(filled + 0.01 * SOL);
+
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.noLiquidity.toNumber()).to.be.at.least(MIN_RESERVE);
+      expect(acc.noLiquidity.toNumber()).to.be.lessThan(MIN_RESERVE + 10);
+      const position = await program.account.userPosition.fetch(
+        positionPdaFor(trader.publicKey, market.marketId)
+      );
+      expect(position.yesShares.toNumber()).to.be.greaterThan(0);
+    });
+  });
+
+  describe("Editing Questions", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const editQuestion = (m: TestMarket, question: string) =>
+      program.methods
+        .editQuestion(question)
+        .accounts({
+          config: configPda,
+          market: m.marketPda,
+          metadata: m.metadataPda,
+          authority: authority,
+        })
+        .rpc();
+
+    it("Edits the question before any trade", async () => {
+      const market = await createTestMarket(219);
+      await editQuestion(market, "Will SOL close above $200 this week?");
+      const metadata = await program.account.marketMetadata.fetch(
+        market.metadataPda
+      );
+      expect(metadata.question).to.equal(
+        "Will SOL close above $200 this week?"
+      );
+    });
+
+    it("Rejects a question over the length limit", async () => {
+      const market = await createTestMarket(220);
+      try {
+        await editQuestion(market, "?".repeat(201));
+        expect.fail("A 201-byte question should be rejected");
+      } catch (error) {
+        expect(error.message).to.include("QuestionTooLong");
+      }
+    });
+
+    it("Locks the question once the market has traded", async () => {
+      const market = await createTestMarket(221);
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.01 * SOL);
+      try {
+        await editQuestion(market, "A different question?");
+        expect.fail("The question should be locked after a trade");
+      } catch (error) {
+        expect(error.message).to.include("QuestionLocked");
+      }
+    });
+  });
+
+  describe("House Fee Exemption", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    const house = (provider.wallet as anchor.Wallet).payer;
+    let market: TestMarket;
+
+    const setExempt = (exempt: boolean) =>
+      program.methods
+        .setHouseFeeExempt(exempt)
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+
+    // Lamports a buy paid into the default fee vault
+    const feePaidBy = async (trader: anchor.web3.Keypair) => {
+      const before = await provider.connection.getBalance(feeVaultPda);
+      await buy(trader, market, true, 0.01 * SOL);
+      return (await provider.connection.getBalance(feeVaultPda)) - before;
+    };
+
+    before(async () => {
+      market = await createTestMarket(222);
+    });
+
+    after(async () => {
+      await setExempt(false);
+    });
+
+    it("Charges the authority a normal fee while the flag is off", async () => {
+      await setExempt(false);
+      expect(await feePaidBy(house)).to.be.greaterThan(0);
+    });
+
+    it("Lets the authority trade fee-free when exempt", async () => {
+      await setExempt(true);
+      const sharesBefore = (
+        await program.account.market.fetch(market.marketPda)
+      ).totalYesShares.toNumber();
+
+      expect(await feePaidBy(house)).to.equal(0);
+      const acc = await program.account.market.fetch(market.marketPda);
+      expect(acc.totalYesShares.toNumber()).to.be.greaterThan(sharesBefore);
+    });
+
+    it("Still charges regular users when exempt", async () => {
+      await setExempt(true);
+      const trader = await fundedKeypair(1);
+      expect(await feePaidBy(trader)).to.be.greaterThan(0);
+    });
+  });
+
+  describe("Audit Log", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const auditLog = async () =>
+      (await program.methods.getAuditLog().view()) as {
+        action: object;
+        actor: anchor.web3.PublicKey;
+        marketId: anchor.BN;
+        amount: anchor.BN;
+      }[];
+
+    it("Records admin actions in order", async () => {
+      const market = await createTestMarket(223, 15);
+      const trader = await fundedKeypair(1);
+      await buy(trader, market, true, 0.01 * SOL);
+
+      const config = await program.account.config.fetch(configPda);
+      await program.methods
+        .setFee(config.feePercentage)
+        .accounts({ config: configPda, authority: authority })
+        .rpc();
+      await program.methods
+        .withdrawFees(new anchor.BN(1000), 0)
+        .accounts({
+          config: configPda,
+          feeVault: feeVaultPda,
+          authority: authority,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        })
+        .rpc();
+      await resolveTestMarket(market, true);
+
+      const recent = (await auditLog()).slice(-3);
+      expect(recent.map((e) => Object.keys(e.action)[0])).to.deep.equal([
+        "feeSet",
+        "feesWithdrawn",
+        "resolvedYes",
+      ]);
+      recent.forEach((e) =>
+        expect(e.actor.toBase58()).to.equal(authority.toBase58())
+      );
+      expect(recent[0].amount.toNumber()).to.equal(config.feePercentage);
+      expect(recent[1].amount.toNumber()).to.equal(1000);
+      expect(recent[2].marketId.toNumber()).to.equal(223);
+    });
+  });
+
+  describe("Claim Account Set", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    it("Keeps the metadata account out of every claim path", async () => {
+      const claimPaths = ["claim_winnings", "claim_partial", "push_claim"];
+      const claims = program.idl.instructions.filter((ix) =>
+        claimPaths.includes(ix.name)
+      );
+      expect(claims).to.have.length(claimPaths.length);
+      claims.forEach((ix) =>
+        expect(ix.accounts.map((a) => a.name)).not.to.include("metadata")
+      );
+    });
+
+    it("Claims without the metadata account in the transaction", async () => {
+      const market = await createTestMarket(224, 15);
+      const winner = await fundedKeypair(1);
+      await buy(winner, market, true, 0.02 * SOL);
+      await resolveTestMarket(market, true);
+
+      const sig = await program.methods
+        .claimWinnings()
+        .accounts(claimAccounts(winner.publicKey, market))
+        .signers([winner])
+        .rpc();
+      const tx = await provider.connection.getTransaction(sig, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const keys = tx.transaction.message
+        .getAccountKeys()
+        .staticAccountKeys.map((k) => k.toBase58());
+      expect(keys).to.include(market.marketPda.toBase58());
+      expect(keys).not.to.include(market.metadataPda.toBase58());
+    });
+  });
 });